@@ -0,0 +1,23 @@
+-- The schema as of the introduction of migrations, which matches the tables of databases set up by hand before that. Later changes belong in new migrations. Keep sqlite/0001_initial.sql in sync.
+
+CREATE TABLE IF NOT EXISTS offers (
+    name VARCHAR(255) NOT NULL,
+    description TEXT NOT NULL,
+    phone VARCHAR(255) NULL,
+    mail VARCHAR(255) NULL,
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS requests (
+    name VARCHAR(255) NOT NULL,
+    description TEXT NOT NULL,
+    phone VARCHAR(255) NULL,
+    mail VARCHAR(255) NULL,
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS notices (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    position TEXT NOT NULL,
+    text TEXT NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
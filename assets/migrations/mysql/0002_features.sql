@@ -0,0 +1,105 @@
+-- The columns and tables added after the introduction of migrations, for databases which were set up by hand with the baseline schema of 0001. Keep sqlite/0002_features.sql in sync.
+
+ALTER TABLE offers
+    ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'open',
+    ADD COLUMN created_at DATETIME NULL,
+    ADD COLUMN category VARCHAR(32) NULL,
+    ADD COLUMN deleted_at DATETIME NULL,
+    ADD INDEX (deleted_at),
+    ADD INDEX (created_at);
+
+ALTER TABLE requests
+    ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'open',
+    ADD COLUMN created_at DATETIME NULL,
+    ADD COLUMN category VARCHAR(32) NULL,
+    ADD COLUMN deleted_at DATETIME NULL,
+    ADD INDEX (deleted_at),
+    ADD INDEX (created_at);
+
+-- The position was a SET of the positions known when the table was created by hand, which would reject all newer ones. SET values are read as comma separated lists, so they are kept by the conversion.
+ALTER TABLE notices
+    MODIFY position TEXT NOT NULL,
+    ADD COLUMN visible_from DATETIME NULL,
+    ADD COLUMN visible_until DATETIME NULL,
+    ADD COLUMN severity VARCHAR(16) NOT NULL DEFAULT 'info',
+    ADD COLUMN priority INT NOT NULL DEFAULT 0,
+    ADD COLUMN deleted_at DATETIME NULL;
+
+CREATE TABLE IF NOT EXISTS entry_images (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    entry_table VARCHAR(16) NOT NULL,
+    entry_id INT NOT NULL,
+    file VARCHAR(32) NOT NULL,
+    INDEX (entry_table, entry_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS entry_translations (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    entry_table VARCHAR(16) NOT NULL,
+    entry_id INT NOT NULL,
+    locale VARCHAR(8) NOT NULL,
+    description TEXT NOT NULL,
+    INDEX (entry_table, entry_id, locale)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    timestamp DATETIME NOT NULL,
+    actor VARCHAR(255) NOT NULL,
+    action VARCHAR(64) NOT NULL,
+    target_id INT NOT NULL,
+    snapshot TEXT NOT NULL,
+    INDEX (action),
+    INDEX (target_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS two_factor (
+    account VARCHAR(255) NOT NULL PRIMARY KEY,
+    secret VARCHAR(64) NULL,
+    pending_secret VARCHAR(64) NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS two_factor_recovery (
+    account VARCHAR(255) NOT NULL,
+    code_hash CHAR(64) NOT NULL,
+    PRIMARY KEY (account, code_hash)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS maintenance (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    start DATETIME NOT NULL,
+    end DATETIME NOT NULL,
+    reason TEXT NULL,
+    read_only BOOLEAN NOT NULL DEFAULT FALSE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    client VARCHAR(255) NOT NULL,
+    token_hash CHAR(64) NOT NULL UNIQUE,
+    can_write BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at DATETIME NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS subscriptions (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    mail VARCHAR(255) NOT NULL,
+    frequency VARCHAR(16) NOT NULL,
+    offers BOOLEAN NOT NULL,
+    requests BOOLEAN NOT NULL,
+    token VARCHAR(64) NOT NULL UNIQUE,
+    created_at DATETIME NOT NULL,
+    confirmed_at DATETIME NULL,
+    last_digest_at DATETIME NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS saved_searches (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    mail VARCHAR(255) NOT NULL,
+    entry_type VARCHAR(16) NULL,
+    keywords TEXT NOT NULL,
+    category VARCHAR(32) NULL,
+    token VARCHAR(64) NOT NULL UNIQUE,
+    created_at DATETIME NOT NULL,
+    confirmed_at DATETIME NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
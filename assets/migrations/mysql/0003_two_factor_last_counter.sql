@@ -0,0 +1,3 @@
+-- The time step of the last accepted TOTP code, so that codes cannot be used twice.
+
+ALTER TABLE two_factor ADD COLUMN last_counter BIGINT NULL;
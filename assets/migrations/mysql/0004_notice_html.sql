@@ -0,0 +1,4 @@
+-- Notices written before Markdown was supported contain HTML, which is still shown as it is.
+
+ALTER TABLE notices ADD COLUMN html BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE notices SET html=TRUE;
@@ -0,0 +1,23 @@
+-- The schema as of the introduction of migrations, see mysql/0001_initial.sql.
+
+CREATE TABLE IF NOT EXISTS offers (
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    phone TEXT NULL,
+    mail TEXT NULL,
+    id INTEGER PRIMARY KEY AUTOINCREMENT
+);
+
+CREATE TABLE IF NOT EXISTS requests (
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    phone TEXT NULL,
+    mail TEXT NULL,
+    id INTEGER PRIMARY KEY AUTOINCREMENT
+);
+
+CREATE TABLE IF NOT EXISTS notices (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    position TEXT NOT NULL,
+    text TEXT NOT NULL
+);
@@ -0,0 +1,100 @@
+-- The columns and tables added after the introduction of migrations, see mysql/0002_features.sql. SQLite adds one column per statement.
+
+ALTER TABLE offers ADD COLUMN status TEXT NOT NULL DEFAULT 'open';
+ALTER TABLE offers ADD COLUMN created_at TEXT NULL;
+ALTER TABLE offers ADD COLUMN category TEXT NULL;
+ALTER TABLE offers ADD COLUMN deleted_at TEXT NULL;
+CREATE INDEX IF NOT EXISTS offers_deleted_at ON offers (deleted_at);
+CREATE INDEX IF NOT EXISTS offers_created_at ON offers (created_at);
+
+ALTER TABLE requests ADD COLUMN status TEXT NOT NULL DEFAULT 'open';
+ALTER TABLE requests ADD COLUMN created_at TEXT NULL;
+ALTER TABLE requests ADD COLUMN category TEXT NULL;
+ALTER TABLE requests ADD COLUMN deleted_at TEXT NULL;
+CREATE INDEX IF NOT EXISTS requests_deleted_at ON requests (deleted_at);
+CREATE INDEX IF NOT EXISTS requests_created_at ON requests (created_at);
+
+ALTER TABLE notices ADD COLUMN visible_from TEXT NULL;
+ALTER TABLE notices ADD COLUMN visible_until TEXT NULL;
+ALTER TABLE notices ADD COLUMN severity TEXT NOT NULL DEFAULT 'info';
+ALTER TABLE notices ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE notices ADD COLUMN deleted_at TEXT NULL;
+
+CREATE TABLE IF NOT EXISTS entry_images (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    entry_table TEXT NOT NULL,
+    entry_id INTEGER NOT NULL,
+    file TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS entry_images_entry ON entry_images (entry_table, entry_id);
+
+CREATE TABLE IF NOT EXISTS entry_translations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    entry_table TEXT NOT NULL,
+    entry_id INTEGER NOT NULL,
+    locale TEXT NOT NULL,
+    description TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS entry_translations_entry ON entry_translations (entry_table, entry_id, locale);
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp TEXT NOT NULL,
+    actor TEXT NOT NULL,
+    action TEXT NOT NULL,
+    target_id INTEGER NOT NULL,
+    snapshot TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS audit_log_action ON audit_log (action);
+CREATE INDEX IF NOT EXISTS audit_log_target_id ON audit_log (target_id);
+
+CREATE TABLE IF NOT EXISTS two_factor (
+    account TEXT NOT NULL PRIMARY KEY,
+    secret TEXT NULL,
+    pending_secret TEXT NULL
+);
+
+CREATE TABLE IF NOT EXISTS two_factor_recovery (
+    account TEXT NOT NULL,
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (account, code_hash)
+);
+
+CREATE TABLE IF NOT EXISTS maintenance (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    start TEXT NOT NULL,
+    end TEXT NOT NULL,
+    reason TEXT NULL,
+    read_only INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    client TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    can_write INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS subscriptions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    mail TEXT NOT NULL,
+    frequency TEXT NOT NULL,
+    offers INTEGER NOT NULL,
+    requests INTEGER NOT NULL,
+    token TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL,
+    confirmed_at TEXT NULL,
+    last_digest_at TEXT NULL
+);
+
+CREATE TABLE IF NOT EXISTS saved_searches (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    mail TEXT NOT NULL,
+    entry_type TEXT NULL,
+    keywords TEXT NOT NULL,
+    category TEXT NULL,
+    token TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL,
+    confirmed_at TEXT NULL
+);
@@ -0,0 +1,3 @@
+-- See mysql/0003_two_factor_last_counter.sql.
+
+ALTER TABLE two_factor ADD COLUMN last_counter INTEGER NULL;
@@ -0,0 +1,4 @@
+-- See mysql/0004_notice_html.sql.
+
+ALTER TABLE notices ADD COLUMN html INTEGER NOT NULL DEFAULT 0;
+UPDATE notices SET html=1;
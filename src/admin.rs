@@ -3,7 +3,8 @@ use iron::typemap::Key;
 
 use plugin;
 
-use util::check_admin_auth;
+use two_factor;
+use util::check_admin_password;
 
 pub struct IsAdmin;
 
@@ -14,7 +15,8 @@ impl Key for IsAdmin {
 impl<'a, 'b> plugin::Plugin<Request<'a, 'b>> for IsAdmin {
     type Error = ();
 
+    /// Like `check_admin_auth`. The second factor is only checked for admin credentials, so public page views do not touch the database.
     fn eval(req: &mut Request) -> Result<bool, ()> {
-        Ok(check_admin_auth(req).is_ok())
+        Ok(check_admin_password(req).is_ok() && two_factor::check_second_factor(req).is_ok())
     }
 }
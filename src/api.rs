@@ -0,0 +1,446 @@
+//! Versioned JSON API under `/api/v1`, e.g. for the newsletter and the main site.
+//!
+//! Clients authenticate with an `Authorization: Bearer …` header instead of the site password, see `client`. Tokens are created on the admin page and stored as SHA-256 hashes in the table `api_tokens` (`id`, `client`, `token_hash`, `can_write`, `created_at`). Errors are returned as JSON objects with an `error` field.
+
+use std::io::prelude::*;
+use std::str::FromStr;
+
+use chrono::prelude::*;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use iron::{headers, status};
+use iron::mime::Mime;
+use iron::modifiers::Header;
+use iron::prelude::*;
+
+use mysql::prelude::*;
+
+use router::Router;
+
+use serde_json;
+
+use urlencoded::UrlEncodedBody;
+
+use audit;
+use entry::{self, Entry};
+use error::{self, Error};
+use maintenance;
+use notice::{self, NoticeData, NoticePositions, Position, Severity};
+use saved_search;
+use util;
+
+const TOKEN_LENGTH: usize = 32;
+
+struct ApiError {
+    status: status::Status,
+    body: serde_json::Value
+}
+
+impl ApiError {
+    fn new<S: Into<String>>(error_status: status::Status, message: S) -> ApiError {
+        ApiError {
+            status: error_status,
+            body: json!({"error": message.into()})
+        }
+    }
+}
+
+impl From<::db::Error> for ApiError {
+    fn from(e: ::db::Error) -> ApiError {
+        error::log(&e);
+        ApiError::new(status::InternalServerError, "database error")
+    }
+}
+
+type ApiResult = Result<(status::Status, serde_json::Value), ApiError>;
+
+fn respond(result: ApiResult) -> IronResult<Response> {
+    let (response_status, body) = match result {
+        Ok(response) => response,
+        Err(error) => (error.status, error.body)
+    };
+    let mut response = Response::with((response_status, "application/json".parse::<Mime>().unwrap(), body.to_string()));
+    if response_status == status::Unauthorized {
+        response.headers.set_raw("WWW-Authenticate", vec![b"Bearer realm=\"WiW-Boerse API\"".to_vec()]);
+    }
+    Ok(response)
+}
+
+fn hash_token(token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input_str(token.trim());
+    hasher.result_str()
+}
+
+/// Looks up a token, returning the name of its client and whether it has write access.
+pub fn token_client(conn: &mut ::db::Conn, token: &str) -> Result<Option<(String, bool)>, ::db::Error> {
+    for row in try!(conn.query(format!("SELECT client, can_write FROM api_tokens WHERE token_hash={}", ::sql_escape(hash_token(token))))) {
+        let values = try!(row);
+        return Ok(Some((String::from_value(values[0].clone()), bool::from_value(values[1].clone()))));
+    }
+    Ok(None)
+}
+
+/// Returns the name of the client the request's token belongs to. Requests which change data need a token with write access.
+fn client(req: &Request, conn: &mut ::db::Conn, write: bool) -> Result<String, ApiError> {
+    let token = match req.headers.get::<headers::Authorization<headers::Bearer>>() {
+        Some(&headers::Authorization(headers::Bearer { ref token })) => token.clone(),
+        None => { return Err(ApiError::new(status::Unauthorized, "missing API token")); }
+    };
+    match try!(token_client(conn, &token)) {
+        Some((_, false)) if write => Err(ApiError::new(status::Forbidden, "this API token is read-only")),
+        Some((client, _)) => Ok(client),
+        None => Err(ApiError::new(status::Unauthorized, "invalid API token"))
+    }
+}
+
+fn connection() -> Result<::db::PooledConn, ApiError> {
+    ::db::connection().map_err(|e| match e {
+        ::db::Error::PoolTimeout => ApiError::new(status::ServiceUnavailable, "all database connections are in use"),
+        _ => {
+            error::log(&e);
+            ApiError::new(status::InternalServerError, "could not connect to the database")
+        }
+    })
+}
+
+fn id_param(req: &Request) -> Result<i32, ApiError> {
+    let id_str = try!(req.extensions.get::<Router>().and_then(|params| params.find("id")).ok_or(ApiError::new(status::InternalServerError, "missing id")));
+    i32::from_str(id_str).map_err(|_| ApiError::new(status::BadRequest, format!("{:?} is not a number", id_str)))
+}
+
+fn json_body(req: &mut Request) -> Result<serde_json::Value, ApiError> {
+    let mut body = String::default();
+    try!(req.body.read_to_string(&mut body).map_err(|_| ApiError::new(status::BadRequest, "could not read the request body")));
+    serde_json::from_str(&body).map_err(|e| ApiError::new(status::BadRequest, format!("invalid JSON: {}", e)))
+}
+
+fn check_writable(conn: &mut ::db::Conn) -> Result<(), ApiError> {
+    match try!(maintenance::read_only(conn)) {
+        Some(end) => Err(ApiError {
+            status: status::ServiceUnavailable,
+            body: json!({
+                "error": "the board is in read-only mode for maintenance",
+                "until": end.map(|end| end.to_rfc3339())
+            })
+        }),
+        None => Ok(())
+    }
+}
+
+fn not_found(entry_type: entry::Type) -> ApiError {
+    ApiError::new(status::NotFound, format!("this {} does not exist or has been deleted", entry_type.map("offer", "request")))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NewEntry {
+    name: String,
+    description: String,
+    phone: Option<String>,
+    mail: Option<String>,
+    /// The slug of one of the `entry::CATEGORIES`.
+    category: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatusUpdate {
+    status: String
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NewNotice {
+    text: String,
+    /// Comma-separated positions as stored in the database, e.g. `index,detail` or `all`.
+    position: String,
+    visible_from: Option<DateTime<UTC>>,
+    visible_until: Option<DateTime<UTC>>,
+    severity: Option<String>,
+    #[serde(default)]
+    priority: i32
+}
+
+fn list_entries(entry_type: entry::Type, req: &mut Request) -> ApiResult {
+    let mut conn = try!(connection());
+    try!(client(req, &mut conn, false));
+    let entries = try!(Entry::list(entry_type, &mut conn));
+    Ok((status::Ok, json!(entries.into_iter().map(|entry| entry.to_json(entry_type)).collect::<Vec<_>>())))
+}
+
+fn get_entry(entry_type: entry::Type, req: &mut Request) -> ApiResult {
+    let mut conn = try!(connection());
+    try!(client(req, &mut conn, false));
+    let id = try!(id_param(req));
+    let entry = try!(try!(Entry::load(entry_type, id, &mut conn)).ok_or(not_found(entry_type)));
+    Ok((status::Ok, entry.to_json(entry_type)))
+}
+
+fn add_entry(entry_type: entry::Type, req: &mut Request) -> ApiResult {
+    let mut conn = try!(connection());
+    try!(client(req, &mut conn, true));
+    try!(check_writable(&mut conn));
+    let new_entry = try!(serde_json::from_value::<NewEntry>(try!(json_body(req))).map_err(|e| ApiError::new(status::BadRequest, e.to_string())));
+    let nullable = |value: Option<String>| value.map(|value| value.trim().to_owned()).and_then(|value| if value.is_empty() { None } else { Some(value) });
+    let (phone, mail) = (nullable(new_entry.phone), nullable(new_entry.mail));
+    if new_entry.name.trim().is_empty() { return Err(ApiError::new(status::BadRequest, "missing name")); }
+    if new_entry.description.trim().is_empty() { return Err(ApiError::new(status::BadRequest, "missing description")); }
+    if phone.is_none() && mail.is_none() { return Err(ApiError::new(status::BadRequest, "either phone or mail is required")); }
+    if let Some(ref category) = new_entry.category {
+        if entry::category_name(category).is_none() { return Err(ApiError::new(status::BadRequest, format!("unknown category {:?}", category))); }
+    }
+    let id = try!(conn.query(format!(
+        "INSERT INTO {} (name, description, phone, mail, created_at, category) VALUES ({}, {}, {}, {}, {}, {})",
+        entry_type.table(),
+        ::sql_escape(new_entry.name.trim()),
+        ::sql_escape(new_entry.description.trim()),
+        phone.map_or("NULL".to_owned(), ::sql_escape),
+        mail.map_or("NULL".to_owned(), ::sql_escape),
+        util::sql_timestamp(UTC::now()),
+        new_entry.category.as_ref().map_or("NULL".to_owned(), ::sql_escape)
+    ))).last_insert_id() as i32;
+    let entry = try!(try!(Entry::load(entry_type, id, &mut conn)).ok_or(not_found(entry_type)));
+    let json = entry.to_json(entry_type);
+    saved_search::notify_matches(entry_type, entry, &mut conn);
+    Ok((status::Created, json))
+}
+
+fn update_status(entry_type: entry::Type, req: &mut Request) -> ApiResult {
+    let mut conn = try!(connection());
+    let actor = format!("api:{}", try!(client(req, &mut conn, true)));
+    let id = try!(id_param(req));
+    let update = try!(serde_json::from_value::<StatusUpdate>(try!(json_body(req))).map_err(|e| ApiError::new(status::BadRequest, e.to_string())));
+    let new_status = try!(entry::Status::from_str(&update.status).ok_or(ApiError::new(status::BadRequest, format!("unknown status {:?}", update.status))));
+    let before = try!(::entry_snapshot(entry_type, id, &mut conn));
+    if try!(Entry::load(entry_type, id, &mut conn)).is_none() { return Err(not_found(entry_type)); }
+    try!(conn.query(format!("UPDATE {} SET status={} WHERE id={} AND deleted_at IS NULL", entry_type.table(), ::sql_escape(new_status.as_str()), id)));
+    let after = try!(::entry_snapshot(entry_type, id, &mut conn));
+    try!(audit::record(&mut conn, &actor, entry_type.map(audit::Action::UpdateOfferStatus, audit::Action::UpdateRequestStatus), id, &json!({"before": before, "after": after})));
+    let entry = try!(try!(Entry::load(entry_type, id, &mut conn)).ok_or(not_found(entry_type)));
+    Ok((status::Ok, entry.to_json(entry_type)))
+}
+
+fn del_entry(entry_type: entry::Type, req: &mut Request) -> ApiResult {
+    let mut conn = try!(connection());
+    let actor = format!("api:{}", try!(client(req, &mut conn, true)));
+    let id = try!(id_param(req));
+    if try!(Entry::load(entry_type, id, &mut conn)).is_none() { return Err(not_found(entry_type)); }
+    let snapshot = try!(::entry_snapshot(entry_type, id, &mut conn));
+    if try!(conn.query(format!("UPDATE {} SET deleted_at={} WHERE id={} AND deleted_at IS NULL", entry_type.table(), util::sql_timestamp(UTC::now()), id))).affected_rows() == 0 {
+        return Err(not_found(entry_type));
+    }
+    try!(audit::record(&mut conn, &actor, entry_type.map(audit::Action::DeleteOffer, audit::Action::DeleteRequest), id, &snapshot));
+    Ok((status::Ok, json!({"id": id, "deleted": true})))
+}
+
+pub fn list_offers(req: &mut Request) -> IronResult<Response> { respond(list_entries(entry::Type::Offer, req)) }
+pub fn list_requests(req: &mut Request) -> IronResult<Response> { respond(list_entries(entry::Type::Request, req)) }
+pub fn get_offer(req: &mut Request) -> IronResult<Response> { respond(get_entry(entry::Type::Offer, req)) }
+pub fn get_request(req: &mut Request) -> IronResult<Response> { respond(get_entry(entry::Type::Request, req)) }
+pub fn add_offer(req: &mut Request) -> IronResult<Response> { respond(add_entry(entry::Type::Offer, req)) }
+pub fn add_request(req: &mut Request) -> IronResult<Response> { respond(add_entry(entry::Type::Request, req)) }
+pub fn update_offer_status(req: &mut Request) -> IronResult<Response> { respond(update_status(entry::Type::Offer, req)) }
+pub fn update_request_status(req: &mut Request) -> IronResult<Response> { respond(update_status(entry::Type::Request, req)) }
+pub fn del_offer(req: &mut Request) -> IronResult<Response> { respond(del_entry(entry::Type::Offer, req)) }
+pub fn del_request(req: &mut Request) -> IronResult<Response> { respond(del_entry(entry::Type::Request, req)) }
+
+fn notice_json(id: i32, notice: &NoticeData) -> serde_json::Value {
+    let mut json = notice.snapshot();
+    json["id"] = json!(id);
+    json
+}
+
+fn list_notices_inner(req: &mut Request) -> ApiResult {
+    let mut conn = try!(connection());
+    try!(client(req, &mut conn, false));
+    let notices = try!(notice::visible_notices(&mut conn));
+    Ok((status::Ok, json!(notices.iter().map(|&(id, ref notice)| notice_json(id, notice)).collect::<Vec<_>>())))
+}
+
+fn get_notice_inner(req: &mut Request) -> ApiResult {
+    let mut conn = try!(connection());
+    try!(client(req, &mut conn, false));
+    let id = try!(id_param(req));
+    let notice = try!(try!(NoticeData::load(id, &mut conn)).ok_or(ApiError::new(status::NotFound, "this notice does not exist or has been deleted")));
+    Ok((status::Ok, notice_json(id, &notice)))
+}
+
+fn add_notice_inner(req: &mut Request) -> ApiResult {
+    let mut conn = try!(connection());
+    let actor = format!("api:{}", try!(client(req, &mut conn, true)));
+    try!(check_writable(&mut conn));
+    let new_notice = try!(serde_json::from_value::<NewNotice>(try!(json_body(req))).map_err(|e| ApiError::new(status::BadRequest, e.to_string())));
+    if new_notice.text.trim().is_empty() { return Err(ApiError::new(status::BadRequest, "missing text")); }
+    let positions = NoticePositions::from(&new_notice.position);
+    if !positions.any() { return Err(ApiError::new(status::BadRequest, "position must name at least one position")); }
+    let severity = match new_notice.severity {
+        Some(ref severity) => try!(Severity::from_str(severity).ok_or(ApiError::new(status::BadRequest, format!("unknown severity {:?}", severity)))),
+        None => Severity::default()
+    };
+    if let (Some(from), Some(until)) = (new_notice.visible_from, new_notice.visible_until) {
+        if until <= from { return Err(ApiError::new(status::BadRequest, "visible_until must be after visible_from")); }
+    }
+    let notice = NoticeData {
+        text: new_notice.text.trim().to_owned(),
+        positions: positions,
+        visible_from: new_notice.visible_from,
+        visible_until: new_notice.visible_until,
+        severity: severity,
+        priority: new_notice.priority,
+        html: false
+    };
+    let id = try!(notice.insert(&mut conn));
+    try!(audit::record(&mut conn, &actor, audit::Action::AddNotice, id, &notice.snapshot()));
+    Ok((status::Created, notice_json(id, &notice)))
+}
+
+fn del_notice_inner(req: &mut Request) -> ApiResult {
+    let mut conn = try!(connection());
+    let actor = format!("api:{}", try!(client(req, &mut conn, true)));
+    let id = try!(id_param(req));
+    let notice = try!(try!(NoticeData::load(id, &mut conn)).ok_or(ApiError::new(status::NotFound, "this notice does not exist or has been deleted")));
+    if try!(conn.query(format!("UPDATE notices SET deleted_at={} WHERE id={} AND deleted_at IS NULL", util::sql_timestamp(UTC::now()), id))).affected_rows() == 0 {
+        return Err(ApiError::new(status::NotFound, "this notice does not exist or has been deleted"));
+    }
+    try!(audit::record(&mut conn, &actor, audit::Action::DeleteNotice, id, &notice.snapshot()));
+    Ok((status::Ok, json!({"id": id, "deleted": true})))
+}
+
+pub fn list_notices(req: &mut Request) -> IronResult<Response> { respond(list_notices_inner(req)) }
+pub fn get_notice(req: &mut Request) -> IronResult<Response> { respond(get_notice_inner(req)) }
+pub fn add_notice(req: &mut Request) -> IronResult<Response> { respond(add_notice_inner(req)) }
+pub fn del_notice(req: &mut Request) -> IronResult<Response> { respond(del_notice_inner(req)) }
+
+fn tokens_page_inner(new_token: Option<(&str, &str)>, form_error: Option<&'static str>, req: &mut Request) -> IronResult<Response> {
+    let mut conn = try!(::db_connection());
+    let mut rows = String::default();
+    for row in try!(conn.query("SELECT id, client, can_write, created_at FROM api_tokens ORDER BY client").map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))) {
+        let values = try!(row.map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+        rows.push_str(&format!(
+            r#"
+<tr>
+    <td>{client}</td>
+    <td>{access}</td>
+    <td><div style="float: right;"><a href="/admin/api/{id}/loeschen" class="btn btn-danger"><i class="fa fa-trash-o"></i> Widerrufen</a></div>{created_at}</td>
+</tr>
+            "#,
+            id=i32::from_value(values[0].clone()),
+            client=util::escape_html(String::from_value(values[1].clone())),
+            access=if bool::from_value(values[2].clone()) { "lesen und schreiben" } else { "nur lesen" },
+            created_at=util::berlin_time(NaiveDateTime::from_value(values[3].clone())).format("%d.%m.%Y %H:%M")
+        ));
+    }
+    if rows.is_empty() {
+        rows = r#"<tr><td colspan="3" style="color: gray; font-style: italic;">Keine API-Schlüssel vorhanden.</td></tr>"#.to_owned();
+    }
+    Ok(Response::with((if form_error.is_some() { status::BadRequest } else { status::Ok }, "text/html".parse::<Mime>().unwrap(), util::page("/admin/api", true, &format!(
+        r#"
+        {notices}
+        {message}
+        <h2>API-Schlüssel</h2>
+        <p>Mit einem API-Schlüssel können andere Anwendungen, z.B. der Newsletter, über <code>/api/v1</code> auf die Börse zugreifen. Der Schlüssel wird im Header <code>Authorization: Bearer …</code> übergeben.</p>
+        <p>Jeder Schlüssel kann auch für die Feeds benutzt werden: <code>/biete.atom?token=…</code>, <code>/suche.atom?token=…</code> und <code>/alle.atom?token=…</code>.</p>
+        <table class="table table-responsive">
+            <thead>
+                <tr>
+                    <th>Anwendung</th>
+                    <th>Zugriff</th>
+                    <th>Erstellt am</th>
+                </tr>
+            </thead>
+            <tbody>
+                {rows}
+            </tbody>
+        </table>
+        <h3>Neuer Schlüssel</h3>
+        <form class="form-inline" action="/admin/api" method="post">
+            <input type="text" class="form-control" name="client" placeholder="Name der Anwendung" />
+            <div class="checkbox"><label><input type="checkbox" name="can-write" /> Schreibzugriff</label></div>
+            <button type="submit" class="btn btn-primary">Erstellen</button>
+        </form>
+        "#,
+        notices=try!(notice::format_notices(&Position::Admin, req, &mut conn, true).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))),
+        message=match (new_token, form_error) {
+            (_, Some(msg)) => format!(r#"<div class="alert alert-danger"><strong>{}</strong> Es wurde kein Schlüssel erstellt.</div>"#, msg),
+            (Some((client, token)), None) => format!(r#"<div class="alert alert-success">Der Schlüssel für {} lautet <code>{}</code>. Bitte notieren Sie ihn jetzt, er wird nicht noch einmal angezeigt.</div>"#, util::escape_html(client), token),
+            (None, None) => String::default()
+        },
+        rows=rows
+    )))))
+}
+
+pub fn tokens_page(req: &mut Request) -> IronResult<Response> {
+    tokens_page_inner(None, None, req)
+}
+
+fn add_token_inner(req: &mut Request) -> Result<(String, String), &'static str> {
+    let actor = audit::actor(req);
+    let (client, can_write) = {
+        let form_data = try!(req.get_ref::<UrlEncodedBody>().map_err(|_| "Fehlender Formularinhalt."));
+        let client = form_data.get("client").and_then(|values| values.first()).map_or(String::default(), |client| client.trim().to_owned());
+        if client.is_empty() { return Err("Fehlender Name der Anwendung."); }
+        (client, form_data.contains_key("can-write"))
+    };
+    let token = util::random_token(TOKEN_LENGTH);
+    let mut conn = try!(::db_connection().map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    let id = try!(conn.query(format!(
+        "INSERT INTO api_tokens (client, token_hash, can_write, created_at) VALUES ({}, {}, {}, {})",
+        ::sql_escape(&client),
+        ::sql_escape(hash_token(&token)),
+        ::db::sql_bool(can_write),
+        util::sql_timestamp(UTC::now())
+    )).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank."))).last_insert_id() as i32;
+    try!(audit::record(&mut conn, &actor, audit::Action::AddApiToken, id, &json!({"client": client, "can_write": can_write})).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    Ok((client, token))
+}
+
+pub fn add_token(req: &mut Request) -> IronResult<Response> {
+    match add_token_inner(req) {
+        Ok((client, token)) => tokens_page_inner(Some((&client, &token)), None, req),
+        Err(e) => tokens_page_inner(None, Some(e), req)
+    }
+}
+
+pub fn del_token(req: &mut Request) -> IronResult<Response> {
+    let actor = audit::actor(req);
+    let mut conn = try!(::db_connection());
+    let err_msg = "Fehler beim Lesen der Nummer.";
+    let id_str = try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg))).find("id").ok_or(Error::internal(err_msg)));
+    let id = try!(i32::from_str(id_str).map_err(|e| Error::invalid(e, format!("Die Nummer {:?} ist keine Nummer.", id_str))));
+    let mut snapshot = None;
+    for row in try!(conn.query(format!("SELECT client, can_write FROM api_tokens WHERE id={}", id)).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))) {
+        let values = try!(row.map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+        snapshot = Some(json!({"client": String::from_value(values[0].clone()), "can_write": bool::from_value(values[1].clone())}));
+    }
+    let snapshot = try!(snapshot.ok_or(Error::not_found("Dieser API-Schlüssel existiert nicht.")));
+    try!(conn.query(format!("DELETE FROM api_tokens WHERE id={}", id)).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(audit::record(&mut conn, &actor, audit::Action::DeleteApiToken, id, &snapshot).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    Ok(Response::with((status::SeeOther, Header(headers::Location("/admin/api".to_owned())))))
+}
+
+#[cfg(test)]
+mod tests {
+    use migrate;
+
+    use super::{hash_token, token_client};
+
+    #[test]
+    fn looks_up_tokens_by_hash() {
+        let mut conn = migrate::test_conn();
+        conn.query(format!("INSERT INTO api_tokens (client, token_hash, can_write, created_at) VALUES ('Webseite', {}, 0, '2017-03-01 18:00:00')", ::sql_escape(hash_token("lesen")))).unwrap();
+        conn.query(format!("INSERT INTO api_tokens (client, token_hash, can_write, created_at) VALUES ('Verwaltung', {}, 1, '2017-03-01 18:00:00')", ::sql_escape(hash_token("schreiben")))).unwrap();
+        assert_eq!(token_client(&mut conn, "lesen").unwrap(), Some(("Webseite".to_owned(), false)));
+        assert_eq!(token_client(&mut conn, " schreiben\n").unwrap(), Some(("Verwaltung".to_owned(), true)));
+        assert_eq!(token_client(&mut conn, "falsch").unwrap(), None);
+    }
+
+    #[test]
+    fn stores_only_hashes() {
+        assert_eq!(hash_token("lesen").len(), 64);
+        assert!(hash_token("lesen") != "lesen");
+        assert_eq!(hash_token("lesen"), hash_token(" lesen "));
+    }
+}
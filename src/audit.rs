@@ -0,0 +1,189 @@
+//! Append-only log of admin actions, stored in the table `audit_log` (`id`, `timestamp`, `actor`, `action`, `target_id`, `snapshot`).
+
+use chrono::prelude::*;
+
+use iron::status;
+use iron::mime::Mime;
+use iron::prelude::*;
+
+use mysql::prelude::*;
+
+use serde_json;
+
+use urlencoded::UrlEncodedQuery;
+
+use error::Error;
+use notice::{self, Position};
+use util::{self, admin_account};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    AddApiToken,
+    AddMaintenance,
+    AddNotice,
+    DeleteApiToken,
+    DeleteMaintenance,
+    DeleteNotice,
+    DeleteOffer,
+    DeleteRequest,
+    EditNotice,
+    ImportNotices,
+    ImportOffers,
+    ImportRequests,
+    RestoreNotice,
+    RestoreOffer,
+    RestoreRequest,
+    UpdateOfferStatus,
+    UpdateRequestStatus
+}
+
+impl Action {
+    fn all() -> Vec<Action> {
+        vec![Action::AddApiToken, Action::AddMaintenance, Action::AddNotice, Action::DeleteApiToken, Action::DeleteMaintenance, Action::DeleteNotice, Action::DeleteOffer, Action::DeleteRequest, Action::EditNotice, Action::ImportNotices, Action::ImportOffers, Action::ImportRequests, Action::RestoreNotice, Action::RestoreOffer, Action::RestoreRequest, Action::UpdateOfferStatus, Action::UpdateRequestStatus]
+    }
+
+    fn from_str(s: &str) -> Option<Action> {
+        Action::all().into_iter().find(|action| action.as_str() == s)
+    }
+
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Action::AddApiToken => "add_api_token",
+            Action::AddMaintenance => "add_maintenance",
+            Action::AddNotice => "add_notice",
+            Action::DeleteApiToken => "delete_api_token",
+            Action::DeleteMaintenance => "delete_maintenance",
+            Action::DeleteNotice => "delete_notice",
+            Action::DeleteOffer => "delete_offer",
+            Action::DeleteRequest => "delete_request",
+            Action::EditNotice => "edit_notice",
+            Action::ImportNotices => "import_notices",
+            Action::ImportOffers => "import_offers",
+            Action::ImportRequests => "import_requests",
+            Action::RestoreNotice => "restore_notice",
+            Action::RestoreOffer => "restore_offer",
+            Action::RestoreRequest => "restore_request",
+            Action::UpdateOfferStatus => "update_offer_status",
+            Action::UpdateRequestStatus => "update_request_status"
+        }
+    }
+
+    fn german_description(&self) -> &'static str {
+        match *self {
+            Action::AddApiToken => "API-Schlüssel erstellt",
+            Action::AddMaintenance => "Wartungsfenster eingetragen",
+            Action::AddNotice => "Notiz veröffentlicht",
+            Action::DeleteApiToken => "API-Schlüssel widerrufen",
+            Action::DeleteMaintenance => "Wartungsfenster gelöscht",
+            Action::DeleteNotice => "Notiz gelöscht",
+            Action::DeleteOffer => "Angebot gelöscht",
+            Action::DeleteRequest => "Anfrage gelöscht",
+            Action::EditNotice => "Notiz bearbeitet",
+            Action::ImportNotices => "Notizen importiert",
+            Action::ImportOffers => "Angebote importiert",
+            Action::ImportRequests => "Anfragen importiert",
+            Action::RestoreNotice => "Notiz wiederhergestellt",
+            Action::RestoreOffer => "Angebot wiederhergestellt",
+            Action::RestoreRequest => "Anfrage wiederhergestellt",
+            Action::UpdateOfferStatus => "Status eines Angebots geändert",
+            Action::UpdateRequestStatus => "Status einer Anfrage geändert"
+        }
+    }
+}
+
+/// Returns the name of the account to be recorded as the actor for this request.
+pub fn actor(req: &Request) -> String {
+    admin_account(req).map(|(name, _)| name).unwrap_or_else(|| "(unbekannt)".to_owned())
+}
+
+pub fn record(conn: &mut ::db::Conn, actor: &str, action: Action, target_id: i32, snapshot: &serde_json::Value) -> Result<(), ::db::Error> {
+    try!(conn.query(format!(
+        "INSERT INTO audit_log (timestamp, actor, action, target_id, snapshot) VALUES ({}, {}, {}, {}, {})",
+        util::sql_timestamp(UTC::now()),
+        ::sql_escape(actor),
+        ::sql_escape(action.as_str()),
+        target_id,
+        ::sql_escape(snapshot.to_string())
+    )));
+    Ok(())
+}
+
+/// The admin page listing the most recent log entries, filterable by action, actor and target id.
+pub fn log_page(req: &mut Request) -> IronResult<Response> {
+    let (action_filter, actor_filter, target_filter) = match req.get_ref::<UrlEncodedQuery>() {
+        Ok(query) => (
+            query.get("aktion").and_then(|values| values.first()).and_then(|action| Action::from_str(action)),
+            query.get("akteur").and_then(|values| values.first()).map(|actor| actor.trim().to_owned()).and_then(|actor| if actor.is_empty() { None } else { Some(actor) }),
+            query.get("id").and_then(|values| values.first()).and_then(|id| id.trim().parse::<i32>().ok())
+        ),
+        Err(_) => (None, None, None)
+    };
+    let mut conditions = Vec::default();
+    if let Some(action) = action_filter { conditions.push(format!("action={}", ::sql_escape(action.as_str()))); }
+    if let Some(ref actor) = actor_filter { conditions.push(format!("actor={}", ::sql_escape(actor))); }
+    if let Some(id) = target_filter { conditions.push(format!("target_id={}", id)); }
+    let mut conn = try!(::db_connection());
+    let query = format!(
+        "SELECT timestamp, actor, action, target_id, snapshot FROM audit_log{} ORDER BY id DESC LIMIT 500",
+        if conditions.is_empty() { String::default() } else { format!(" WHERE {}", conditions.join(" AND ")) }
+    );
+    let mut log_rows = String::default();
+    for row in try!(conn.query(query).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))) {
+        let values = try!(row.map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+        let action = String::from_value(values[2].clone());
+        let snapshot = String::from_value(values[4].clone());
+        log_rows.push_str(&format!(
+            r#"
+<tr>
+    <td>{timestamp}</td>
+    <td>{actor}</td>
+    <td>{action}</td>
+    <td>{target_id}</td>
+    <td><pre>{snapshot}</pre></td>
+</tr>
+            "#,
+            timestamp=util::berlin_time(NaiveDateTime::from_value(values[0].clone())).format("%d.%m.%Y %H:%M:%S"),
+            actor=util::escape_html(String::from_value(values[1].clone())),
+            action=Action::from_str(&action).map_or(action.clone(), |action| action.german_description().to_owned()),
+            target_id=i32::from_value(values[3].clone()),
+            snapshot=util::escape_html(serde_json::from_str::<serde_json::Value>(&snapshot).ok().and_then(|value| serde_json::to_string_pretty(&value).ok()).unwrap_or(snapshot))
+        ));
+    }
+    if log_rows.is_empty() {
+        log_rows = r#"<tr><td colspan="5" style="color: gray; font-style: italic;">Keine passenden Einträge.</td></tr>"#.to_owned();
+    }
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/admin/protokoll", true, &format!(
+        r#"
+        {notices}
+        <h2>Protokoll</h2>
+        <form class="form-inline" action="/admin/protokoll" method="get" style="margin-bottom: 20px;">
+            <select class="form-control" name="aktion">
+                <option value="">alle Aktionen</option>
+                {action_options}
+            </select>
+            <input type="text" class="form-control" name="akteur" placeholder="Benutzername" value="{actor}" />
+            <input type="number" class="form-control" name="id" placeholder="Nummer" value="{id}" />
+            <button type="submit" class="btn btn-primary">Filtern</button>
+        </form>
+        <table class="table table-responsive">
+            <thead>
+                <tr>
+                    <th>Zeit</th>
+                    <th>Benutzer</th>
+                    <th>Aktion</th>
+                    <th>Nummer</th>
+                    <th>Daten</th>
+                </tr>
+            </thead>
+            <tbody>
+                {log_rows}
+            </tbody>
+        </table>
+        "#,
+        notices=try!(notice::format_notices(&Position::Admin, req, &mut conn, true).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))),
+        action_options=Action::all().into_iter().map(|action| format!(r#"<option value="{}"{}>{}</option>"#, action.as_str(), if action_filter == Some(action) { " selected" } else { "" }, action.german_description())).collect::<String>(),
+        actor=actor_filter.map_or(String::default(), util::escape_html),
+        id=target_filter.map_or(String::default(), |id| id.to_string()),
+        log_rows=log_rows
+    )))))
+}
@@ -0,0 +1,308 @@
+//! The storage layer, which runs the same SQL statements against MySQL or SQLite depending on `database` in the config.
+//!
+//! Rows are returned as lists of MySQL values for both backends, so they can be read with `FromValue` as before. Times are stored as `YYYY-MM-DD HH:MM:SS` in UTC (see `util::sql_timestamp`), which SQLite keeps as text that sorts and compares like MySQL's `DATETIME`.
+//!
+//! Requests take connections from a bounded pool with `connection`. A connection is checked before it is handed out and replaced if the server has closed it in the meantime. When all connections are in use, `connection` waits up to `acquire_timeout_secs` (see `util::ConfigPool`) for one to be returned.
+//!
+//! Statements must stick to the syntax both backends understand. String literals are built with `::sql_escape`, which escapes for the configured backend, upserts with `upsert_clause`, and transactions are started with `BEGIN`.
+
+use std::error;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use std::vec;
+
+use mysql::Value;
+
+use rusqlite;
+use rusqlite::types::Value as SqliteValue;
+
+use util::{CONFIG, MY_OPTS};
+
+/// How long an SQLite connection waits for another one to finish writing.
+const SQLITE_BUSY_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Mysql,
+    /// Stored in the file at `sqlite_path` in the config. Meant for development and small deployments without a database server.
+    Sqlite
+}
+
+impl Default for Backend {
+    fn default() -> Backend {
+        Backend::Mysql
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Mysql(::mysql::Error),
+    Sqlite(rusqlite::Error),
+    /// No pooled connection became available within `acquire_timeout_secs`.
+    PoolTimeout
+}
+
+impl From<::mysql::Error> for Error {
+    fn from(e: ::mysql::Error) -> Error {
+        Error::Mysql(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Error {
+        Error::Sqlite(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Mysql(ref e) => write!(f, "MySQL error: {}", e),
+            Error::Sqlite(ref e) => write!(f, "SQLite error: {}", e),
+            Error::PoolTimeout => write!(f, "timed out waiting for a database connection")
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Mysql(ref e) => e.description(),
+            Error::Sqlite(ref e) => e.description(),
+            Error::PoolTimeout => "timed out waiting for a database connection"
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Mysql(ref e) => Some(e),
+            Error::Sqlite(ref e) => Some(e),
+            Error::PoolTimeout => None
+        }
+    }
+}
+
+/// The values of a row, in the order of the selected columns.
+pub type Row = Vec<Value>;
+
+/// The rows returned by a statement, which have already been read completely. Iterating yields `Result`s like the MySQL driver's query results.
+pub struct QueryResult {
+    rows: vec::IntoIter<Row>,
+    affected_rows: u64,
+    last_insert_id: u64
+}
+
+impl QueryResult {
+    pub fn affected_rows(&self) -> u64 {
+        self.affected_rows
+    }
+
+    pub fn last_insert_id(&self) -> u64 {
+        self.last_insert_id
+    }
+}
+
+impl Iterator for QueryResult {
+    type Item = Result<Row, Error>;
+
+    fn next(&mut self) -> Option<Result<Row, Error>> {
+        self.rows.next().map(Ok)
+    }
+}
+
+fn from_sqlite_value(value: SqliteValue) -> Value {
+    match value {
+        SqliteValue::Null => Value::NULL,
+        SqliteValue::Integer(i) => Value::Int(i),
+        SqliteValue::Real(f) => Value::Float(f),
+        SqliteValue::Text(s) => Value::Bytes(s.into_bytes()),
+        SqliteValue::Blob(bytes) => Value::Bytes(bytes)
+    }
+}
+
+pub enum Conn {
+    Mysql(::mysql::Conn),
+    Sqlite(rusqlite::Connection)
+}
+
+impl Conn {
+    /// Connects to the database configured in `util::Config`.
+    pub fn new() -> Result<Conn, Error> {
+        Ok(match CONFIG.database {
+            Backend::Mysql => Conn::Mysql(try!(::mysql::Conn::new(MY_OPTS.clone()))),
+            Backend::Sqlite => {
+                let conn = try!(rusqlite::Connection::open(&CONFIG.sqlite_path));
+                try!(conn.busy_timeout(Duration::from_secs(SQLITE_BUSY_TIMEOUT_SECS)));
+                Conn::Sqlite(conn)
+            }
+        })
+    }
+
+    /// Whether the connection can still be used. SQLite connections are to a local file and cannot be closed by the server.
+    fn is_alive(&mut self) -> bool {
+        match *self {
+            Conn::Mysql(ref mut conn) => conn.ping(),
+            Conn::Sqlite(_) => true
+        }
+    }
+
+    /// Runs a single statement.
+    pub fn query<Q: AsRef<str>>(&mut self, query: Q) -> Result<QueryResult, Error> {
+        match *self {
+            Conn::Mysql(ref mut conn) => {
+                let result = try!(conn.query(query));
+                let affected_rows = result.affected_rows();
+                let last_insert_id = result.last_insert_id();
+                let mut rows = Vec::default();
+                for row in result {
+                    rows.push(try!(row).unwrap());
+                }
+                Ok(QueryResult { rows: rows.into_iter(), affected_rows: affected_rows, last_insert_id: last_insert_id })
+            }
+            Conn::Sqlite(ref conn) => {
+                let mut stmt = try!(conn.prepare(query.as_ref()));
+                let num_columns = stmt.column_count() as usize;
+                let mut rows = Vec::default();
+                let affected_rows = if num_columns == 0 {
+                    try!(stmt.execute(&[])) as u64
+                } else {
+                    let mut result = try!(stmt.query(&[]));
+                    while let Some(row) = result.next() {
+                        let row = try!(row);
+                        let mut values = Vec::with_capacity(num_columns);
+                        for i in 0..num_columns {
+                            values.push(from_sqlite_value(try!(row.get_checked::<usize, SqliteValue>(i))));
+                        }
+                        rows.push(values);
+                    }
+                    0
+                };
+                Ok(QueryResult { rows: rows.into_iter(), affected_rows: affected_rows, last_insert_id: conn.last_insert_rowid() as u64 })
+            }
+        }
+    }
+}
+
+struct PoolState {
+    idle: Vec<Conn>,
+    /// The number of connections which are idle, in use, or being opened.
+    open: usize
+}
+
+struct Pool {
+    state: Mutex<PoolState>,
+    returned: Condvar
+}
+
+lazy_static! {
+    static ref POOL: Pool = Pool {
+        state: Mutex::new(PoolState { idle: Vec::default(), open: 0 }),
+        returned: Condvar::new()
+    };
+}
+
+impl Pool {
+    fn lock(&self) -> MutexGuard<PoolState> {
+        // the state stays consistent even if a thread panicked while holding the lock
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Opens a connection for a slot which has already been counted in `open`, and gives the slot up if that fails.
+    fn open(&self) -> Result<PooledConn, Error> {
+        match Conn::new() {
+            Ok(conn) => Ok(PooledConn { conn: Some(conn) }),
+            Err(e) => {
+                self.lock().open -= 1;
+                self.returned.notify_one();
+                Err(e)
+            }
+        }
+    }
+
+    fn get(&self) -> Result<PooledConn, Error> {
+        let deadline = Instant::now() + Duration::from_secs(CONFIG.db_pool.acquire_timeout_secs);
+        let mut state = self.lock();
+        loop {
+            let idle = state.idle.pop();
+            if let Some(mut conn) = idle {
+                drop(state);
+                if conn.is_alive() { return Ok(PooledConn { conn: Some(conn) }); }
+                // reconnect in the same slot
+                return self.open();
+            }
+            if state.open < CONFIG.db_pool.max_size {
+                state.open += 1;
+                drop(state);
+                return self.open();
+            }
+            let now = Instant::now();
+            if now >= deadline { return Err(Error::PoolTimeout); }
+            state = self.returned.wait_timeout(state, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+
+    fn put_back(&self, conn: Conn) {
+        self.lock().idle.push(conn);
+        self.returned.notify_one();
+    }
+}
+
+/// A connection from the pool, which is returned to the pool when dropped.
+pub struct PooledConn {
+    conn: Option<Conn>
+}
+
+impl Deref for PooledConn {
+    type Target = Conn;
+
+    fn deref(&self) -> &Conn {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledConn {
+    fn deref_mut(&mut self) -> &mut Conn {
+        self.conn.as_mut().unwrap()
+    }
+}
+
+impl PooledConn {
+    /// Rolls back the transaction started with `BEGIN`. If that fails, the connection is closed instead of being returned to the pool with the transaction still open.
+    pub fn rollback(mut self) {
+        if let Err(e) = self.query("ROLLBACK") {
+            ::error::log(&e);
+            drop(self.conn.take());
+            POOL.lock().open -= 1;
+            POOL.returned.notify_one();
+        }
+    }
+}
+
+impl Drop for PooledConn {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            POOL.put_back(conn);
+        }
+    }
+}
+
+/// Takes a connection from the pool, opening a new one if none is idle and the pool is not full.
+pub fn connection() -> Result<PooledConn, Error> {
+    POOL.get()
+}
+
+/// A boolean as SQL literal. SQLite only understands `TRUE` and `FALSE` since version 3.23, so both backends get `1` and `0`.
+pub fn sql_bool(value: bool) -> &'static str {
+    if value { "1" } else { "0" }
+}
+
+/// The end of an `INSERT` statement which updates the given columns instead if a row with the same `key` already exists. Further assignments can be appended after a comma. SQLite supports this since version 3.24, see the `rusqlite` dependency.
+pub fn upsert_clause(key: &str, columns: &[&str]) -> String {
+    match CONFIG.database {
+        Backend::Mysql => format!("ON DUPLICATE KEY UPDATE {}", columns.iter().map(|column| format!("{0}=VALUES({0})", column)).collect::<Vec<_>>().join(", ")),
+        Backend::Sqlite => format!("ON CONFLICT ({}) DO UPDATE SET {}", key, columns.iter().map(|column| format!("{0}=excluded.{0}", column)).collect::<Vec<_>>().join(", "))
+    }
+}
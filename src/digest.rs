@@ -0,0 +1,312 @@
+//! E-mail digests of new entries, sent daily or weekly.
+//!
+//! Subscriptions are stored in the table `subscriptions` (`id`, `mail`, `frequency`, `offers`, `requests`, `token`, `created_at`, `confirmed_at`, `last_digest_at`). A subscription only becomes active once the link in the confirmation mail has been opened. The `token` authenticates the confirmation and unsubscribe links, which are exempt from `check_auth`. Mails are handed to the `sendmail` binary from the config.
+
+use std::io::{self, ErrorKind};
+use std::io::prelude::*;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time;
+
+use chrono::Duration;
+use chrono::prelude::*;
+
+use chrono_tz::Europe::Berlin;
+
+use iron::status;
+use iron::mime::Mime;
+use iron::prelude::*;
+
+use mysql::prelude::*;
+
+use router::Router;
+
+use urlencoded::UrlEncodedBody;
+
+use admin::IsAdmin;
+use entry::{self, ENTRY_COLUMNS, Entry};
+use error::{self, Error};
+use logging;
+use util::{self, BASE_URL, CONFIG};
+
+/// Unconfirmed subscriptions are deleted after this many days.
+pub const CONFIRMATION_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Frequency {
+    Daily,
+    Weekly
+}
+
+impl Frequency {
+    fn all() -> Vec<Frequency> {
+        vec![Frequency::Daily, Frequency::Weekly]
+    }
+
+    fn from_str(s: &str) -> Option<Frequency> {
+        Frequency::all().into_iter().find(|frequency| frequency.as_str() == s)
+    }
+
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Frequency::Daily => "daily",
+            Frequency::Weekly => "weekly"
+        }
+    }
+
+    fn german_name(&self) -> &'static str {
+        match *self {
+            Frequency::Daily => "täglich",
+            Frequency::Weekly => "wöchentlich"
+        }
+    }
+
+    fn interval(&self) -> Duration {
+        match *self {
+            Frequency::Daily => Duration::days(1),
+            Frequency::Weekly => Duration::weeks(1)
+        }
+    }
+}
+
+struct Subscription {
+    id: i32,
+    mail: String,
+    frequency: Frequency,
+    offers: bool,
+    requests: bool,
+    token: String,
+    last_digest_at: DateTime<UTC>
+}
+
+impl Subscription {
+    fn entry_types(&self) -> Vec<entry::Type> {
+        let mut entry_types = Vec::default();
+        if self.offers { entry_types.push(entry::Type::Offer); }
+        if self.requests { entry_types.push(entry::Type::Request); }
+        entry_types
+    }
+
+    fn unsubscribe_url(&self) -> String {
+        format!("{}/abo/{}/abbestellen", BASE_URL, self.token)
+    }
+}
+
+/// Only a plain address, so it cannot be used to inject mail headers.
+pub fn is_valid_mail(mail: &str) -> bool {
+    mail.contains('@') && !mail.starts_with('@') && !mail.ends_with('@') && !mail.chars().any(|c| c.is_whitespace() || c.is_control() || c == ',' || c == '<' || c == '>')
+}
+
+/// Hands a plain text mail to sendmail. The subject must be ASCII.
+pub fn send_mail(to: &str, subject: &str, body: &str, unsubscribe_url: Option<&str>) -> io::Result<()> {
+    let mut child = try!(Command::new(&CONFIG.mail.sendmail).arg("-t").arg("-oi").stdin(Stdio::piped()).spawn());
+    {
+        let stdin = try!(child.stdin.as_mut().ok_or(io::Error::new(ErrorKind::Other, "sendmail has no stdin")));
+        try!(write!(stdin, "From: {}\r\nTo: {}\r\nSubject: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n", CONFIG.mail.from, to, subject));
+        if let Some(url) = unsubscribe_url {
+            try!(write!(stdin, "List-Unsubscribe: <{}>\r\nList-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n", url));
+        }
+        try!(write!(stdin, "\r\n{}", body.replace("\n", "\r\n")));
+    }
+    let exit_status = try!(child.wait());
+    if exit_status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(ErrorKind::Other, format!("sendmail exited with {}", exit_status)))
+    }
+}
+
+fn format_digest(subscription: &Subscription, conn: &mut ::db::Conn) -> Result<Option<String>, ::db::Error> {
+    let mut sections = Vec::default();
+    for entry_type in subscription.entry_types() {
+        let mut section = format!("{}:\n", entry_type.german_plural());
+        let mut any = false;
+        for row in try!(conn.query(format!("SELECT {} FROM {} WHERE deleted_at IS NULL AND created_at > {} ORDER BY created_at", ENTRY_COLUMNS, entry_type.table(), util::sql_timestamp(subscription.last_digest_at)))) {
+            let entry = Entry::from_row(&try!(row));
+            section.push_str(&format!("\n* {}: {}\n  {}/{}/{}\n", entry.name, entry.description.replace("\n", "\n  "), BASE_URL, entry_type.url_part(), entry.id));
+            any = true;
+        }
+        if any { sections.push(section); }
+    }
+    Ok(if sections.is_empty() {
+        None
+    } else {
+        Some(format!(
+            "Hallo,\n\nseit {since} Uhr wurden auf der WiW-Börse folgende Einträge eingestellt:\n\n{sections}\n-- \nDiese Mail wurde an {mail} geschickt, weil Sie die {frequency}e Zusammenfassung abonniert haben.\nAbbestellen: {unsubscribe_url}\n",
+            since=subscription.last_digest_at.with_timezone(&Berlin).format("%d.%m.%Y %H:%M"),
+            sections=sections.join("\n"),
+            mail=subscription.mail,
+            frequency=subscription.frequency.german_name(),
+            unsubscribe_url=subscription.unsubscribe_url()
+        ))
+    })
+}
+
+/// Sends all digests which are due and deletes expired unconfirmed subscriptions. Subscriptions are only marked as sent if sending succeeded, so failed mails are retried on the next run.
+pub fn send_digests(conn: &mut ::db::Conn) -> Result<(), ::db::Error> {
+    let now = UTC::now();
+    let mut due = Vec::default();
+    for row in try!(conn.query("SELECT id, mail, frequency, offers, requests, token, last_digest_at FROM subscriptions WHERE confirmed_at IS NOT NULL")) {
+        let values = try!(row);
+        let subscription = Subscription {
+            id: i32::from_value(values[0].clone()),
+            mail: String::from_value(values[1].clone()),
+            frequency: Frequency::from_str(&String::from_value(values[2].clone())).unwrap_or(Frequency::Weekly),
+            offers: bool::from_value(values[3].clone()),
+            requests: bool::from_value(values[4].clone()),
+            token: String::from_value(values[5].clone()),
+            last_digest_at: UTC.from_utc_datetime(&NaiveDateTime::from_value(values[6].clone()))
+        };
+        if subscription.last_digest_at + subscription.frequency.interval() <= now {
+            due.push(subscription);
+        }
+    }
+    for subscription in due {
+        if let Some(body) = try!(format_digest(&subscription, conn)) {
+            if let Err(e) = send_mail(&subscription.mail, "WiW-Boerse: neue Eintraege", &body, Some(&subscription.unsubscribe_url())) {
+                logging::log_message("error", &format!("failed to send digest to {}: {}", subscription.mail, e));
+                continue;
+            }
+        }
+        try!(conn.query(format!("UPDATE subscriptions SET last_digest_at={} WHERE id={}", util::sql_timestamp(now), subscription.id)));
+    }
+    try!(conn.query(format!("DELETE FROM subscriptions WHERE confirmed_at IS NULL AND created_at < {}", util::sql_timestamp(now - Duration::days(CONFIRMATION_DAYS)))));
+    Ok(())
+}
+
+/// Runs `send_digests` once an hour. Meant to be run in a background thread.
+pub fn send_loop() {
+    loop {
+        if let Ok(mut conn) = ::db_connection() {
+            if let Err(e) = send_digests(&mut conn) {
+                logging::log_message("error", &format!("failed to send digests: {}", e));
+            }
+        }
+        thread::sleep(time::Duration::from_secs(60 * 60));
+    }
+}
+
+fn subscribe_page_inner(form_error: Option<&'static str>, req: &mut Request) -> IronResult<Response> {
+    let is_admin = req.get::<IsAdmin>().unwrap_or(false);
+    Ok(Response::with((if form_error.is_some() { status::BadRequest } else { status::Ok }, "text/html".parse::<Mime>().unwrap(), util::page("/abo/neu", is_admin, &format!(
+        r#"
+        {error_message}
+        <h2>Per E-Mail benachrichtigen</h2>
+        <p>Wir schicken Ihnen täglich oder wöchentlich eine Zusammenfassung der neuen Angebote und Anfragen. Wenn es nichts Neues gibt, bekommen Sie keine Mail.</p>
+        <form class="form-horizontal" action="/abo/neu" method="post">
+            <div class="form-group">
+                <label for="mail" class="col-sm-2 control-label">E-Mail</label>
+                <div class="col-sm-10">
+                    <input type="email" class="form-control" name="mail" id="mail" />
+                </div>
+            </div>
+            <div class="form-group">
+                <label class="col-sm-2 control-label">Häufigkeit</label>
+                <div class="col-sm-10">
+                    <div class="radio"><label><input type="radio" name="frequency" value="daily" /> täglich</label></div>
+                    <div class="radio"><label><input type="radio" name="frequency" value="weekly" checked /> wöchentlich</label></div>
+                </div>
+            </div>
+            <div class="form-group">
+                <label class="col-sm-2 control-label">Benachrichtigen über</label>
+                <div class="col-sm-10">
+                    <div class="checkbox"><label><input type="checkbox" name="offers" checked /> Angebote</label></div>
+                    <div class="checkbox"><label><input type="checkbox" name="requests" checked /> Anfragen</label></div>
+                </div>
+            </div>
+            <div class="form-group">
+                <div class="col-sm-offset-2 col-sm-10">
+                    <p class="help-block">Sie erhalten zuerst eine Mail mit einem Bestätigungslink. Jede Zusammenfassung enthält einen Link zum Abbestellen.</p>
+                    <a href="/" style="float: right;" class="btn btn-danger">Abbrechen</a>
+                    <button type="submit" class="btn btn-primary">Abonnieren</button>
+                </div>
+            </div>
+        </form>
+        "#,
+        error_message=if let Some(msg) = form_error { format!(r#"<div class="alert alert-danger"><strong>{}</strong> Bitte füllen Sie das Formular erneut aus.</div>"#, msg) } else { String::default() }
+    )))))
+}
+
+pub fn subscribe_page(req: &mut Request) -> IronResult<Response> {
+    subscribe_page_inner(None, req)
+}
+
+fn subscribe_inner(req: &mut Request) -> Result<Response, &'static str> {
+    let is_admin = req.get::<IsAdmin>().unwrap_or(false);
+    let (mail, frequency, offers, requests) = {
+        let form_data = try!(req.get_ref::<UrlEncodedBody>().map_err(|_| "Fehlender Formularinhalt."));
+        let mail = form_data.get("mail").and_then(|values| values.first()).map_or(String::default(), |mail| mail.trim().to_owned());
+        if !is_valid_mail(&mail) { return Err("Ungültige Mailadresse."); }
+        let frequency = try!(form_data.get("frequency").and_then(|values| values.first()).and_then(|frequency| Frequency::from_str(frequency)).ok_or("Bitte wählen Sie aus, wie oft Sie benachrichtigt werden möchten."));
+        let (offers, requests) = (form_data.contains_key("offers"), form_data.contains_key("requests"));
+        if !offers && !requests { return Err("Bitte wählen Sie Angebote, Anfragen oder beides aus."); }
+        (mail, frequency, offers, requests)
+    };
+    let token = util::random_token(32);
+    let mut conn = try!(::db_connection().map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(conn.query(format!("DELETE FROM subscriptions WHERE mail={} AND confirmed_at IS NULL", ::sql_escape(&mail))).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(conn.query(format!(
+        "INSERT INTO subscriptions (mail, frequency, offers, requests, token, created_at) VALUES ({}, {}, {}, {}, {}, {})",
+        ::sql_escape(&mail),
+        ::sql_escape(frequency.as_str()),
+        ::db::sql_bool(offers),
+        ::db::sql_bool(requests),
+        ::sql_escape(&token),
+        util::sql_timestamp(UTC::now())
+    )).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(send_mail(&mail, "WiW-Boerse: Bitte bestaetigen Sie Ihr Abonnement", &format!(
+        "Hallo,\n\nSie möchten {frequency} per Mail über neue {types} auf der WiW-Börse informiert werden. Bitte bestätigen Sie dies über den folgenden Link:\n\n{base_url}/abo/{token}/bestaetigen\n\nWenn Sie das nicht waren, können Sie diese Mail ignorieren. Ohne Bestätigung wird die Adresse nach {days} Tagen gelöscht.\n",
+        frequency=frequency.german_name(),
+        types=match (offers, requests) { (true, true) => "Angebote und Anfragen", (true, false) => "Angebote", (false, _) => "Anfragen" },
+        base_url=BASE_URL,
+        token=token,
+        days=CONFIRMATION_DAYS
+    ), None).map_err(|e| error::logged(e, "Die Bestätigungsmail konnte nicht verschickt werden.")));
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/abo/neu", is_admin, &format!(
+        r#"
+        <div class="alert alert-success">Wir haben eine Mail an {} geschickt. Bitte öffnen Sie den Link darin, um das Abonnement zu bestätigen.</div>
+        <p><a href="/">Zurück zur Börse</a></p>
+        "#,
+        util::escape_html(&mail)
+    )))))
+}
+
+pub fn subscribe(req: &mut Request) -> IronResult<Response> {
+    subscribe_inner(req).or_else(|e| subscribe_page_inner(Some(e), req))
+}
+
+fn token_param(req: &Request) -> IronResult<String> {
+    let err_msg = "Fehler beim Lesen des Links.";
+    Ok(try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg))).find("token").ok_or(Error::internal(err_msg))).to_owned())
+}
+
+/// Activates a subscription. An earlier subscription for the same address is replaced, so this is also how the settings are changed.
+pub fn confirm(req: &mut Request) -> IronResult<Response> {
+    let token = try!(token_param(req));
+    let mut conn = try!(::db_connection());
+    let mut mail = None;
+    for row in try!(conn.query(format!("SELECT mail FROM subscriptions WHERE token={}", ::sql_escape(&token))).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))) {
+        let values = try!(row.map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+        mail = Some(String::from_value(values[0].clone()));
+    }
+    let mail = try!(mail.ok_or(Error::not_found("Dieser Link ist ungültig oder abgelaufen.")));
+    let now = util::sql_timestamp(UTC::now());
+    try!(conn.query(format!("DELETE FROM subscriptions WHERE mail={} AND token<>{}", ::sql_escape(&mail), ::sql_escape(&token))).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(conn.query(format!("UPDATE subscriptions SET confirmed_at={0}, last_digest_at={0} WHERE token={1} AND confirmed_at IS NULL", now, ::sql_escape(&token))).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/abo/neu", false, &format!(
+        r#"<div class="alert alert-success">Das Abonnement für {} ist bestätigt.</div>"#,
+        util::escape_html(&mail)
+    )))))
+}
+
+/// Deletes a subscription. Works with both GET (link in the mail) and POST (one-click unsubscribe from the mail client).
+pub fn unsubscribe(req: &mut Request) -> IronResult<Response> {
+    let token = try!(token_param(req));
+    let mut conn = try!(::db_connection());
+    let deleted = try!(conn.query(format!("DELETE FROM subscriptions WHERE token={}", ::sql_escape(&token))).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))).affected_rows() > 0;
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/abo/neu", false, if deleted {
+        r#"<div class="alert alert-success">Sie erhalten keine weiteren Mails von der WiW-Börse.</div>"#
+    } else {
+        r#"<div class="alert alert-info">Dieses Abonnement wurde bereits abbestellt.</div>"#
+    }))))
+}
@@ -1,3 +1,13 @@
+use std::collections::HashMap;
+
+use chrono::prelude::*;
+
+use mysql::prelude::*;
+
+use serde_json;
+
+use i18n::Locale;
+
 #[derive(Clone, Copy)]
 pub enum Type {
     Offer,
@@ -16,10 +26,6 @@ impl Type {
         self.map("das", "die")
     }
 
-    pub fn german_article_capital(&self) -> &'static str {
-        self.map("Das", "Die")
-    }
-
     pub fn german_noun(&self) -> &'static str {
         self.map("Angebot", "Anfrage")
     }
@@ -35,4 +41,184 @@ impl Type {
     pub fn url_part(&self) -> &'static str {
         self.map("biete", "suche")
     }
+
+    /// The prefix of this type's keys in the translation catalogues.
+    pub fn key(&self) -> &'static str {
+        self.map("offer", "request")
+    }
+
+    /// Looks up a text that differs between offers and requests, e.g. `text(locale, "noun")` for `offer.noun` or `request.noun`.
+    pub fn text(&self, locale: Locale, key: &str) -> String {
+        locale.t(&format!("{}.{}", self.key(), key))
+    }
+
+    pub fn noun(&self, locale: Locale) -> String {
+        self.text(locale, "noun")
+    }
+
+    pub fn plural(&self, locale: Locale) -> String {
+        self.text(locale, "plural")
+    }
+}
+
+/// The categories an entry can be filed under, as slug and German name. The slug is stored in the column `category` of `offers` and `requests` and used in the URL of the category page.
+pub const CATEGORIES: &'static [(&'static str, &'static str)] = &[
+    ("moebel", "Möbel"),
+    ("haushalt", "Haushalt"),
+    ("kleidung", "Kleidung"),
+    ("kinder", "Kinder und Spielzeug"),
+    ("fahrraeder", "Fahrräder"),
+    ("elektro", "Elektrogeräte"),
+    ("hilfe", "Hilfe und Begleitung"),
+    ("sonstiges", "Sonstiges")
+];
+
+pub fn category_name(slug: &str) -> Option<&'static str> {
+    CATEGORIES.iter().find(|&&(category_slug, _)| category_slug == slug).map(|&(_, name)| name)
+}
+
+/// The name of a category in the given locale, or `None` if the category does not exist.
+pub fn category_label(slug: &str, locale: Locale) -> Option<String> {
+    category_name(slug).map(|_| locale.t(&format!("category.{}", slug)))
+}
+
+/// Whether an entry is still available. Stored in the column `status` of `offers` and `requests`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Open,
+    Reserved,
+    Closed
+}
+
+impl Status {
+    pub fn all() -> Vec<Status> {
+        vec![Status::Open, Status::Reserved, Status::Closed]
+    }
+
+    pub fn from_str(s: &str) -> Option<Status> {
+        Status::all().into_iter().find(|status| status.as_str() == s)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Status::Open => "open",
+            Status::Reserved => "reserved",
+            Status::Closed => "closed"
+        }
+    }
+
+    pub fn german_name(&self) -> &'static str {
+        match *self {
+            Status::Open => "offen",
+            Status::Reserved => "reserviert",
+            Status::Closed => "erledigt"
+        }
+    }
+
+    pub fn name(&self, locale: Locale) -> String {
+        locale.t(&format!("status.{}", self.as_str()))
+    }
+}
+
+impl Default for Status {
+    fn default() -> Status {
+        Status::Open
+    }
+}
+
+/// The columns read by `Entry::from_row`, in order.
+pub const ENTRY_COLUMNS: &'static str = "id, name, description, phone, mail, status, created_at, category";
+
+/// An offer or request which has not been deleted.
+pub struct Entry {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+    pub phone: Option<String>,
+    pub mail: Option<String>,
+    pub status: Status,
+    /// `None` for entries created before the column `created_at` was added.
+    pub created_at: Option<DateTime<UTC>>,
+    /// The slug of one of the `CATEGORIES`.
+    pub category: Option<String>
+}
+
+impl Entry {
+    pub fn from_row(values: &::db::Row) -> Entry {
+        Entry {
+            id: i32::from_value(values[0].clone()),
+            name: String::from_value(values[1].clone()),
+            description: String::from_value(values[2].clone()),
+            phone: Option::<String>::from_value(values[3].clone()),
+            mail: Option::<String>::from_value(values[4].clone()),
+            status: Status::from_str(&String::from_value(values[5].clone())).unwrap_or_default(),
+            created_at: Option::<NaiveDateTime>::from_value(values[6].clone()).map(|time| UTC.from_utc_datetime(&time)),
+            category: Option::<String>::from_value(values[7].clone())
+        }
+    }
+
+    pub fn load(entry_type: Type, id: i32, conn: &mut ::db::Conn) -> Result<Option<Entry>, ::db::Error> {
+        for row in try!(conn.query(format!("SELECT {} FROM {} WHERE id={} AND deleted_at IS NULL", ENTRY_COLUMNS, entry_type.table(), id))) {
+            return Ok(Some(Entry::from_row(&try!(row))));
+        }
+        Ok(None)
+    }
+
+    pub fn list(entry_type: Type, conn: &mut ::db::Conn) -> Result<Vec<Entry>, ::db::Error> {
+        let mut entries = Vec::default();
+        for row in try!(conn.query(format!("SELECT {} FROM {} WHERE deleted_at IS NULL ORDER BY id", ENTRY_COLUMNS, entry_type.table()))) {
+            entries.push(Entry::from_row(&try!(row)));
+        }
+        Ok(entries)
+    }
+
+    pub fn to_json(&self, entry_type: Type) -> serde_json::Value {
+        json!({
+            "id": self.id,
+            "type": entry_type.map("offer", "request"),
+            "name": self.name,
+            "description": self.description,
+            "phone": self.phone,
+            "mail": self.mail,
+            "status": self.status.as_str(),
+            "created_at": self.created_at.map(|time| time.to_rfc3339()),
+            "category": self.category,
+            "url": format!("/{}/{}", entry_type.url_part(), self.id)
+        })
+    }
+}
+
+/// Saves descriptions of an entry in additional languages. They are stored in the table `entry_translations` (`id`, `entry_table`, `entry_id`, `locale`, `description`), while the column `description` keeps the original.
+pub fn save_translations(entry_type: Type, id: i32, translations: &[(Locale, String)], conn: &mut ::db::Conn) -> Result<(), ::db::Error> {
+    for &(locale, ref description) in translations {
+        try!(conn.query(format!(
+            "INSERT INTO entry_translations (entry_table, entry_id, locale, description) VALUES ({}, {}, {}, {})",
+            ::sql_escape(entry_type.table()),
+            id,
+            ::sql_escape(locale.code()),
+            ::sql_escape(description)
+        )));
+    }
+    Ok(())
+}
+
+/// The descriptions of the given entries in `locale`, by entry id. Entries without a description in that language are missing from the map.
+pub fn translated_descriptions(entry_type: Type, ids: &[i32], locale: Locale, conn: &mut ::db::Conn) -> Result<HashMap<i32, String>, ::db::Error> {
+    let mut descriptions = HashMap::new();
+    if ids.is_empty() { return Ok(descriptions); }
+    for row in try!(conn.query(format!(
+        "SELECT entry_id, description FROM entry_translations WHERE entry_table={} AND locale={} AND entry_id IN ({})",
+        ::sql_escape(entry_type.table()),
+        ::sql_escape(locale.code()),
+        ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", ")
+    ))) {
+        let values = try!(row);
+        descriptions.insert(i32::from_value(values[0].clone()), String::from_value(values[1].clone()));
+    }
+    Ok(descriptions)
+}
+
+pub fn delete_translations(entry_type: Type, id: i32, conn: &mut ::db::Conn) -> Result<(), ::db::Error> {
+    try!(conn.query(format!("DELETE FROM entry_translations WHERE entry_table={} AND entry_id={}", ::sql_escape(entry_type.table()), id)));
+    Ok(())
 }
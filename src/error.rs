@@ -0,0 +1,168 @@
+//! The error type for handlers, which keeps the underlying error for the log apart from the message shown to users.
+//!
+//! Converting an `Error` into an `IronError` (e.g. with `try!`) responds with the status and message of its kind, which `error_page` then renders as a full page. Server errors are logged with their technical details by `error_page::ErrorPages`. Form handlers which show errors next to the form instead use `logged` to log the underlying error before discarding it.
+
+use std::error;
+use std::fmt;
+
+use iron::prelude::*;
+use iron::status;
+
+use db;
+use logging;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A statement failed or no database connection could be opened.
+    Db(db::Error, String),
+    /// The request contained something which could not be parsed, e.g. an id which is not a number.
+    Invalid(Box<error::Error + Send>, String),
+    /// Another library failed while handling the request, e.g. while generating a PDF.
+    Other(Box<error::Error + Send>, String),
+    /// Missing or wrong credentials.
+    Auth(String),
+    /// The account is logged in but not allowed to do this.
+    Forbidden(String),
+    NotFound(String),
+    /// Writing is disabled during maintenance, see `maintenance::check_writable`.
+    ReadOnly(String),
+    /// A bug in the Börse, e.g. a route without a parameter its handler expects.
+    Internal(String)
+}
+
+impl Error {
+    pub fn db<M: Into<String>>(e: db::Error, message: M) -> Error {
+        Error::Db(e, message.into())
+    }
+
+    pub fn invalid<E: error::Error + Send + 'static, M: Into<String>>(e: E, message: M) -> Error {
+        Error::Invalid(Box::new(e), message.into())
+    }
+
+    pub fn other<E: error::Error + Send + 'static, M: Into<String>>(e: E, message: M) -> Error {
+        Error::Other(Box::new(e), message.into())
+    }
+
+    pub fn auth<M: Into<String>>(message: M) -> Error {
+        Error::Auth(message.into())
+    }
+
+    pub fn forbidden<M: Into<String>>(message: M) -> Error {
+        Error::Forbidden(message.into())
+    }
+
+    pub fn not_found<M: Into<String>>(message: M) -> Error {
+        Error::NotFound(message.into())
+    }
+
+    pub fn read_only<M: Into<String>>(message: M) -> Error {
+        Error::ReadOnly(message.into())
+    }
+
+    pub fn internal<M: Into<String>>(message: M) -> Error {
+        Error::Internal(message.into())
+    }
+
+    /// The status of the response. A database connection which could not be taken from the pool in time is only a temporary problem.
+    pub fn status(&self) -> status::Status {
+        match *self {
+            Error::Db(db::Error::PoolTimeout, _) => status::ServiceUnavailable,
+            Error::Db(_, _) | Error::Other(_, _) | Error::Internal(_) => status::InternalServerError,
+            Error::Invalid(_, _) => status::BadRequest,
+            Error::Auth(_) => status::Unauthorized,
+            Error::Forbidden(_) => status::Forbidden,
+            Error::NotFound(_) => status::NotFound,
+            Error::ReadOnly(_) => status::ServiceUnavailable
+        }
+    }
+
+    /// The message for users, which does not contain any technical details.
+    pub fn message(&self) -> &str {
+        match *self {
+            Error::Db(_, ref message) |
+            Error::Invalid(_, ref message) |
+            Error::Other(_, ref message) |
+            Error::Auth(ref message) |
+            Error::Forbidden(ref message) |
+            Error::NotFound(ref message) |
+            Error::ReadOnly(ref message) |
+            Error::Internal(ref message) => message
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Db(ref e, _) => write!(f, "database error: {}", e),
+            Error::Invalid(ref e, _) => write!(f, "invalid request: {}", e),
+            Error::Other(ref e, _) => write!(f, "{}", e),
+            Error::Auth(_) => write!(f, "authentication error"),
+            Error::Forbidden(_) => write!(f, "forbidden"),
+            Error::NotFound(_) => write!(f, "not found"),
+            Error::ReadOnly(_) => write!(f, "the board is in read-only mode"),
+            Error::Internal(ref message) => write!(f, "internal server error: {}", message)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Db(_, _) => "database error",
+            Error::Invalid(_, _) => "invalid request",
+            Error::Other(ref e, _) => e.description(),
+            Error::Auth(_) => "authentication error",
+            Error::Forbidden(_) => "forbidden",
+            Error::NotFound(_) => "not found",
+            Error::ReadOnly(_) => "the board is in read-only mode",
+            Error::Internal(_) => "internal server error"
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Db(ref e, _) => Some(e),
+            Error::Invalid(ref e, _) | Error::Other(ref e, _) => Some(&**e),
+            _ => None
+        }
+    }
+}
+
+impl From<Error> for IronError {
+    fn from(err: Error) -> IronError {
+        let response = (err.status(), err.message().to_owned());
+        IronError::new(err, response)
+    }
+}
+
+/// The message of an error followed by those of its causes which are not already included in a previous message.
+pub fn causes(e: &error::Error) -> Vec<String> {
+    let mut messages = vec![e.to_string()];
+    let mut cause = e.cause();
+    while let Some(e) = cause {
+        let text = e.to_string();
+        if !messages.iter().any(|message| message.contains(&text)) {
+            messages.push(text);
+        }
+        cause = e.cause();
+    }
+    messages
+}
+
+/// An error and its causes on one line.
+pub fn describe(e: &error::Error) -> String {
+    let mut causes = causes(e).into_iter();
+    causes.next().unwrap_or_default() + &causes.map(|cause| format!(" (caused by: {})", cause)).collect::<String>()
+}
+
+/// Logs an error which did not occur while handling a request, or whose request is not at hand.
+pub fn log(e: &error::Error) {
+    logging::log_error(None, e);
+}
+
+/// Logs `e` and returns `message` in its place, for handlers which show the message next to a form.
+pub fn logged<E: error::Error, M>(e: E, message: M) -> M {
+    log(&e);
+    message
+}
@@ -0,0 +1,76 @@
+//! Renders error responses as full pages with the site layout, instead of the bare messages of `IronError`s and the router's empty 404 responses.
+//!
+//! Only responses without a content type or with plain text are replaced; their text becomes the message on the page. Handlers which render their own error pages, the JSON API and feeds are left alone. Server errors are logged with their causes (see `logging`), while the page only shows the message for users and the request id. The static pages in `error/` are still served by the web server while the Börse itself is down.
+
+use iron::{headers, status};
+use iron::middleware::AfterMiddleware;
+use iron::mime::{Mime, SubLevel, TopLevel};
+use iron::prelude::*;
+use iron::response::WriteBody;
+
+use admin::IsAdmin;
+use i18n::{CurrentLocale, Locale};
+use logging::{self, RequestId};
+use util;
+
+pub struct ErrorPages;
+
+fn is_plain(response: &Response) -> bool {
+    match response.headers.get::<headers::ContentType>() {
+        None => true,
+        Some(&headers::ContentType(Mime(TopLevel::Text, SubLevel::Plain, _))) => true,
+        Some(_) => false
+    }
+}
+
+fn default_message(error_status: status::Status, locale: Locale) -> String {
+    match error_status {
+        status::Unauthorized => locale.t("error_page.unauthorized"),
+        status::NotFound => locale.t("error_page.not_found"),
+        _ if error_status.is_server_error() => locale.t("error_page.server_error"),
+        _ => locale.t("error_page.other")
+    }
+}
+
+fn render(req: &mut Request, mut response: Response) -> Response {
+    let error_status = response.status.unwrap_or(status::InternalServerError);
+    if !(error_status.is_client_error() || error_status.is_server_error()) || !is_plain(&response) || req.url.path().first() == Some(&"api") {
+        return response;
+    }
+    let locale = req.get::<CurrentLocale>().unwrap_or_default();
+    let is_admin = req.get::<IsAdmin>().unwrap_or(false);
+    let mut body = Vec::default();
+    if let Some(mut old_body) = response.body.take() {
+        let _ = old_body.write_body(&mut body);
+    }
+    let message = String::from_utf8_lossy(&body).trim().to_owned();
+    let nav_path = format!("/{}", req.url.path().join("/"));
+    response.headers.remove::<headers::ContentLength>();
+    response.headers.set(headers::ContentType("text/html; charset=utf-8".parse().unwrap()));
+    response.body = Some(Box::new(util::localized_page(&nav_path, is_admin, locale, &format!(
+        r#"
+        <div class="alert alert-danger">{message}</div>
+        {request_id}
+        <p><a href="/">{back}</a></p>
+        "#,
+        message=if message.is_empty() { default_message(error_status, locale) } else { util::escape_html(message) },
+        request_id=req.extensions.get::<RequestId>().map_or(String::default(), |request_id| format!(r#"<p class="text-muted">{}</p>"#, locale.t_args("error_page.request_id", &[("id", &request_id[..])]))),
+        back=locale.t("back")
+    ))));
+    response
+}
+
+impl AfterMiddleware for ErrorPages {
+    fn after(&self, req: &mut Request, response: Response) -> IronResult<Response> {
+        Ok(render(req, response))
+    }
+
+    fn catch(&self, req: &mut Request, err: IronError) -> IronResult<Response> {
+        let error_status = err.response.status.unwrap_or(status::InternalServerError);
+        // read-only mode also responds with 503, but is expected
+        if error_status.is_server_error() && (error_status != status::ServiceUnavailable || err.error.cause().is_some()) {
+            logging::log_error(Some(&*req), &*err.error);
+        }
+        Ok(render(req, err.response))
+    }
+}
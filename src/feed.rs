@@ -0,0 +1,110 @@
+//! Atom feeds of the newest offers and requests.
+//!
+//! Since feed readers cannot log in, a feed requested with `?token=…` is exempt from `check_auth` and accepts any API token instead (see `api`).
+
+use chrono::prelude::*;
+
+use iron::status;
+use iron::mime::Mime;
+use iron::prelude::*;
+
+use urlencoded::UrlEncodedQuery;
+
+use api;
+use entry::{self, Entry};
+use error::Error;
+use util::{self, BASE_URL};
+
+/// The `tag:` URI authority used for entry ids. Must never change, or feed readers will show all entries again.
+const TAG_AUTHORITY: &'static str = "tag:boerse.willkommeninwoellstein.de,2017";
+const MAX_ENTRIES: usize = 50;
+
+/// Rejects requests with an invalid token. Requests without a token have already passed `check_auth`.
+fn check_token(req: &mut Request, conn: &mut ::db::Conn) -> IronResult<()> {
+    let token = match req.get_ref::<UrlEncodedQuery>() {
+        Ok(query) => query.get("token").and_then(|values| values.first()).cloned(),
+        Err(_) => None
+    };
+    if let Some(token) = token {
+        if try!(api::token_client(conn, &token).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))).is_none() {
+            return Err(Error::auth("Ungültiger Zugangsschlüssel.").into());
+        }
+    }
+    Ok(())
+}
+
+/// Entries without a creation time are dated to the epoch so that their timestamps stay stable.
+fn created_at(entry: &Entry) -> DateTime<UTC> {
+    entry.created_at.unwrap_or_else(|| UTC.timestamp(0, 0))
+}
+
+fn format_entry(entry_type: entry::Type, entry: &Entry) -> String {
+    let mut content = util::escape_html(&entry.description).replace("\n", "<br />");
+    if let Some(ref phone) = entry.phone { content.push_str(&format!("<br />Telefon: {}", util::escape_html(phone))); }
+    if let Some(ref mail) = entry.mail { content.push_str(&format!("<br />E-Mail: {}", util::escape_html(mail))); }
+    format!(
+        r#"
+    <entry>
+        <id>{tag_authority}:{url_part}/{id}</id>
+        <title>{noun} von {name}{status}</title>
+        <link href="{base_url}/{url_part}/{id}" />
+        <published>{created_at}</published>
+        <updated>{created_at}</updated>
+        <author><name>{name}</name></author>
+        <content type="html">{content}</content>
+    </entry>
+        "#,
+        tag_authority=TAG_AUTHORITY,
+        url_part=entry_type.url_part(),
+        id=entry.id,
+        noun=entry_type.german_noun(),
+        name=util::escape_html(&entry.name),
+        status=if entry.status == entry::Status::Open { String::default() } else { format!(" ({})", entry.status.german_name()) },
+        base_url=BASE_URL,
+        created_at=created_at(entry).to_rfc3339(),
+        content=util::escape_html(content)
+    )
+}
+
+fn feed(entry_types: &[entry::Type], path: &str, title: &str, req: &mut Request) -> IronResult<Response> {
+    let mut conn = try!(::db_connection());
+    try!(check_token(req, &mut conn));
+    let mut entries = Vec::default();
+    for &entry_type in entry_types {
+        for entry in try!(Entry::list(entry_type, &mut conn).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))) {
+            entries.push((entry_type, entry));
+        }
+    }
+    entries.sort_by(|&(_, ref a), &(_, ref b)| (created_at(b), b.id).cmp(&(created_at(a), a.id)));
+    entries.truncate(MAX_ENTRIES);
+    Ok(Response::with((status::Ok, "application/atom+xml; charset=utf-8".parse::<Mime>().unwrap(), format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+    <id>{tag_authority}:{path}</id>
+    <title>{title}</title>
+    <link rel="alternate" href="{base_url}/" />
+    <updated>{updated}</updated>
+    <author><name>Willkommen in Wöllstein</name></author>
+    {entries}
+</feed>
+"#,
+        tag_authority=TAG_AUTHORITY,
+        path=path,
+        title=title,
+        base_url=BASE_URL,
+        updated=entries.iter().map(|&(_, ref entry)| created_at(entry)).max().unwrap_or_else(|| UTC.timestamp(0, 0)).to_rfc3339(),
+        entries=entries.iter().map(|&(entry_type, ref entry)| format_entry(entry_type, entry)).collect::<String>()
+    ))))
+}
+
+pub fn offers_feed(req: &mut Request) -> IronResult<Response> {
+    feed(&[entry::Type::Offer], "biete", "WiW-Börse: Angebote", req)
+}
+
+pub fn requests_feed(req: &mut Request) -> IronResult<Response> {
+    feed(&[entry::Type::Request], "suche", "WiW-Börse: Anfragen", req)
+}
+
+pub fn combined_feed(req: &mut Request) -> IronResult<Response> {
+    feed(&[entry::Type::Offer, entry::Type::Request], "alle", "WiW-Börse: Angebote und Anfragen", req)
+}
@@ -0,0 +1,165 @@
+//! Translations of the public pages.
+//!
+//! The texts are kept in one flat JSON catalogue per locale in `assets/i18n`. Keys missing from a catalogue fall back to German. Values may contain HTML and `{name}` placeholders.
+//!
+//! Only the board itself is translated: the index, category, entry and submission pages. Admin pages, subscription and saved search pages, mails and feeds stay German.
+//!
+//! The locale is taken from the `lang` cookie set by the language switcher, then from the `Accept-Language` header.
+
+use std::collections::BTreeMap;
+
+use iron::{headers, status};
+use iron::modifiers::Header;
+use iron::prelude::*;
+use iron::typemap::Key;
+
+use plugin;
+
+use router::Router;
+
+use serde_json;
+
+use error::Error;
+use util;
+
+const COOKIE_NAME: &'static str = "lang";
+
+lazy_static! {
+    static ref CATALOGUES: BTreeMap<&'static str, BTreeMap<String, String>> = {
+        let mut catalogues = BTreeMap::new();
+        catalogues.insert("de", serde_json::from_str(include_str!("../assets/i18n/de.json")).unwrap());
+        catalogues.insert("en", serde_json::from_str(include_str!("../assets/i18n/en.json")).unwrap());
+        catalogues.insert("ar", serde_json::from_str(include_str!("../assets/i18n/ar.json")).unwrap());
+        catalogues.insert("fa", serde_json::from_str(include_str!("../assets/i18n/fa.json")).unwrap());
+        catalogues
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    De,
+    En,
+    Ar,
+    Fa
+}
+
+impl Locale {
+    pub fn all() -> Vec<Locale> {
+        vec![Locale::De, Locale::En, Locale::Ar, Locale::Fa]
+    }
+
+    pub fn from_code(code: &str) -> Option<Locale> {
+        Locale::all().into_iter().find(|locale| locale.code() == code)
+    }
+
+    /// The ISO 639-1 code, used in the cookie, in URLs and as the `lang` attribute.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Locale::De => "de",
+            Locale::En => "en",
+            Locale::Ar => "ar",
+            Locale::Fa => "fa"
+        }
+    }
+
+    /// The name of the language in the language itself, for the language switcher.
+    pub fn native_name(&self) -> &'static str {
+        match *self {
+            Locale::De => "Deutsch",
+            Locale::En => "English",
+            Locale::Ar => "العربية",
+            Locale::Fa => "فارسی"
+        }
+    }
+
+    pub fn is_rtl(&self) -> bool {
+        match *self {
+            Locale::Ar | Locale::Fa => true,
+            Locale::De | Locale::En => false
+        }
+    }
+
+    /// The `lang` and `dir` attributes for the `<html>` element.
+    pub fn html_attributes(&self) -> String {
+        format!(r#"lang="{}" dir="{}""#, self.code(), if self.is_rtl() { "rtl" } else { "ltr" })
+    }
+
+    /// Additional styles for the `<head>`. Bootstrap 3 has no right-to-left support of its own.
+    pub fn head(&self) -> String {
+        if self.is_rtl() { format!("<style>{}</style>", include_str!("../assets/rtl.css")) } else { String::default() }
+    }
+
+    fn lookup(&self, key: &str) -> Option<&'static str> {
+        CATALOGUES.get(self.code()).and_then(|catalogue| catalogue.get(key)).map(|text| &text[..])
+    }
+
+    /// Returns the translation for `key`, falling back to German and then to the key itself.
+    pub fn t(&self, key: &str) -> String {
+        self.lookup(key).or_else(|| Locale::De.lookup(key)).unwrap_or(key).to_owned()
+    }
+
+    /// Like `t`, with each `{name}` placeholder replaced by the corresponding value.
+    pub fn t_args(&self, key: &str, args: &[(&str, &str)]) -> String {
+        args.iter().fold(self.t(key), |text, &(name, value)| text.replace(&format!("{{{}}}", name), value))
+    }
+
+    /// Picks the supported language the client prefers most, ignoring region subtags.
+    fn from_accept_language(header: &str) -> Option<Locale> {
+        let mut languages = header.split(',').filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let language = parts.next().and_then(|tag| tag.split('-').next()).map(str::to_lowercase);
+            let quality = parts.filter_map(|param| if param.starts_with("q=") { param[2..].parse::<f32>().ok() } else { None }).next().unwrap_or(1.0);
+            language.and_then(|language| Locale::from_code(&language)).map(|locale| (locale, quality))
+        }).filter(|&(_, quality)| quality > 0.0).collect::<Vec<_>>();
+        languages.sort_by(|&(_, a), &(_, b)| b.partial_cmp(&a).unwrap());
+        languages.first().map(|&(locale, _)| locale)
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Locale {
+        Locale::De
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CurrentLocale;
+
+impl Key for CurrentLocale {
+    type Value = Locale;
+}
+
+impl<'a, 'b> plugin::Plugin<Request<'a, 'b>> for CurrentLocale {
+    type Error = ();
+
+    fn eval(req: &mut Request) -> Result<Locale, ()> {
+        if let Some(locale) = util::cookie(req, COOKIE_NAME).and_then(|code| Locale::from_code(&code)) {
+            return Ok(locale);
+        }
+        Ok(req.headers.get_raw("Accept-Language")
+            .and_then(|lines| lines.iter().filter_map(|line| String::from_utf8(line.clone()).ok()).filter_map(|line| Locale::from_accept_language(&line)).next())
+            .unwrap_or_default())
+    }
+}
+
+/// Links to all languages with the current one highlighted, shown at the top of the public pages.
+pub fn format_switcher(locale: Locale) -> String {
+    format!(
+        r#"<p style="text-align: end;"><i class="fa fa-globe"></i> {}</p>"#,
+        Locale::all().into_iter().map(|other| if other == locale {
+            format!(r#"<strong lang="{}">{}</strong>"#, other.code(), other.native_name())
+        } else {
+            format!(r#"<a href="/sprache/{0}" lang="{0}" hreflang="{0}">{1}</a>"#, other.code(), other.native_name())
+        }).collect::<Vec<_>>().join(" · ")
+    )
+}
+
+/// Remembers the chosen language in a cookie and returns to the previous page.
+pub fn set_locale(req: &mut Request) -> IronResult<Response> {
+    let err_msg = "Fehler beim Lesen der Sprache.";
+    let code = try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg))).find("lang").ok_or(Error::internal(err_msg))).to_owned();
+    let locale = try!(Locale::from_code(&code).ok_or(Error::not_found("Diese Sprache wird nicht unterstützt.")));
+    let mut response = Response::with((status::SeeOther, Header(headers::Location(util::back_location(req)))));
+    response.headers.set(headers::SetCookie(vec![format!("{}={}; Path=/; Max-Age=31536000", COOKIE_NAME, locale.code())]));
+    Ok(response)
+}
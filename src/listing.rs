@@ -0,0 +1,151 @@
+//! Pagination and sorting of the entry tables on the index and category pages.
+//!
+//! The state is kept in the query string (`sort`, `offers_page`, `requests_page`) so that links to a specific page can be shared.
+
+use iron::prelude::*;
+
+use urlencoded::UrlEncodedQuery;
+
+use entry;
+use i18n::Locale;
+
+/// The number of entries shown per page in each table.
+pub const PAGE_SIZE: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    Newest,
+    Oldest,
+    Category
+}
+
+impl Sort {
+    pub fn all() -> Vec<Sort> {
+        vec![Sort::Newest, Sort::Oldest, Sort::Category]
+    }
+
+    pub fn from_str(s: &str) -> Option<Sort> {
+        Sort::all().into_iter().find(|sort| sort.as_str() == s)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Sort::Newest => "newest",
+            Sort::Oldest => "oldest",
+            Sort::Category => "category"
+        }
+    }
+
+    pub fn name(&self, locale: Locale) -> String {
+        locale.t(&format!("sort.{}", self.as_str()))
+    }
+
+    /// The `ORDER BY` clause. Entries from before `created_at` was recorded have no creation time, so the id is used instead.
+    pub fn order_by(&self) -> &'static str {
+        match *self {
+            Sort::Newest => "id DESC",
+            Sort::Oldest => "id ASC",
+            Sort::Category => "category IS NULL, category ASC, id DESC"
+        }
+    }
+}
+
+impl Default for Sort {
+    fn default() -> Sort {
+        Sort::Newest
+    }
+}
+
+/// The sort order and the current page of both tables. Pages are numbered from 1.
+#[derive(Debug, Clone, Copy)]
+pub struct Listing {
+    pub sort: Sort,
+    offers_page: usize,
+    requests_page: usize
+}
+
+impl Default for Listing {
+    fn default() -> Listing {
+        Listing {
+            sort: Sort::default(),
+            offers_page: 1,
+            requests_page: 1
+        }
+    }
+}
+
+impl Listing {
+    /// Reads the listing state from the query string. Missing or invalid parameters fall back to the defaults.
+    pub fn from_query(req: &mut Request) -> Listing {
+        let query = match req.get_ref::<UrlEncodedQuery>() {
+            Ok(query) => query,
+            Err(_) => { return Listing::default(); }
+        };
+        let param = |key: &str| query.get(key).and_then(|values| values.first());
+        Listing {
+            sort: param("sort").and_then(|sort| Sort::from_str(sort)).unwrap_or_default(),
+            offers_page: param("offers_page").and_then(|page| page.parse().ok()).map_or(1, |page: usize| page.max(1)),
+            requests_page: param("requests_page").and_then(|page| page.parse().ok()).map_or(1, |page: usize| page.max(1))
+        }
+    }
+
+    pub fn page(&self, entry_type: entry::Type) -> usize {
+        entry_type.map(self.offers_page, self.requests_page)
+    }
+
+    pub fn with_page(&self, entry_type: entry::Type, page: usize) -> Listing {
+        match entry_type {
+            entry::Type::Offer => Listing { offers_page: page, ..*self },
+            entry::Type::Request => Listing { requests_page: page, ..*self }
+        }
+    }
+
+    /// Changing the sort order starts both tables from the first page again.
+    pub fn with_sort(&self, sort: Sort) -> Listing {
+        Listing { sort: sort, ..Listing::default() }
+    }
+
+    /// The query string for this state, including the leading `?` and escaped for use in an HTML attribute, or the empty string if everything is at its default.
+    pub fn query_string(&self) -> String {
+        let mut params = Vec::default();
+        if self.sort != Sort::default() { params.push(format!("sort={}", self.sort.as_str())); }
+        if self.offers_page != 1 { params.push(format!("offers_page={}", self.offers_page)); }
+        if self.requests_page != 1 { params.push(format!("requests_page={}", self.requests_page)); }
+        if params.is_empty() { String::default() } else { format!("?{}", params.join("&amp;")) }
+    }
+
+    /// Links for switching between the given sort orders, with the current one highlighted.
+    pub fn format_sort_links(&self, base_path: &str, sorts: &[Sort], locale: Locale) -> String {
+        format!("{}: {}", locale.t("sort.label"), sorts.iter().map(|&sort| if sort == self.sort {
+            format!("<strong>{}</strong>", sort.name(locale))
+        } else {
+            format!(r#"<a href="{}{}">{}</a>"#, base_path, self.with_sort(sort).query_string(), sort.name(locale))
+        }).collect::<Vec<_>>().join(" · "))
+    }
+
+    /// A table row with links to the previous and next page of the given table. Empty if everything fits on one page.
+    pub fn format_pager(&self, entry_type: entry::Type, base_path: &str, num_pages: usize, locale: Locale) -> String {
+        if num_pages <= 1 { return String::default(); }
+        let page = self.page(entry_type);
+        format!(
+            r#"
+<tr>
+    <td colspan="2">
+        <ul class="pager">
+            <li class="previous{previous_disabled}"><a href="{previous}">{previous_label}</a></li>
+            {page_label}
+            <li class="next{next_disabled}"><a href="{next}">{next_label}</a></li>
+        </ul>
+    </td>
+</tr>
+            "#,
+            previous_disabled=if page > 1 { "" } else { " disabled" },
+            previous=if page > 1 { format!("{}{}", base_path, self.with_page(entry_type, page - 1).query_string()) } else { "#".to_owned() },
+            previous_label=locale.t("pager.previous"),
+            page_label=locale.t_args("pager.page", &[("page", &page.to_string()), ("num_pages", &num_pages.to_string())]),
+            next_disabled=if page < num_pages { "" } else { " disabled" },
+            next=if page < num_pages { format!("{}{}", base_path, self.with_page(entry_type, page + 1).query_string()) } else { "#".to_owned() },
+            next_label=locale.t("pager.next")
+        )
+    }
+}
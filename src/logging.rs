@@ -0,0 +1,156 @@
+//! Request and error logging to stderr, as plain text or as JSON lines depending on `log_format` in the config.
+//!
+//! Every request gets a random id, which is logged with the request and its errors, sent in the `X-Request-Id` header and shown on error pages (see `error_page`), so users can report it.
+
+use std::error::Error;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use chrono::prelude::*;
+
+use iron::{headers, status};
+use iron::middleware::{AfterMiddleware, BeforeMiddleware};
+use iron::prelude::*;
+use iron::typemap::Key;
+
+use error;
+use util::{self, CONFIG};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Plain,
+    /// One JSON object per line, for log collectors.
+    Json
+}
+
+impl Default for Format {
+    fn default() -> Format {
+        Format::Plain
+    }
+}
+
+/// The id of the current request.
+pub struct RequestId;
+
+impl Key for RequestId {
+    type Value = String;
+}
+
+struct Start;
+
+impl Key for Start {
+    type Value = Instant;
+}
+
+/// Logs every request once the response has been rendered. Must be linked as the first before and the last after middleware.
+pub struct RequestLog;
+
+fn write_line(line: String) {
+    let _ = writeln!(io::stderr(), "{}", line);
+}
+
+/// The user name the request was sent with, if any.
+fn user(req: &Request) -> Option<String> {
+    match req.headers.get::<headers::Authorization<headers::Basic>>() {
+        Some(&headers::Authorization(headers::Basic { ref username, .. })) => Some(username.clone()),
+        None => None
+    }
+}
+
+fn path(req: &Request) -> String {
+    format!("/{}", req.url.path().join("/"))
+}
+
+/// Logs an error with its causes. `req` is `None` for errors outside of requests, e.g. in the background threads.
+pub fn log_error(req: Option<&Request>, e: &Error) {
+    let causes = error::causes(e);
+    let request_id = req.and_then(|req| req.extensions.get::<RequestId>().cloned());
+    write_line(match CONFIG.log_format {
+        Format::Plain => format!(
+            "{} ERROR {}{}",
+            UTC::now().format("%Y-%m-%dT%H:%M:%SZ"),
+            req.map_or(String::default(), |req| format!("[{}] {} {}: ", request_id.as_ref().map_or("-", |id| &id[..]), req.method, path(req))),
+            error::describe(e)
+        ),
+        Format::Json => json!({
+            "time": UTC::now().to_rfc3339(),
+            "level": "error",
+            "request_id": request_id,
+            "method": req.map(|req| req.method.to_string()),
+            "path": req.map(path),
+            "error": causes[0],
+            "causes": &causes[1..]
+        }).to_string()
+    });
+}
+
+/// Logs a message which does not belong to a request, e.g. from the background threads.
+pub fn log_message(level: &str, message: &str) {
+    write_line(match CONFIG.log_format {
+        Format::Plain => format!("{} {} {}", UTC::now().format("%Y-%m-%dT%H:%M:%SZ"), level.to_uppercase(), message),
+        Format::Json => json!({
+            "time": UTC::now().to_rfc3339(),
+            "level": level,
+            "message": message
+        }).to_string()
+    });
+}
+
+fn log_request(req: &Request, status: Option<status::Status>) {
+    let request_id = req.extensions.get::<RequestId>().cloned();
+    let latency_ms = req.extensions.get::<Start>().map(|start| {
+        let elapsed = start.elapsed();
+        elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64
+    });
+    let status_code = status.map(|status| status.to_u16());
+    let user = user(req);
+    write_line(match CONFIG.log_format {
+        Format::Plain => format!(
+            "{} INFO [{}] {} {} {} {}ms {}",
+            UTC::now().format("%Y-%m-%dT%H:%M:%SZ"),
+            request_id.as_ref().map_or("-", |id| &id[..]),
+            req.method,
+            path(req),
+            status_code.map_or("-".to_owned(), |code| code.to_string()),
+            latency_ms.map_or("-".to_owned(), |ms| ms.to_string()),
+            user.as_ref().map_or("-", |user| &user[..])
+        ),
+        Format::Json => json!({
+            "time": UTC::now().to_rfc3339(),
+            "level": "info",
+            "request_id": request_id,
+            "method": req.method.to_string(),
+            "path": path(req),
+            "status": status_code,
+            "latency_ms": latency_ms,
+            "user": user
+        }).to_string()
+    });
+}
+
+impl BeforeMiddleware for RequestLog {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        req.extensions.insert::<Start>(Instant::now());
+        req.extensions.insert::<RequestId>(util::random_token(12));
+        Ok(())
+    }
+}
+
+impl AfterMiddleware for RequestLog {
+    fn after(&self, req: &mut Request, mut response: Response) -> IronResult<Response> {
+        log_request(req, response.status);
+        if let Some(request_id) = req.extensions.get::<RequestId>() {
+            response.headers.set_raw("X-Request-Id", vec![request_id.clone().into_bytes()]);
+        }
+        Ok(response)
+    }
+
+    fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+        log_request(req, err.response.status);
+        if let Some(request_id) = req.extensions.get::<RequestId>() {
+            err.response.headers.set_raw("X-Request-Id", vec![request_id.clone().into_bytes()]);
+        }
+        Err(err)
+    }
+}
@@ -1,29 +1,56 @@
+extern crate base32;
 extern crate chrono;
 extern crate chrono_tz;
+extern crate crypto;
+extern crate csv;
+extern crate image;
 extern crate iron;
 #[macro_use] extern crate lazy_static;
 extern crate mysql;
+extern crate params;
 extern crate plugin;
+extern crate qrcode;
+extern crate rand;
 extern crate regex;
+extern crate rusqlite;
 #[macro_use] extern crate router;
 #[macro_use] extern crate serde_derive;
-extern crate serde_json;
+#[macro_use] extern crate serde_json;
 extern crate staticfile;
 extern crate urlencoded;
 extern crate wiw;
 
 mod admin;
+mod api;
+mod audit;
+mod db;
+mod digest;
 mod entry;
+mod error;
+mod error_page;
+mod feed;
+mod i18n;
+mod listing;
+mod logging;
+mod maintenance;
+mod migrate;
+mod notice;
+mod photo;
+mod print;
+mod saved_search;
+mod transfer;
+mod trash;
+mod two_factor;
 mod util;
 
+use std::env;
 use std::path::Path;
+use std::process;
 use std::str::FromStr;
+use std::thread;
 
-use chrono::Duration;
 use chrono::prelude::*;
 
-use chrono_tz::Europe::Berlin;
-
 use iron::status;
 use iron::prelude::*;
 use iron::mime::Mime;
@@ -36,339 +63,290 @@ use router::Router;
 
 use staticfile::Static;
 
-use urlencoded::UrlEncodedBody;
 
 use admin::IsAdmin;
-use util::{DbError, InternalError, IsTls, MY_OPTS, Nyi, check_admin_auth, check_auth};
-
-struct NoticePositions {
-    index: bool,
-    create_offer: bool,
-    create_request: bool
-}
-
-impl NoticePositions {
-    fn any(&self) -> bool {
-        self.index || self.create_offer || self.create_request
-    }
-
-    fn contains(&self, pos: Option<entry::Type>) -> bool {
-        match pos {
-            Some(entry::Type::Offer) => self.create_offer,
-            Some(entry::Type::Request) => self.create_request,
-            None => self.index
-        }
-    }
-
-    fn mysql_string(&self) -> String {
-        let mut position_strings = Vec::default();
-        if self.index { position_strings.push("index"); }
-        if self.create_offer { position_strings.push("create_offer"); }
-        if self.create_request { position_strings.push("create_request"); }
-        position_strings.join(",")
+use error::Error;
+use i18n::{CurrentLocale, Locale};
+use notice::Position;
+use util::{CONFIG, IsTls, check_admin_auth, check_admin_password, check_auth};
+
+/// Quotes a string literal for the configured database backend. MySQL interprets backslashes in literals, SQLite does not.
+fn sql_escape<S: AsRef<str>>(s: S) -> String {
+    match CONFIG.database {
+        db::Backend::Mysql => format!("\"{}\"", Regex::new("\0|\n|\r|\\|'|\"|\x1a").unwrap().replace_all(s.as_ref(), "\\$0")),
+        db::Backend::Sqlite => format!("'{}'", s.as_ref().replace('\'', "''"))
     }
 }
 
-impl<S: AsRef<str>> From<S> for NoticePositions {
-    fn from(s: S) -> NoticePositions {
-        NoticePositions {
-            index: s.as_ref().contains("index"),
-            create_offer: s.as_ref().contains("create_offer"),
-            create_request: s.as_ref().contains("create_request")
-        }
-    }
-}
-
-fn mysql_escape<S: AsRef<str>>(s: S) -> String {
-    format!("\"{}\"", Regex::new("\0|\n|\r|\\|'|\"|\x1a").unwrap().replace_all(s.as_ref(), "\\$0"))
-}
-
-fn mysql_escape_nullable<S: AsRef<str>>(s: S) -> String {
+fn sql_escape_nullable<S: AsRef<str>>(s: S) -> String {
     if s.as_ref() == "" {
         "NULL".to_owned()
     } else {
-        mysql_escape(s)
+        sql_escape(s)
     }
 }
 
-fn mysql_connection() -> IronResult<::mysql::Conn> {
-    ::mysql::Conn::new(MY_OPTS.clone()).map_err(|_| IronError::new(DbError, (status::InternalServerError, "Konnte die Datenbank nicht laden. Bitte kontaktieren Sie die Administration.")))
-}
-
-fn format_notices(entry_type: Option<entry::Type>, conn: &mut ::mysql::Conn, is_admin: bool) -> Result<String, ::mysql::Error> {
-    let entries = try!(conn.query("SELECT * FROM notices"));
-    Ok(entries.filter_map(|row| match row {
-        Ok(values) => {
-            if NoticePositions::from(String::from_value(values[1].clone())).contains(entry_type) {
-                Some(format!(
-                    r#"<div class="alert alert-info">{edit_buttons}{text}</div>"#,
-                    text=String::from_value(values[2].clone()),
-                    edit_buttons=if is_admin { format!(r#"<div style="float: right;"><a href="/notiz/{}/loeschen" class="btn btn-danger"><i class="fa fa-trash-o"></i></a></div>"#, i32::from_value(values[0].clone())) } else { "".to_owned() }
-                ))
-            } else {
-                None
-            }
-        }
-        Err(_) => Some(r#"<div class="alert alert-danger"><strong>Fehlerhafte Nachricht.</strong></div>"#.to_owned())
-    }).collect())
+fn db_connection() -> IronResult<db::PooledConn> {
+    db::connection().map_err(|e| {
+        let message = match e {
+            db::Error::PoolTimeout => "Die Börse ist gerade überlastet. Bitte versuchen Sie es in einer Minute erneut.",
+            _ => "Konnte die Datenbank nicht laden. Bitte kontaktieren Sie die Administration."
+        };
+        Error::db(e, message).into()
+    })
 }
 
-fn format_reboot_notice(utc_time: DateTime<UTC>, upgrade: bool) -> String {
-    let berlin_time = utc_time.with_timezone(&Berlin);
-    let now = Local::now().with_timezone(&Berlin);
-    if upgrade {
-        format!(
-            r#"<div class="alert alert-warning">Die Börse wird ab {date} {start_time} Uhr über einen längeren Zeitraum zeitweise nicht erreichbar sein. Bitte ab sofort bis diese Nachricht gelöscht wird keine Angebote/Anfragen einreichen, diese gehen sonst möglicherweise verloren.</div>"#,
-            date=if now.date() == berlin_time.date() { format!("heute ({})", berlin_time.format("%d.%m.%Y")) } else { format!("{}", berlin_time.format("%d.%m.%Y")) },
-            start_time=berlin_time.format("%H:%M")
-        )
-    } else {
-        let berlin_end_time = berlin_time + Duration::minutes(15);
-        if berlin_end_time.date() != berlin_time.date() {
-            return format!(
-                r#"<div class="alert alert-warning">Die Börse wird zwischen {start_time} Uhr und {end_time} Uhr zeitweise nicht erreichbar sein. In diesem Zeitraum bitte keine Angebote/Anfragen einreichen, diese gehen sonst verloren.</div>"#,
-                start_time=berlin_time.format("%d.%m.%Y %H:%M"),
-                end_time=berlin_end_time.format("%d.%m.%Y %H:%M")
-            );
-        }
-        format!(
-            r#"<div class="alert alert-warning">Die Börse wird {date} zwischen {start_time} Uhr und {end_time} Uhr zeitweise nicht erreichbar sein. In diesem Zeitraum bitte keine Angebote/Anfragen einreichen, diese gehen sonst verloren.</div>"#,
-            date=if now.date() == berlin_time.date() { format!("heute ({})", berlin_time.format("%d.%m.%Y")) } else { format!("am {}", berlin_time.format("%d.%m.%Y")) },
-            start_time=berlin_time.format("%H:%M"),
-            end_time=berlin_end_time.format("%H:%M")
-        )
+/// A label for entries which are no longer open.
+fn format_status(entry_status: entry::Status, locale: Locale) -> String {
+    match entry_status {
+        entry::Status::Open => String::default(),
+        entry::Status::Reserved => format!(r#" <span class="label label-warning">{}</span>"#, entry_status.name(locale)),
+        entry::Status::Closed => format!(r#" <span class="label label-default">{}</span>"#, entry_status.name(locale))
     }
 }
 
-fn format_entries(entry_type: entry::Type, conn: &mut ::mysql::Conn, is_admin: bool) -> Result<String, ::mysql::Error> {
-    let entries = try!(conn.query(format!("SELECT * FROM {}", entry_type.table()))).collect::<Vec<_>>();
+/// Renders one page of the entries of a table, sorted and paginated according to `listing`. `base_path` is the page the pagination links point to.
+fn format_entries(entry_type: entry::Type, category: Option<&str>, listing: &listing::Listing, base_path: &str, locale: Locale, conn: &mut ::db::Conn, is_admin: bool) -> Result<String, ::db::Error> {
+    let condition = format!(
+        "deleted_at IS NULL{}",
+        category.map_or(String::default(), |category| format!(" AND category={}", sql_escape(category)))
+    );
+    let mut num_entries = 0;
+    for row in try!(conn.query(format!("SELECT COUNT(*) FROM {} WHERE {}", entry_type.table(), condition))) {
+        num_entries = usize::from_value(try!(row)[0].clone());
+    }
+    let num_pages = (num_entries + listing::PAGE_SIZE - 1) / listing::PAGE_SIZE;
+    let listing = listing.with_page(entry_type, listing.page(entry_type).min(num_pages.max(1)));
+    let entries = try!(conn.query(format!(
+        "SELECT name, description, phone, mail, id, status FROM {} WHERE {} ORDER BY {} LIMIT {} OFFSET {}",
+        entry_type.table(),
+        condition,
+        listing.sort.order_by(),
+        listing::PAGE_SIZE,
+        (listing.page(entry_type) - 1) * listing::PAGE_SIZE
+    ))).collect::<Vec<_>>();
+    let ids = entries.iter().filter_map(|row| row.as_ref().ok().and_then(|values| Option::<i32>::from_value(values[4].clone()))).collect::<Vec<_>>();
+    let photos = try!(photo::files_by_entry(entry_type, &ids, conn));
+    let translations = try!(entry::translated_descriptions(entry_type, &ids, locale, conn));
     Ok(if entries.len() > 0 {
         entries.into_iter().map(|row| match row {
             Ok(values) => {
-                // name, description, phone, mail, id
+                // name, description, phone, mail, id, status
                 format!(
                     r#"
 <tr>
-    <td>{name}{mail}{phone}</td>
-    <td>{edit_buttons}{description}</td>
+    <td>{name}{status}{mail}{phone}</td>
+    <td>{edit_buttons}{description}{thumbnails}</td>
 </tr>
                     "#,
-                    name=String::from_value(values[0].clone()),
-                    description=String::from_value(values[1].clone()).replace("\n", "<br />"),
-                    phone=match Option::<String>::from_value(values[2].clone()) { Some(phone) => format!(r#"<br /><a href="tel:{0}">{0}</a>"#, phone), None => "".to_owned() },
-                    mail=match Option::<String>::from_value(values[3].clone()) { Some(mail) => format!(r#"<br /><a href="mailto:{0}">{0}</a>"#, mail), None => "".to_owned() },
+                    name=match Option::<i32>::from_value(values[4].clone()) { Some(i) => format!(r#"<a href="/{}/{}">{}</a>"#, entry_type.url_part(), i, util::escape_html(String::from_value(values[0].clone()))), None => util::escape_html(String::from_value(values[0].clone())) },
+                    status=format_status(entry::Status::from_str(&String::from_value(values[5].clone())).unwrap_or_default(), locale),
+                    description=match Option::<i32>::from_value(values[4].clone()).and_then(|i| translations.get(&i)) {
+                        Some(translation) => format!(r#"<span lang="{}">{}</span>"#, locale.code(), util::escape_html(translation).replace("\n", "<br />")),
+                        None => format!(r#"<span dir="auto">{}</span>"#, util::escape_html(String::from_value(values[1].clone())).replace("\n", "<br />"))
+                    },
+                    thumbnails=match Option::<i32>::from_value(values[4].clone()).and_then(|i| photos.get(&i)) { Some(files) => format!("<br />{}", photo::format_thumbnails(files)), None => "".to_owned() },
+                    phone=match Option::<String>::from_value(values[2].clone()) { Some(phone) => format!(r#"<br /><a href="tel:{0}">{0}</a>"#, util::escape_html(phone)), None => "".to_owned() },
+                    mail=match Option::<String>::from_value(values[3].clone()) { Some(mail) => format!(r#"<br /><a href="mailto:{0}">{0}</a>"#, util::escape_html(mail)), None => "".to_owned() },
                     edit_buttons=if is_admin { match Option::<i32>::from_value(values[4].clone()) { Some(i) => format!(r#"<div style="float: right;"><a href="/{}/{}/loeschen" class="btn btn-danger"><i class="fa fa-trash-o"></i></a></div>"#, entry_type.url_part(), i), None => "".to_owned() } } else { "".to_owned() }
                 )
             }
             Err(_) => format!(r#"
 <tr>
     <td></td>
-    <td style="color: gray; font-style: italic;">{}.</td>
+    <td style="color: gray; font-style: italic;">{}</td>
 </tr>
-            "#, entry_type.map("Fehlerhaftes Angebot", "Fehlerhafte Anfrage"))
-        }).fold("".to_string(), |text, row| text + &row)
+            "#, entry_type.text(locale, "invalid"))
+        }).fold("".to_string(), |text, row| text + &row) + &listing.format_pager(entry_type, base_path, num_pages, locale)
     } else {
         format!(r#"
 <tr>
     <td></td>
-    <td style="color: gray; font-style: italic;">Keine aktiven {}.</td>
+    <td style="color: gray; font-style: italic;">{}</td>
 </tr>
-        "#, entry_type.german_plural())
+        "#, entry_type.text(locale, "none"))
     })
 }
 
 fn index(req: &mut Request) -> IronResult<Response> {
     let is_admin = req.get::<IsAdmin>().unwrap_or(false);
-    let mut conn = try!(mysql_connection());
-    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), format!(
+    let locale = req.get::<CurrentLocale>().unwrap_or_default();
+    let listing = listing::Listing::from_query(req);
+    let mut conn = try!(db_connection());
+    let read_only = try!(maintenance::read_only(&mut conn).map_err(|e| Error::db(e, locale.t("error.db")))).is_some();
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::localized_page("/", is_admin, locale, &format!(
         r#"
-<!DOCTYPE html>
-<html>
-    <head>
-        {header}
-    </head>
-    <body>
-        {nav}
-        <div class="container" style="position: relative; top: 71px;">
-            {reboot_notice}
-            {tls_notice}
-            {notices}
-            <div class="panel panel-default">
-                {intro}
+        {reboot_notice}
+        {tls_notice}
+        {notices}
+        <div class="panel panel-default">
+            <div class="panel-heading">
+                <h3 class="panel-title">{intro_title}</h3>
             </div>
-            <div class="row">
-                <div class="col-lg-6 col-sm-12">
-                    <h2>Ich habe/biete <a href="/biete/neu" class="btn btn-success"><i class="fa fa-plus"></i> Angebot hinzufügen</a></h2>
-                    <table class="table table-responsive">
-                        <thead>
-                            <tr>
-                                <th>Eingestellt von</th>
-                                <th>Beschreibung</th>
-                            </tr>
-                        </thead>
-                        <tbody>
-                            {offers}
-                        </tbody>
-                    </table>
-                </div>
-                <div class="col-lg-6 col-sm-12">
-                    <h2>Ich suche <a href="/suche/neu" class="btn btn-success"><i class="fa fa-plus"></i> Anfrage hinzufügen</a></h2>
-                    <table class="table table-responsive">
-                        <thead>
-                            <tr>
-                                <th>Eingestellt von</th>
-                                <th>Beschreibung</th>
-                            </tr>
-                        </thead>
-                        <tbody>
-                            {requests}
-                        </tbody>
-                    </table>
-                </div>
+            <div class="panel-body">
+                <img src="//static.willkommeninwoellstein.de/logo.png" id="logo-boerse" />
+                {intro_body}
+            </div>
+        </div>
+        <p><a href="/abo/neu"><i class="fa fa-envelope-o"></i> {digest_link}</a> · <a href="/suchauftrag/neu"><i class="fa fa-bell-o"></i> {saved_search_link}</a> · <a href="/druck"><i class="fa fa-print"></i> {print_link}</a></p>
+        <p>{categories}: {category_links}</p>
+        <p>{sort_links}</p>
+        <div class="row">
+            <div class="col-lg-6 col-sm-12">
+                <h2>{offer_heading}{new_offer_button}</h2>
+                <table class="table table-responsive">
+                    <thead>
+                        <tr>
+                            <th>{name_header}</th>
+                            <th>{description_header}</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {offers}
+                    </tbody>
+                </table>
+            </div>
+            <div class="col-lg-6 col-sm-12">
+                <h2>{request_heading}{new_request_button}</h2>
+                <table class="table table-responsive">
+                    <thead>
+                        <tr>
+                            <th>{name_header}</th>
+                            <th>{description_header}</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {requests}
+                    </tbody>
+                </table>
             </div>
         </div>
-        {footer}
-    </body>
-</html>
         "#,
-        header=include_str!("../assets/header.html"),
-        intro=include_str!("../assets/intro.html"),
-        nav=wiw::nav("boerse", "/", is_admin),
-        reboot_notice=if let Some((utc_time, upgrade)) = util::reboot_time() { format_reboot_notice(utc_time, upgrade) } else { String::default() },
-        tls_notice=if try!(req.get::<IsTls>()) { "" } else { r#"<div class="alert alert-warning">Sie benutzen die unverschlüsselte Version der Börse. <a href="https://boerse.willkommeninwoellstein.de/">Zur verschlüsselten Version wechseln</a></div>"# },
-        notices=try!(format_notices(None, &mut conn, is_admin).map_err(|e| IronError::new(e, (status::InternalServerError, "Fehler beim Zugriff auf die Datenbank.")))),
-        offers=try!(format_entries(entry::Type::Offer, &mut conn, is_admin).map_err(|e| IronError::new(e, (status::InternalServerError, "Fehler beim Zugriff auf die Datenbank.")))),
-        requests=try!(format_entries(entry::Type::Request, &mut conn, is_admin).map_err(|e| IronError::new(e, (status::InternalServerError, "Fehler beim Zugriff auf die Datenbank.")))),
-        footer=include_str!("../assets/footer.html")
-    ))))
+        reboot_notice=try!(maintenance::format_notices(&mut conn).map_err(|e| Error::db(e, locale.t("error.db")))),
+        tls_notice=if try!(req.get::<IsTls>()) { String::default() } else { format!(r#"<div class="alert alert-warning">{}</div>"#, locale.t("index.tls_notice")) },
+        notices=try!(notice::format_notices(&Position::Index, req, &mut conn, is_admin).map_err(|e| Error::db(e, locale.t("error.db")))),
+        intro_title=locale.t("intro.title"),
+        intro_body=locale.t("intro.body"),
+        digest_link=locale.t("index.digest_link"),
+        saved_search_link=locale.t("index.saved_search_link"),
+        print_link=locale.t("index.print_link"),
+        categories=locale.t("index.categories"),
+        category_links=entry::CATEGORIES.iter().map(|&(slug, _)| format!(r#"<a href="/kategorie/{}">{}</a>"#, slug, locale.t(&format!("category.{}", slug)))).collect::<Vec<_>>().join(" · "),
+        sort_links=listing.format_sort_links("/", &listing::Sort::all(), locale),
+        offer_heading=entry::Type::Offer.text(locale, "heading"),
+        request_heading=entry::Type::Request.text(locale, "heading"),
+        name_header=locale.t("field.name"),
+        description_header=locale.t("field.description"),
+        new_offer_button=if read_only { String::default() } else { format!(r#" <a href="/biete/neu" class="btn btn-success"><i class="fa fa-plus"></i> {}</a>"#, entry::Type::Offer.text(locale, "add")) },
+        new_request_button=if read_only { String::default() } else { format!(r#" <a href="/suche/neu" class="btn btn-success"><i class="fa fa-plus"></i> {}</a>"#, entry::Type::Request.text(locale, "add")) },
+        offers=try!(format_entries(entry::Type::Offer, None, &listing, "/", locale, &mut conn, is_admin).map_err(|e| Error::db(e, locale.t("error.db")))),
+        requests=try!(format_entries(entry::Type::Request, None, &listing, "/", locale, &mut conn, is_admin).map_err(|e| Error::db(e, locale.t("error.db"))))
+    )))))
 }
 
-fn new_notice_page_inner(form_error: Option<&'static str>, req: &mut Request) -> IronResult<Response> {
+/// Shows the form for a new entry. `form_error` is a key of the translation catalogues.
+fn new_entry_page(entry_type: entry::Type, form_error: Option<&'static str>, req: &mut Request) -> IronResult<Response> {
     let is_admin = req.get::<IsAdmin>().unwrap_or(false);
-    Ok(Response::with((if form_error.is_some() { status::BadRequest } else { status::Ok }, "text/html".parse::<Mime>().unwrap(), format!(
+    let locale = req.get::<CurrentLocale>().unwrap_or_default();
+    let mut conn = try!(db_connection());
+    Ok(Response::with((if form_error.is_some() { status::BadRequest } else { status::Ok }, "text/html".parse::<Mime>().unwrap(), util::localized_page(&format!("/{}/neu", entry_type.url_part()), is_admin, locale, &format!(
         r#"
-<!DOCTYPE html>
-<html>
-<head>
-    {header}
-</head>
-<body>
-    {nav}
-    <div class="container" style="position: relative; top: 71px;">
         {error_message}
-        <h2>Neue Notiz</h2>
-        <form class="form-horizontal" action="/notiz/neu" method="post" enctype="application/x-www-form-urlencoded">
+        {reboot_notice}
+        {notices}
+        <h2>{title}</h2>
+        <form class="form-horizontal" action="/{url_part}/neu" method="post" enctype="multipart/form-data">
             <div class="form-group">
-                <label for="text" class="col-sm-2 control-label">Text</label>
+                <label for="name" class="col-sm-2 control-label">{name_label}</label>
                 <div class="col-sm-10">
-                    <input type="text" class="form-control" name="text" id="text" placeholder="Inhalt der Notiz" />
+                    <input type="text" class="form-control" name="name" id="name" placeholder="{name_placeholder}" />
                 </div>
             </div>
             <div class="form-group">
-                <label for="position" class="col-sm-2 control-label">Anzeigen auf</label>
+                <label for="mail" class="col-sm-2 control-label">{mail_label}</label>
                 <div class="col-sm-10">
-                    <div class="checkbox">
-                        <label>
-                            <input type="checkbox" name="position-index" /> Börsen-Hauptseite
-                        </label>
-                    </div>
-                    <div class="checkbox">
-                        <label>
-                            <input type="checkbox" name="position-create-offer" /> Formular „neues Angebot“
-                        </label>
-                    </div>
-                    <div class="checkbox">
-                        <label>
-                            <input type="checkbox" name="position-create-request" /> Formular „neue Anfrage“
-                        </label>
-                    </div>
+                    <input type="email" class="form-control" name="mail" id="mail" placeholder="{mail_placeholder}" />
                 </div>
             </div>
             <div class="form-group">
-                <div class="col-sm-offset-2 col-sm-10">
-                    <a href="/" style="float: right;" class="btn btn-danger">Abbrechen</a>
-                    <button type="submit" class="btn btn-primary">Notiz veröffentlichen</button>
+                <label for="phone" class="col-sm-2 control-label">{phone_label}</label>
+                <div class="col-sm-10">
+                    <input type="tel" class="form-control" name="phone" id="phone" placeholder="{phone_placeholder}" />
+                    <p class="help-block">{contact_help}</p>
                 </div>
             </div>
-        </form>
-    </div>
-    {footer}
-</body>
-</html>
-        "#,
-        error_message=if let Some(msg) = form_error { format!(r#"<div class="alert alert-danger"><strong>{}</strong> Bitte füllen Sie das Formular erneut aus.</div>"#, msg) } else { String::default() },
-        header=include_str!("../assets/header.html"),
-        nav=wiw::nav("boerse", "/notiz/neu", is_admin),
-        footer=include_str!("../assets/footer.html")
-    ))))
-}
-
-fn new_entry_page(entry_type: entry::Type, form_error: Option<&'static str>, req: &mut Request) -> IronResult<Response> {
-    let is_admin = req.get::<IsAdmin>().unwrap_or(false);
-    let mut conn = try!(mysql_connection());
-    Ok(Response::with((if form_error.is_some() { status::BadRequest } else { status::Ok }, "text/html".parse::<Mime>().unwrap(), format!(
-        r#"
-<!DOCTYPE html>
-<html>
-<head>
-    {header}
-</head>
-<body>
-    {nav}
-    <div class="container" style="position: relative; top: 71px;">
-        {error_message}
-        {reboot_notice}
-        {notices}
-        <h2>{title}</h2>
-        <form class="form-horizontal" action="/{url_part}/neu" method="post" enctype="application/x-www-form-urlencoded">
             <div class="form-group">
-                <label for="name" class="col-sm-2 control-label">Eingestellt von</label>
+                <label for="category" class="col-sm-2 control-label">{category_label}</label>
                 <div class="col-sm-10">
-                    <input type="text" class="form-control" name="name" id="name" placeholder="Ihr Name" />
+                    <select class="form-control" name="category" id="category">
+                        <option value="">{no_category}</option>
+                        {category_options}
+                    </select>
                 </div>
             </div>
             <div class="form-group">
-                <label for="mail" class="col-sm-2 control-label">E-Mail</label>
+                <label for="description" class="col-sm-2 control-label">{description_label}</label>
                 <div class="col-sm-10">
-                    <input type="email" class="form-control" name="mail" id="mail" placeholder="Eine Mailadresse zur Kontaktaufnahme. Wird in der Liste angezeigt." />
+                    <textarea rows="3" class="form-control" name="description" id="description" placeholder="{description_placeholder}"></textarea>
                 </div>
             </div>
             <div class="form-group">
-                <label for="phone" class="col-sm-2 control-label">Telefon</label>
-                <div class="col-sm-10">
-                    <input type="tel" class="form-control" name="phone" id="phone" placeholder="Eine Telefonnummer zur Kontaktaufnahme. Wird in der Liste angezeigt." />
-                    <p class="help-block">Bitte geben Sie Mailadresse und/oder Telefonnummer an.</p>
+                <div class="col-sm-offset-2 col-sm-10">
+                    <p class="help-block">{translations_help}</p>
                 </div>
             </div>
+            {translation_fields}
             <div class="form-group">
-                <label for="description" class="col-sm-2 control-label">Beschreibung</label>
+                <label for="images" class="col-sm-2 control-label">{images_label}</label>
                 <div class="col-sm-10">
-                    <textarea rows="3" class="form-control" name="description" id="description" placeholder="Beschreiben Sie {article} {entry_type} hier."></textarea>
+                    <input type="file" name="images[]" id="images" accept="image/*" multiple />
+                    <p class="help-block">{images_help}</p>
                 </div>
             </div>
             <div class="form-group">
                 <div class="col-sm-offset-2 col-sm-10">
-                    <a href="/" style="float: right;" class="btn btn-danger">Abbrechen</a>
-                    <button type="submit" class="btn btn-primary">{entry_type} einreichen</button>
+                    <a href="/" style="float: right;" class="btn btn-danger">{cancel}</a>
+                    <button type="submit" class="btn btn-primary">{submit}</button>
                 </div>
             </div>
         </form>
-    </div>
-    {footer}
-</body>
-</html>
         "#,
-        error_message=if let Some(msg) = form_error { format!(r#"<div class="alert alert-danger"><strong>{}</strong> Bitte füllen Sie das Formular erneut aus.</div>"#, msg) } else { String::default() },
-        header=include_str!("../assets/header.html"),
-        nav=wiw::nav("boerse", &format!("/{}/neu", entry_type.url_part())[..], is_admin),
-        reboot_notice=if let Some((utc_time, upgrade)) = util::reboot_time() { format_reboot_notice(utc_time, upgrade) } else { String::default() },
-        notices=try!(format_notices(Some(entry_type), &mut conn, is_admin).map_err(|e| IronError::new(e, (status::InternalServerError, "Fehler beim Zugriff auf die Datenbank.")))),
-        title=entry_type.map("Neues Angebot", "Neue Anfrage"),
+        error_message=if let Some(msg) = form_error { format!(r#"<div class="alert alert-danger"><strong>{}</strong> {}</div>"#, locale.t(msg), locale.t("form.retry")) } else { String::default() },
+        reboot_notice=try!(maintenance::format_notices(&mut conn).map_err(|e| Error::db(e, locale.t("error.db")))),
+        notices=try!(notice::format_notices(&entry_type.map(Position::CreateOffer, Position::CreateRequest), req, &mut conn, is_admin).map_err(|e| Error::db(e, locale.t("error.db")))),
+        title=entry_type.text(locale, "new_title"),
         url_part=entry_type.url_part(),
-        article=entry_type.german_article(),
-        entry_type=entry_type.german_noun(),
-        footer=include_str!("../assets/footer.html")
-    ))))
+        name_label=locale.t("field.name"),
+        name_placeholder=locale.t("form.name_placeholder"),
+        mail_label=locale.t("field.mail"),
+        mail_placeholder=locale.t("form.mail_placeholder"),
+        phone_label=locale.t("field.phone"),
+        phone_placeholder=locale.t("form.phone_placeholder"),
+        contact_help=locale.t("form.contact_help"),
+        category_label=locale.t("field.category"),
+        no_category=locale.t("form.no_category"),
+        category_options=entry::CATEGORIES.iter().map(|&(slug, _)| format!(r#"<option value="{}">{}</option>"#, slug, locale.t(&format!("category.{}", slug)))).collect::<String>(),
+        description_label=locale.t("field.description"),
+        description_placeholder=entry_type.text(locale, "description_placeholder"),
+        translations_help=locale.t("form.translations_help"),
+        translation_fields=Locale::all().into_iter().map(|other| format!(
+            r#"
+            <div class="form-group">
+                <label for="description_{code}" class="col-sm-2 control-label">{label}</label>
+                <div class="col-sm-10">
+                    <textarea rows="2" class="form-control" name="description_{code}" id="description_{code}" lang="{code}" dir="{dir}"></textarea>
+                </div>
+            </div>
+            "#,
+            code=other.code(),
+            label=locale.t_args("form.translation_label", &[("language", other.native_name())]),
+            dir=if other.is_rtl() { "rtl" } else { "ltr" }
+        )).collect::<String>(),
+        images_label=locale.t("field.images"),
+        images_help=locale.t_args("form.images_help", &[("max_photos", &photo::MAX_PHOTOS.to_string())]),
+        cancel=locale.t("form.cancel"),
+        submit=entry_type.text(locale, "submit")
+    )))))
 }
 
 fn new_offer_page(req: &mut Request) -> IronResult<Response> {
@@ -379,57 +357,202 @@ fn new_request_page(req: &mut Request) -> IronResult<Response> {
     new_entry_page(entry::Type::Request, None, req)
 }
 
-fn new_notice_page(req: &mut Request) -> IronResult<Response> {
-    new_notice_page_inner(None, req)
+fn entry_page(entry_type: entry::Type, req: &mut Request) -> IronResult<Response> {
+    let is_admin = req.get::<IsAdmin>().unwrap_or(false);
+    let locale = req.get::<CurrentLocale>().unwrap_or_default();
+    let mut conn = try!(db_connection());
+    let err_msg = entry_type.text(locale, "id_error");
+    let id_str = try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg.clone()))).find("id").ok_or(Error::internal(err_msg.clone())));
+    let id = try!(i32::from_str(id_str).map_err(|e| Error::invalid(e, locale.t_args(&format!("{}.id_not_a_number", entry_type.key()), &[("id", &format!("{:?}", id_str))]))));
+    let entry = try!(entry::Entry::load(entry_type, id, &mut conn).map_err(|e| Error::db(e, locale.t("error.db"))));
+    let entry::Entry { name, description, phone, mail, status: entry_status, category, .. } = try!(entry.ok_or(Error::not_found(entry_type.text(locale, "not_found"))));
+    let translation = try!(entry::translated_descriptions(entry_type, &[id], locale, &mut conn).map_err(|e| Error::db(e, locale.t("error.db")))).remove(&id);
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::localized_page(&format!("/{}/{}", entry_type.url_part(), id), is_admin, locale, &format!(
+        r#"
+        {notices}
+        <h2>{title}{status_label}{edit_buttons}</h2>
+        <p class="lead">{description}</p>
+        {original}
+        {photos}
+        <dl class="dl-horizontal">
+            {category}
+            {mail}
+            {phone}
+        </dl>
+        <p><a href="/">{back}</a></p>
+        "#,
+        notices=try!(notice::format_notices(&Position::Detail, req, &mut conn, is_admin).map_err(|e| Error::db(e, locale.t("error.db")))) + &match category {
+            Some(ref category) => try!(notice::format_notices(&Position::Category(category.clone()), req, &mut conn, is_admin).map_err(|e| Error::db(e, locale.t("error.db")))),
+            None => String::default()
+        },
+        title=locale.t_args(&format!("{}.by", entry_type.key()), &[("name", &util::escape_html(name))]),
+        category=match category.as_ref().and_then(|category| entry::category_label(category, locale).map(|name| (category, name))) {
+            Some((slug, name)) => format!(r#"<dt>{}</dt><dd><a href="/kategorie/{}">{}</a></dd>"#, locale.t("field.category"), slug, name),
+            None => String::default()
+        },
+        status_label=format_status(entry_status, locale),
+        edit_buttons=if is_admin { format!(r#" <a href="/{}/{}/loeschen" class="btn btn-danger"><i class="fa fa-trash-o"></i></a>"#, entry_type.url_part(), id) } else { String::default() },
+        description=match translation {
+            Some(ref translation) => format!(r#"<span lang="{}">{}</span>"#, locale.code(), util::escape_html(translation).replace("\n", "<br />")),
+            None => format!(r#"<span dir="auto">{}</span>"#, util::escape_html(&description).replace("\n", "<br />"))
+        },
+        original=if translation.is_some() { format!(r#"<p class="text-muted">{}: <span dir="auto">{}</span></p>"#, locale.t("entry.original"), util::escape_html(&description).replace("\n", "<br />")) } else { String::default() },
+        photos=match try!(photo::files(entry_type, id, &mut conn).map_err(|e| Error::db(e, locale.t("error.db")))) {
+            ref files if files.is_empty() => String::default(),
+            files => format!("<p>{}</p>", photo::format_photos(&files))
+        },
+        mail=match mail { Some(mail) => format!(r#"<dt>{}</dt><dd><a href="mailto:{1}">{1}</a></dd>"#, locale.t("field.mail"), util::escape_html(mail)), None => String::default() },
+        phone=match phone { Some(phone) => format!(r#"<dt>{}</dt><dd><a href="tel:{1}">{1}</a></dd>"#, locale.t("field.phone"), util::escape_html(phone)), None => String::default() },
+        back=locale.t("back")
+    )))))
 }
 
-fn add_notice_inner(req: &mut Request) -> Result<Response, &'static str> {
-    let form_data = try!(req.get_ref::<UrlEncodedBody>().map_err(|_| "Fehlender Formularinhalt."));
-    let text = mysql_escape_nullable(&form_data["text"][0]);
-    if text == "NULL" { return Err("Fehlender Text."); }
-    let positions = NoticePositions {
-        index: form_data.contains_key("position-index"),
-        create_offer: form_data.contains_key("position-create-offer"),
-        create_request: form_data.contains_key("position-create-request")
-    };
-    if !positions.any() { return Err("Bitte geben Sie mindestens eine Position an, wo die Notiz angezeigt werden soll."); }
-    let position_str = mysql_escape_nullable(positions.mysql_string());
-    let mut conn = try!(mysql_connection().map_err(|_| "Fehler beim Zugriff auf die Datenbank."));
-    try!(conn.query(format!("INSERT INTO notices (position, text) VALUES ({}, {})", position_str, text)).map_err(|_| "Fehler beim Zugriff auf die Datenbank."));
-    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(),
+fn offer_page(req: &mut Request) -> IronResult<Response> {
+    entry_page(entry::Type::Offer, req)
+}
+
+fn request_page(req: &mut Request) -> IronResult<Response> {
+    entry_page(entry::Type::Request, req)
+}
+
+fn category_page(req: &mut Request) -> IronResult<Response> {
+    let is_admin = req.get::<IsAdmin>().unwrap_or(false);
+    let locale = req.get::<CurrentLocale>().unwrap_or_default();
+    let listing = listing::Listing::from_query(req);
+    let mut conn = try!(db_connection());
+    let err_msg = locale.t("category.read_error");
+    let slug = try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg.clone()))).find("slug").ok_or(Error::internal(err_msg.clone()))).to_owned();
+    let name = try!(entry::category_label(&slug, locale).ok_or(Error::not_found(locale.t("category.not_found"))));
+    let base_path = format!("/kategorie/{}", slug);
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::localized_page(&base_path, is_admin, locale, &format!(
         r#"
-<!DOCTYPE html>
-<html>
-    <body>
-        <p>Die Notiz wurde veröffentlicht.</p>
-    </body>
-</html>
-        "#
-    ))) //TODO full HTML page with link to indexs
+        {notices}
+        <h2>{name} <a href="/suchauftrag/neu?category={slug}" class="btn btn-default"><i class="fa fa-bell-o"></i> {notify}</a></h2>
+        <p>{sort_links}</p>
+        <div class="row">
+            <div class="col-lg-6 col-sm-12">
+                <h3>{offer_heading}</h3>
+                <table class="table table-responsive">
+                    <thead>
+                        <tr>
+                            <th>{name_header}</th>
+                            <th>{description_header}</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {offers}
+                    </tbody>
+                </table>
+            </div>
+            <div class="col-lg-6 col-sm-12">
+                <h3>{request_heading}</h3>
+                <table class="table table-responsive">
+                    <thead>
+                        <tr>
+                            <th>{name_header}</th>
+                            <th>{description_header}</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {requests}
+                    </tbody>
+                </table>
+            </div>
+        </div>
+        <p><a href="/">{back}</a></p>
+        "#,
+        notices=try!(notice::format_notices(&Position::Category(slug.clone()), req, &mut conn, is_admin).map_err(|e| Error::db(e, locale.t("error.db")))),
+        name=name,
+        slug=slug,
+        notify=locale.t("category.notify"),
+        sort_links=listing.format_sort_links(&base_path, &[listing::Sort::Newest, listing::Sort::Oldest], locale),
+        offer_heading=entry::Type::Offer.text(locale, "heading"),
+        request_heading=entry::Type::Request.text(locale, "heading"),
+        name_header=locale.t("field.name"),
+        description_header=locale.t("field.description"),
+        offers=try!(format_entries(entry::Type::Offer, Some(&slug), &listing, &base_path, locale, &mut conn, is_admin).map_err(|e| Error::db(e, locale.t("error.db")))),
+        requests=try!(format_entries(entry::Type::Request, Some(&slug), &listing, &base_path, locale, &mut conn, is_admin).map_err(|e| Error::db(e, locale.t("error.db")))),
+        back=locale.t("back")
+    )))))
 }
 
+/// Returns a text field of a multipart form, or the empty string if it is missing.
+fn param_str<'a>(form_data: &'a params::Map, key: &str) -> &'a str {
+    match form_data.find(&[key]) {
+        Some(&params::Value::String(ref value)) => value,
+        _ => ""
+    }
+}
+
+/// Inserts an entry with its translations and photos, within a transaction started by the caller. `values` are the escaped values of the columns `name`, `description`, `phone`, `mail`, `created_at` and `category`. Returns the id and the file names of the photos.
+fn insert_entry(entry_type: entry::Type, values: &str, translations: &[(Locale, String)], images: Vec<image::DynamicImage>, conn: &mut db::Conn) -> Result<(i32, Vec<String>), &'static str> {
+    let id = try!(conn.query(format!("INSERT INTO {} (name, description, phone, mail, created_at, category) VALUES ({})", entry_type.table(), values)).map_err(|e| error::logged(e, "error.db"))).last_insert_id() as i32;
+    try!(entry::save_translations(entry_type, id, translations, conn).map_err(|e| error::logged(e, "error.db")));
+    let files = try!(photo::save(entry_type, id, images, conn));
+    Ok((id, files))
+}
+
+/// Errors are keys of the translation catalogues, see `new_entry_page`.
 fn add_entry(entry_type: entry::Type, req: &mut Request) -> Result<Response, &'static str> {
-    let form_data = try!(req.get_ref::<UrlEncodedBody>().map_err(|_| "Fehlender Formularinhalt."));
-    let name = mysql_escape_nullable(&form_data["name"][0]);
-    if name == "NULL" { return Err("Fehlender Name.") }
-    let description = mysql_escape_nullable(&form_data["description"][0]);
-    if description == "NULL" { return Err("Fehlende Beschreibung.") }
-    let phone = mysql_escape_nullable(&form_data["phone"][0]);
-    let mail = mysql_escape_nullable(&form_data["mail"][0]);
-    if phone == "NULL" && mail == "NULL" { return Err("Bitte geben Sie eine Telefonnummer oder Mailadresse an.") }
-    let mut conn = try!(mysql_connection().map_err(|_| "Fehler beim Zugriff auf die Datenbank."));
-    try!(conn.query(format!("INSERT INTO {} (name, description, phone, mail) VALUES ({}, {}, {}, {})", entry_type.table(), name, description, phone, mail)).map_err(|_| "Fehler beim Zugriff auf die Datenbank."));
-    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), format!(
+    let is_admin = req.get::<IsAdmin>().unwrap_or(false);
+    let locale = req.get::<CurrentLocale>().unwrap_or_default();
+    let (name, description, phone, mail, category, translations, images) = {
+        let form_data = try!(req.get_ref::<params::Params>().map_err(|_| "error.missing_form"));
+        let name = sql_escape_nullable(param_str(form_data, "name"));
+        if name == "NULL" { return Err("error.missing_name") }
+        let description = sql_escape_nullable(param_str(form_data, "description"));
+        if description == "NULL" { return Err("error.missing_description") }
+        let phone = sql_escape_nullable(param_str(form_data, "phone"));
+        let mail = sql_escape_nullable(param_str(form_data, "mail"));
+        if phone == "NULL" && mail == "NULL" { return Err("error.missing_contact") }
+        let category = match param_str(form_data, "category").trim() {
+            "" => "NULL".to_owned(),
+            category => if entry::category_name(category).is_some() { sql_escape(category) } else { return Err("error.unknown_category") }
+        };
+        let translations = Locale::all().into_iter().filter_map(|other| match param_str(form_data, &format!("description_{}", other.code())).trim() {
+            "" => None,
+            translation => Some((other, translation.to_owned()))
+        }).collect::<Vec<_>>();
+        let images = try!(photo::decode_uploads(&match form_data.find(&["images"]) {
+            Some(&params::Value::File(ref file)) => vec![file],
+            Some(&params::Value::Array(ref values)) => values.iter().filter_map(|value| if let params::Value::File(ref file) = *value { Some(file) } else { None }).collect(),
+            _ => Vec::default()
+        }));
+        (name, description, phone, mail, category, translations, images)
+    };
+    let mut conn = try!(db_connection().map_err(|e| error::logged(e, "error.db")));
+    let values = format!("{}, {}, {}, {}, {}, {}", name, description, phone, mail, util::sql_timestamp(UTC::now()), category);
+    // all or nothing, so that a failed submission can be repeated without creating the entry twice
+    try!(conn.query("BEGIN").map_err(|e| error::logged(e, "error.db")));
+    let (id, files) = match insert_entry(entry_type, &values, &translations, images, &mut conn) {
+        Ok(saved) => saved,
+        Err(e) => {
+            conn.rollback();
+            return Err(e);
+        }
+    };
+    let committed = conn.query("COMMIT");
+    if let Err(e) = committed {
+        photo::remove_files(&files);
+        conn.rollback();
+        return Err(error::logged(e, "error.db"));
+    }
+    if let Some(entry) = try!(entry::Entry::load(entry_type, id, &mut conn).map_err(|e| error::logged(e, "error.db"))) {
+        saved_search::notify_matches(entry_type, entry, &mut conn);
+    }
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::localized_page(&format!("/{}/neu", entry_type.url_part()), is_admin, locale, &format!(
         r#"
-<!DOCTYPE html>
-<html>
-    <body>
-        <p>{your_entry} wurde eingetragen.</p>
-    </body>
-</html>
+        {notices}
+        <div class="alert alert-success">{submitted}</div>
+        <p><a href="/{url_part}/{id}">{view}</a> · <a href="/">{back}</a></p>
         "#,
-        your_entry=entry_type.map("Ihr Angebot", "Ihre Anfrage")
-    )))) //TODO full HTML page with link to index
+        notices=try!(notice::format_notices(&Position::Confirmation, req, &mut conn, is_admin).map_err(|e| error::logged(e, "error.db"))),
+        submitted=entry_type.text(locale, "submitted"),
+        url_part=entry_type.url_part(),
+        id=id,
+        view=entry_type.text(locale, "view"),
+        back=locale.t("back")
+    )))))
 }
 
 fn add_offer(req: &mut Request) -> IronResult<Response> {
@@ -440,17 +563,36 @@ fn add_request(req: &mut Request) -> IronResult<Response> {
     add_entry(entry::Type::Request, req).or_else(|e| new_entry_page(entry::Type::Request, Some(e), req))
 }
 
-fn add_notice(req: &mut Request) -> IronResult<Response> {
-    add_notice_inner(req).or_else(|e| new_notice_page_inner(Some(e), req))
+/// Returns the current data of an entry for the audit log, or `null` if it does not exist.
+pub fn entry_snapshot(entry_type: entry::Type, id: i32, conn: &mut ::db::Conn) -> Result<serde_json::Value, ::db::Error> {
+    for row in try!(conn.query(format!("SELECT name, description, phone, mail, status, category FROM {} WHERE id={}", entry_type.table(), id))) {
+        let values = try!(row);
+        return Ok(json!({
+            "name": String::from_value(values[0].clone()),
+            "description": String::from_value(values[1].clone()),
+            "phone": Option::<String>::from_value(values[2].clone()),
+            "mail": Option::<String>::from_value(values[3].clone()),
+            "status": String::from_value(values[4].clone()),
+            "category": Option::<String>::from_value(values[5].clone())
+        }));
+    }
+    Ok(serde_json::Value::Null)
 }
 
 fn del_entry(entry_type: entry::Type, req: &mut Request) -> IronResult<Response> {
-    let mut conn = try!(mysql_connection());
-    let err_msg = format!("Fehler beim Lesen der {}nummer.", entry_type.map("Angebots", "Anfragen"));
-    let id_str = try!(try!(req.extensions.get::<Router>().ok_or(IronError::new(InternalError, (status::InternalServerError, err_msg.clone())))).find("id").ok_or(IronError::new(InternalError, (status::InternalServerError, err_msg.clone()))));
-    let id = try!(i32::from_str(id_str).map_err(|e| IronError::new(e, (status::BadRequest, format!("Die {}nummer {:?} ist keine Nummer.", entry_type.map("Angebots", "Anfragen"), id_str)))));
-    try!(conn.query(format!("DELETE FROM {} WHERE id={}", entry_type.table(), id)).map_err(|e| IronError::new(e, (status::InternalServerError, "Fehler beim Zugriff auf die Datenbank."))));
-    Ok(Response::with((status::Ok, format!("{} {} wurde gelöscht.", entry_type.german_article_capital(), entry_type.german_noun()))))
+    let actor = audit::actor(req);
+    let locale = req.get::<CurrentLocale>().unwrap_or_default();
+    let mut conn = try!(db_connection());
+    let err_msg = entry_type.text(locale, "id_error");
+    let id_str = try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg.clone()))).find("id").ok_or(Error::internal(err_msg.clone())));
+    let id = try!(i32::from_str(id_str).map_err(|e| Error::invalid(e, locale.t_args(&format!("{}.id_not_a_number", entry_type.key()), &[("id", &format!("{:?}", id_str))]))));
+    let snapshot = try!(entry_snapshot(entry_type, id, &mut conn).map_err(|e| Error::db(e, locale.t("error.db"))));
+    let deleted = !snapshot.is_null() && try!(conn.query(format!("UPDATE {} SET deleted_at={} WHERE id={} AND deleted_at IS NULL", entry_type.table(), util::sql_timestamp(UTC::now()), id)).map_err(|e| Error::db(e, locale.t("error.db")))).affected_rows() > 0;
+    if !deleted {
+        return Err(Error::not_found(entry_type.text(locale, "not_found_or_trashed")).into());
+    }
+    try!(audit::record(&mut conn, &actor, entry_type.map(audit::Action::DeleteOffer, audit::Action::DeleteRequest), id, &snapshot).map_err(|e| Error::db(e, locale.t("error.db"))));
+    Ok(Response::with((status::Ok, entry_type.text(locale, "trashed"))))
 }
 
 fn del_offer(req: &mut Request) -> IronResult<Response> {
@@ -461,44 +603,95 @@ fn del_request(req: &mut Request) -> IronResult<Response> {
     del_entry(entry::Type::Request, req)
 }
 
-fn del_notice(req: &mut Request) -> IronResult<Response> {
-    let mut conn = try!(mysql_connection());
-    let err_msg = "Fehler beim Lesen der Notiznummer.";
-    let id_str = try!(try!(req.extensions.get::<Router>().ok_or(IronError::new(InternalError, (status::InternalServerError, err_msg)))).find("id").ok_or(IronError::new(InternalError, (status::InternalServerError, err_msg))));
-    let id = try!(i32::from_str(id_str).map_err(|e| IronError::new(e, (status::BadRequest, format!("Die Notiznummer {:?} ist keine Nummer.", id_str)))));
-    try!(conn.query(format!("DELETE FROM notices WHERE id={}", id)).map_err(|e| IronError::new(e, (status::InternalServerError, "Fehler beim Zugriff auf die Datenbank."))));
-    Ok(Response::with((status::Ok, "Die Notiz wurde gelöscht.")))
-}
-
-fn nyi() -> IronError {
-    IronError::new(Nyi, (status::NotImplemented, "Diese Seite ist noch nicht verfügbar, bitte versuchen Sie es später erneut."))
-}
-
-fn nyi_handler(_: &mut Request) -> IronResult<Response> {
-    Err(nyi())
-}
-
 fn main() {
+    // apply pending schema migrations, `wiw-boerse migrate` stops after this
+    if !migrate::run() { process::exit(1); }
+    if env::args().nth(1).map_or(false, |arg| arg == "migrate") { return; }
     // route
     let router = router! {
         index: get "/" => index,
+        api_tokens: get "/admin/api" => { let mut c = Chain::new(api::tokens_page); c.link_before(check_admin_auth); c },
+        add_api_token: post "/admin/api" => { let mut c = Chain::new(api::add_token); c.link_before(check_admin_auth); c },
+        delete_api_token: get "/admin/api/:id/loeschen" => { let mut c = Chain::new(api::del_token); c.link_before(check_admin_auth); c },
+        audit_log: get "/admin/protokoll" => { let mut c = Chain::new(audit::log_page); c.link_before(check_admin_auth); c },
+        notices_admin: get "/admin/notizen" => { let mut c = Chain::new(notice::notices_admin_page); c.link_before(check_admin_auth); c },
+        data: get "/admin/daten" => { let mut c = Chain::new(transfer::data_page); c.link_before(check_admin_auth); c },
+        data_export: get "/admin/daten/:file" => { let mut c = Chain::new(transfer::export); c.link_before(check_admin_auth); c },
+        data_import: post "/admin/daten/import" => { let mut c = Chain::new(transfer::import); c.link_before(check_admin_auth); c },
+        trash: get "/admin/papierkorb" => { let mut c = Chain::new(trash::trash_page); c.link_before(check_admin_auth); c },
+        maintenance: get "/admin/wartung" => { let mut c = Chain::new(maintenance::maintenance_page); c.link_before(check_admin_auth); c },
+        add_maintenance: post "/admin/wartung" => { let mut c = Chain::new(maintenance::add_window); c.link_before(check_admin_auth); c },
+        delete_maintenance: get "/admin/wartung/:id/loeschen" => { let mut c = Chain::new(maintenance::del_window); c.link_before(check_admin_auth); c },
+        two_factor_page: get "/admin/2fa" => { let mut c = Chain::new(two_factor::login_page); c.link_before(check_admin_password); c },
+        two_factor_login: post "/admin/2fa" => { let mut c = Chain::new(two_factor::login); c.link_before(check_admin_password); c },
+        two_factor_enrol_page: get "/admin/2fa/einrichten" => { let mut c = Chain::new(two_factor::enrol_page); c.link_before(check_admin_password); c },
+        two_factor_enrol: post "/admin/2fa/einrichten" => { let mut c = Chain::new(two_factor::enrol); c.link_before(check_admin_password); c },
+        two_factor_disable: post "/admin/2fa/deaktivieren" => { let mut c = Chain::new(two_factor::disable); c.link_before(check_admin_auth); c },
+        subscribe_page: get "/abo/neu" => digest::subscribe_page,
+        subscribe: post "/abo/neu" => digest::subscribe,
+        confirm_subscription: get "/abo/:token/bestaetigen" => digest::confirm,
+        unsubscribe: get "/abo/:token/abbestellen" => digest::unsubscribe,
+        unsubscribe_one_click: post "/abo/:token/abbestellen" => digest::unsubscribe,
+        new_saved_search_page: get "/suchauftrag/neu" => saved_search::new_search_page,
+        add_saved_search: post "/suchauftrag/neu" => saved_search::add_search,
+        saved_search: get "/suchauftrag/:token" => saved_search::search_page,
+        edit_saved_search: post "/suchauftrag/:token" => saved_search::edit_search,
+        confirm_saved_search: get "/suchauftrag/:token/bestaetigen" => saved_search::confirm,
+        delete_saved_search: get "/suchauftrag/:token/loeschen" => saved_search::del_search,
+        delete_saved_search_one_click: post "/suchauftrag/:token/loeschen" => saved_search::del_search,
+        api_offers: get "/api/v1/biete" => api::list_offers,
+        api_add_offer: post "/api/v1/biete" => api::add_offer,
+        api_offer: get "/api/v1/biete/:id" => api::get_offer,
+        api_delete_offer: delete "/api/v1/biete/:id" => api::del_offer,
+        api_offer_status: put "/api/v1/biete/:id/status" => api::update_offer_status,
+        api_notices: get "/api/v1/notizen" => api::list_notices,
+        api_add_notice: post "/api/v1/notizen" => api::add_notice,
+        api_notice: get "/api/v1/notizen/:id" => api::get_notice,
+        api_delete_notice: delete "/api/v1/notizen/:id" => api::del_notice,
+        api_requests: get "/api/v1/suche" => api::list_requests,
+        api_add_request: post "/api/v1/suche" => api::add_request,
+        api_request: get "/api/v1/suche/:id" => api::get_request,
+        api_delete_request: delete "/api/v1/suche/:id" => api::del_request,
+        api_request_status: put "/api/v1/suche/:id/status" => api::update_request_status,
+        combined_feed: get "/alle.atom" => feed::combined_feed,
+        category: get "/kategorie/:slug" => category_page,
+        print: get "/druck" => print::print_page,
+        print_pdf: get "/druck.pdf" => print::print_pdf,
+        offers_feed: get "/biete.atom" => feed::offers_feed,
+        requests_feed: get "/suche.atom" => feed::requests_feed,
+        photo: get "/bilder/:file" => photo::serve,
+        set_locale: get "/sprache/:lang" => i18n::set_locale,
         static: get "/static" => Static::new(Path::new("static")),
-        new_offer_page: get "/biete/neu" => new_offer_page,
-        add_offer: post "/biete/neu" => add_offer,
-        offer_page: get "/biete/:id" => nyi_handler,
+        new_offer_page: get "/biete/neu" => { let mut c = Chain::new(new_offer_page); c.link_before(maintenance::check_writable); c },
+        add_offer: post "/biete/neu" => { let mut c = Chain::new(add_offer); c.link_before(maintenance::check_writable); c },
+        offer_page: get "/biete/:id" => offer_page,
         delete_offer: get "/biete/:id/loeschen" => { let mut c = Chain::new(del_offer); c.link_before(check_admin_auth); c },
-        new_notice_page: get "/notiz/neu" => { let mut c = Chain::new(new_notice_page); c.link_before(check_admin_auth); c },
-        add_notice: post "/notiz/neu" => { let mut c = Chain::new(add_notice); c.link_before(check_admin_auth); c },
-        notice_page: get "/notiz/:id" => nyi_handler,
-        delete_notice: get "/notiz/:id/loeschen" => { let mut c = Chain::new(del_notice); c.link_before(check_admin_auth); c },
-        new_request_page: get "/suche/neu" => new_request_page,
-        add_request: post "/suche/neu" => add_request,
-        request_page: get "/suche/:id" => nyi_handler,
-        delete_request: get "/suche/:id/loeschen" => { let mut c = Chain::new(del_request); c.link_before(check_admin_auth); c }
+        restore_offer: get "/biete/:id/wiederherstellen" => { let mut c = Chain::new(trash::restore_offer); c.link_before(check_admin_auth); c },
+        new_notice_page: get "/notiz/neu" => { let mut c = Chain::new(notice::new_notice_page); c.link_before(check_admin_auth); c.link_before(maintenance::check_writable); c },
+        add_notice: post "/notiz/neu" => { let mut c = Chain::new(notice::add_notice); c.link_before(check_admin_auth); c.link_before(maintenance::check_writable); c },
+        notice_page: get "/notiz/:id" => notice::notice_page,
+        edit_notice: post "/notiz/:id" => { let mut c = Chain::new(notice::edit_notice); c.link_before(check_admin_auth); c },
+        hide_notice: get "/notiz/:id/ausblenden" => notice::hide_notice,
+        delete_notice: get "/notiz/:id/loeschen" => { let mut c = Chain::new(notice::del_notice); c.link_before(check_admin_auth); c },
+        restore_notice: get "/notiz/:id/wiederherstellen" => { let mut c = Chain::new(trash::restore_notice); c.link_before(check_admin_auth); c },
+        new_request_page: get "/suche/neu" => { let mut c = Chain::new(new_request_page); c.link_before(maintenance::check_writable); c },
+        add_request: post "/suche/neu" => { let mut c = Chain::new(add_request); c.link_before(maintenance::check_writable); c },
+        request_page: get "/suche/:id" => request_page,
+        delete_request: get "/suche/:id/loeschen" => { let mut c = Chain::new(del_request); c.link_before(check_admin_auth); c },
+        restore_request: get "/suche/:id/wiederherstellen" => { let mut c = Chain::new(trash::restore_request); c.link_before(check_admin_auth); c }
     };
-    // handle auth
+    // purge old entries from the trash
+    thread::spawn(trash::purge_loop);
+    thread::spawn(digest::send_loop);
     let mut chain = Chain::new(router);
+    // assign request ids and start timing
+    chain.link_before(logging::RequestLog);
+    // handle auth
     chain.link_before(check_auth);
+    // render errors with the site layout
+    chain.link_after(error_page::ErrorPages);
+    // log requests with their final status
+    chain.link_after(logging::RequestLog);
     // serve
     Iron::new(chain).http("0.0.0.0:18800").unwrap();
 }
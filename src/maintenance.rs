@@ -0,0 +1,354 @@
+//! Scheduled maintenance windows, announced with a banner on the board and the entry forms until they are over.
+//!
+//! Windows are managed on the admin page and stored in the table `maintenance` (`id`, `start`, `end`, `reason`, `read_only`), both times in UTC. The server's upgrade scripts can still announce a window by writing the JSON file at `reboot_file` (see `util::Config`).
+//!
+//! While a window marked as `read_only` is in progress, or while `read_only` is set in the config, no entries or notices can be submitted (see `check_writable`).
+
+use std::fs::File;
+use std::io::ErrorKind;
+use std::io::prelude::*;
+use std::str::FromStr;
+
+use chrono::Duration;
+use chrono::prelude::*;
+
+use chrono_tz::Europe::Berlin;
+
+use iron::{headers, status};
+use iron::mime::Mime;
+use iron::modifiers::Header;
+use iron::prelude::*;
+
+use mysql::prelude::*;
+
+use router::Router;
+
+use serde_json;
+
+use urlencoded::UrlEncodedBody;
+
+use admin::IsAdmin;
+use audit;
+use error::{self, Error};
+use i18n::CurrentLocale;
+use logging;
+use notice::{self, Position};
+use util::{self, CONFIG};
+
+/// The format of the JSON file. Without `end`, a reboot is assumed to take 15 minutes and an upgrade to last until the file is removed.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RebootConfig {
+    schedule: Option<DateTime<UTC>>,
+    #[serde(default)]
+    upgrade: bool,
+    end: Option<DateTime<UTC>>,
+    reason: Option<String>,
+    #[serde(default)]
+    read_only: bool
+}
+
+const WINDOW_COLUMNS: &'static str = "id, start, end, reason, read_only";
+
+pub struct Window {
+    /// `None` for the window from the JSON file.
+    id: Option<i32>,
+    start: DateTime<UTC>,
+    /// `None` if the end is not known in advance.
+    end: Option<DateTime<UTC>>,
+    reason: Option<String>,
+    /// Whether submissions are blocked while the window is in progress.
+    read_only: bool
+}
+
+impl Window {
+    fn from_row(values: &::db::Row) -> Window {
+        Window {
+            id: Some(i32::from_value(values[0].clone())),
+            start: UTC.from_utc_datetime(&NaiveDateTime::from_value(values[1].clone())),
+            end: Some(UTC.from_utc_datetime(&NaiveDateTime::from_value(values[2].clone()))),
+            reason: Option::<String>::from_value(values[3].clone()),
+            read_only: bool::from_value(values[4].clone())
+        }
+    }
+
+    fn is_over(&self) -> bool {
+        self.end.map_or(false, |end| end <= UTC::now())
+    }
+
+    fn in_progress(&self) -> bool {
+        self.start <= UTC::now() && !self.is_over()
+    }
+
+    fn snapshot(&self) -> serde_json::Value {
+        json!({
+            "start": self.start.to_rfc3339(),
+            "end": self.end.map(|end| end.to_rfc3339()),
+            "reason": self.reason,
+            "read_only": self.read_only
+        })
+    }
+
+    fn german_period(&self) -> String {
+        let start = self.start.with_timezone(&Berlin);
+        match self.end {
+            Some(end) => format!("{} bis {}", start.format("%d.%m.%Y %H:%M"), end.with_timezone(&Berlin).format("%d.%m.%Y %H:%M")),
+            None => format!("ab {} (Ende offen)", start.format("%d.%m.%Y %H:%M"))
+        }
+    }
+
+    /// The banner shown to visitors.
+    fn render(&self) -> String {
+        let start = self.start.with_timezone(&Berlin);
+        let now = UTC::now().with_timezone(&Berlin);
+        let reason = self.reason.as_ref().map_or(String::default(), |reason| format!(" Grund: {}.", util::escape_html(reason)));
+        let request = if self.read_only { "In diesem Zeitraum können keine Angebote/Anfragen eingereicht werden." } else { "In diesem Zeitraum bitte keine Angebote/Anfragen einreichen, diese gehen sonst verloren." };
+        let text = match self.end.map(|end| end.with_timezone(&Berlin)) {
+            None => format!(
+                "Die Börse wird ab {date} {start_time} Uhr über einen längeren Zeitraum zeitweise nicht erreichbar sein.{reason} {request}",
+                date=if now.date() == start.date() { format!("heute ({})", start.format("%d.%m.%Y")) } else { format!("{}", start.format("%d.%m.%Y")) },
+                start_time=start.format("%H:%M"),
+                reason=reason,
+                request=if self.read_only { "Bis diese Nachricht verschwindet, können keine Angebote/Anfragen eingereicht werden." } else { "Bitte ab sofort bis diese Nachricht verschwindet keine Angebote/Anfragen einreichen, diese gehen sonst möglicherweise verloren." }
+            ),
+            Some(end) => if end.date() != start.date() {
+                format!(
+                    "Die Börse wird zwischen {start_time} Uhr und {end_time} Uhr zeitweise nicht erreichbar sein.{reason} {request}",
+                    start_time=start.format("%d.%m.%Y %H:%M"),
+                    end_time=end.format("%d.%m.%Y %H:%M"),
+                    reason=reason,
+                    request=request
+                )
+            } else {
+                format!(
+                    "Die Börse wird {date} zwischen {start_time} Uhr und {end_time} Uhr zeitweise nicht erreichbar sein.{reason} {request}",
+                    date=if now.date() == start.date() { format!("heute ({})", start.format("%d.%m.%Y")) } else { format!("am {}", start.format("%d.%m.%Y")) },
+                    start_time=start.format("%H:%M"),
+                    end_time=end.format("%H:%M"),
+                    reason=reason,
+                    request=request
+                )
+            }
+        };
+        format!(r#"<div class="alert alert-warning">{}</div>"#, text)
+    }
+}
+
+/// Reads the window announced in the JSON file, if any.
+fn file_window() -> Result<Option<Window>, String> {
+    let mut f = match File::open(&CONFIG.reboot_file) {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => { return Ok(None); }
+        Err(e) => { return Err(format!("{} konnte nicht geöffnet werden: {}", CONFIG.reboot_file, e)); }
+    };
+    let mut buf = String::default();
+    try!(f.read_to_string(&mut buf).map_err(|e| format!("{} konnte nicht gelesen werden: {}", CONFIG.reboot_file, e)));
+    let RebootConfig { schedule, upgrade, end, reason, read_only } = try!(serde_json::from_str(&buf).map_err(|e| format!("{} ist fehlerhaft: {}", CONFIG.reboot_file, e)));
+    Ok(schedule.map(move |start| Window {
+        id: None,
+        start: start,
+        end: end.or_else(|| if upgrade { None } else { Some(start + Duration::minutes(15)) }),
+        reason: reason,
+        read_only: read_only
+    }))
+}
+
+/// All windows which are not over yet, sorted by start time. Errors in the JSON file are written to stderr and otherwise ignored.
+fn current_windows(conn: &mut ::db::Conn) -> Result<Vec<Window>, ::db::Error> {
+    let mut windows = Vec::default();
+    for row in try!(conn.query(format!("SELECT {} FROM maintenance WHERE end > {}", WINDOW_COLUMNS, util::sql_timestamp(UTC::now())))) {
+        windows.push(Window::from_row(&try!(row)));
+    }
+    match file_window() {
+        Ok(Some(window)) => if !window.is_over() { windows.push(window); },
+        Ok(None) => {}
+        Err(msg) => { logging::log_message("error", &msg); }
+    }
+    windows.sort_by_key(|window| window.start);
+    Ok(windows)
+}
+
+/// The banners for all windows which are not over yet.
+pub fn format_notices(conn: &mut ::db::Conn) -> Result<String, ::db::Error> {
+    let mut notices = if CONFIG.read_only {
+        r#"<div class="alert alert-warning">Die Börse ist wegen Wartungsarbeiten vorübergehend schreibgeschützt. Bis diese Nachricht verschwindet, können keine Angebote/Anfragen eingereicht werden.</div>"#.to_owned()
+    } else {
+        String::default()
+    };
+    notices.extend(try!(current_windows(conn)).into_iter().map(|window| window.render()));
+    Ok(notices)
+}
+
+/// Returns `Some` with the expected end, if known, while submissions are blocked.
+pub fn read_only(conn: &mut ::db::Conn) -> Result<Option<Option<DateTime<UTC>>>, ::db::Error> {
+    if CONFIG.read_only { return Ok(Some(None)); }
+    Ok(try!(current_windows(conn)).into_iter().find(|window| window.read_only && window.in_progress()).map(|window| window.end))
+}
+
+/// A `BeforeMiddleware` for the submission forms and handlers which responds with a 503 page in read-only mode.
+pub fn check_writable(req: &mut Request) -> IronResult<()> {
+    let mut conn = try!(::db_connection());
+    let locale = req.get::<CurrentLocale>().unwrap_or_default();
+    if let Some(end) = try!(read_only(&mut conn).map_err(|e| Error::db(e, locale.t("error.db")))) {
+        let is_admin = req.get::<IsAdmin>().unwrap_or(false);
+        let read_only_msg = locale.t("error.read_only");
+        return Err(IronError::new(Error::read_only(read_only_msg.clone()), (status::ServiceUnavailable, "text/html".parse::<Mime>().unwrap(), util::localized_page("/", is_admin, locale, &format!(
+            r#"
+            <div class="alert alert-warning">
+                <p><strong>{read_only}</strong></p>
+                <p>{explanation}</p>
+            </div>
+            <p><a href="/">{back}</a></p>
+            "#,
+            read_only=read_only_msg,
+            explanation=locale.t_args("read_only.explanation", &[("end", &end.map_or(locale.t("read_only.end_unknown"), |end| locale.t_args("read_only.end", &[("end", &end.with_timezone(&Berlin).format("%d.%m.%Y %H:%M").to_string())])))]),
+            back=locale.t("back")
+        )))));
+    }
+    Ok(())
+}
+
+fn window_snapshot(id: i32, conn: &mut ::db::Conn) -> Result<serde_json::Value, ::db::Error> {
+    for row in try!(conn.query(format!("SELECT {} FROM maintenance WHERE id={}", WINDOW_COLUMNS, id))) {
+        return Ok(Window::from_row(&try!(row)).snapshot());
+    }
+    Ok(json!(null))
+}
+
+fn maintenance_page_inner(form_error: Option<&'static str>, req: &mut Request) -> IronResult<Response> {
+    let mut conn = try!(::db_connection());
+    let mut rows = String::default();
+    for row in try!(conn.query(format!("SELECT {} FROM maintenance ORDER BY start DESC LIMIT 100", WINDOW_COLUMNS)).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))) {
+        let window = Window::from_row(&try!(row.map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))));
+        rows.push_str(&format!(
+            r#"
+<tr{class}>
+    <td>{period}</td>
+    <td><div style="float: right;"><a href="/admin/wartung/{id}/loeschen" class="btn btn-danger"><i class="fa fa-trash-o"></i> Löschen</a></div>{reason}</td>
+    <td>{read_only}</td>
+</tr>
+            "#,
+            class=if window.is_over() { r#" style="color: gray;""# } else { "" },
+            period=window.german_period(),
+            id=window.id.unwrap_or_default(),
+            reason=window.reason.as_ref().map_or(String::default(), util::escape_html),
+            read_only=if window.read_only { "ja" } else { "nein" }
+        ));
+    }
+    if rows.is_empty() {
+        rows = r#"<tr><td colspan="3" style="color: gray; font-style: italic;">Keine Wartungsfenster eingetragen.</td></tr>"#.to_owned();
+    }
+    let file_status = match file_window() {
+        Ok(Some(window)) => format!(
+            r#"<p>Aus <code>{}</code>: {}{}{}{}</p>"#,
+            util::escape_html(&CONFIG.reboot_file),
+            window.german_period(),
+            if window.read_only { ", schreibgeschützt" } else { "" },
+            window.reason.as_ref().map_or(String::default(), |reason| format!(" ({})", util::escape_html(reason))),
+            if window.is_over() { " — bereits vorbei" } else { "" }
+        ),
+        Ok(None) => format!(r#"<p>In <code>{}</code> ist kein Wartungsfenster angekündigt.</p>"#, util::escape_html(&CONFIG.reboot_file)),
+        Err(msg) => format!(r#"<div class="alert alert-danger">{}</div>"#, util::escape_html(msg))
+    };
+    Ok(Response::with((if form_error.is_some() { status::BadRequest } else { status::Ok }, "text/html".parse::<Mime>().unwrap(), util::page("/admin/wartung", true, &format!(
+        r#"
+        {notices}
+        {error_message}
+        <h2>Wartung</h2>
+        <p>Während eines Wartungsfensters wird auf der Börse und den Formularen ein Hinweis angezeigt, bis das Fenster vorbei ist.</p>
+        {read_only_status}
+        {file_status}
+        <table class="table table-responsive">
+            <thead>
+                <tr>
+                    <th>Zeitraum</th>
+                    <th>Grund</th>
+                    <th>Schreibgeschützt</th>
+                </tr>
+            </thead>
+            <tbody>
+                {rows}
+            </tbody>
+        </table>
+        <h3>Neues Wartungsfenster</h3>
+        <form class="form-horizontal" action="/admin/wartung" method="post">
+            <div class="form-group">
+                <label for="start" class="col-sm-2 control-label">Beginn</label>
+                <div class="col-sm-4">
+                    <input type="datetime-local" class="form-control" name="start" id="start" placeholder="TT.MM.JJJJ HH:MM" />
+                </div>
+                <label for="end" class="col-sm-2 control-label">Ende</label>
+                <div class="col-sm-4">
+                    <input type="datetime-local" class="form-control" name="end" id="end" placeholder="TT.MM.JJJJ HH:MM" />
+                </div>
+            </div>
+            <div class="form-group">
+                <label for="reason" class="col-sm-2 control-label">Grund</label>
+                <div class="col-sm-10">
+                    <input type="text" class="form-control" name="reason" id="reason" placeholder="optional" />
+                </div>
+            </div>
+            <div class="form-group">
+                <div class="col-sm-offset-2 col-sm-10">
+                    <div class="checkbox"><label><input type="checkbox" name="read-only" /> Schreibgeschützt: während des Fensters keine Einreichungen annehmen</label></div>
+                </div>
+            </div>
+            <div class="form-group">
+                <div class="col-sm-offset-2 col-sm-10">
+                    <button type="submit" class="btn btn-primary">Eintragen</button>
+                </div>
+            </div>
+        </form>
+        "#,
+        notices=try!(notice::format_notices(&Position::Admin, req, &mut conn, true).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))),
+        error_message=if let Some(msg) = form_error { format!(r#"<div class="alert alert-danger"><strong>{}</strong> Das Wartungsfenster wurde nicht eingetragen.</div>"#, msg) } else { String::default() },
+        read_only_status=if CONFIG.read_only { r#"<div class="alert alert-warning">Der schreibgeschützte Modus ist in der Konfiguration dauerhaft eingeschaltet.</div>"# } else { "" },
+        file_status=file_status,
+        rows=rows
+    )))))
+}
+
+pub fn maintenance_page(req: &mut Request) -> IronResult<Response> {
+    maintenance_page_inner(None, req)
+}
+
+fn add_window_inner(req: &mut Request) -> Result<Response, &'static str> {
+    let actor = audit::actor(req);
+    let window = {
+        let form_data = try!(req.get_ref::<UrlEncodedBody>().map_err(|_| "Fehlender Formularinhalt."));
+        let start = try!(form_data.get("start").and_then(|values| values.first()).and_then(|value| util::parse_berlin_datetime(value.trim())).ok_or("Fehlender oder ungültiger Beginn. Bitte verwenden Sie das Format TT.MM.JJJJ HH:MM."));
+        let end = try!(form_data.get("end").and_then(|values| values.first()).and_then(|value| util::parse_berlin_datetime(value.trim())).ok_or("Fehlendes oder ungültiges Ende. Bitte verwenden Sie das Format TT.MM.JJJJ HH:MM."));
+        if end <= start { return Err("Das Ende muss nach dem Beginn liegen."); }
+        let reason = form_data.get("reason").and_then(|values| values.first()).map(|reason| reason.trim().to_owned()).and_then(|reason| if reason.is_empty() { None } else { Some(reason) });
+        Window { id: None, start: start, end: Some(end), reason: reason, read_only: form_data.contains_key("read-only") }
+    };
+    let mut conn = try!(::db_connection().map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    let id = try!(conn.query(format!(
+        "INSERT INTO maintenance (start, end, reason, read_only) VALUES ({}, {}, {}, {})",
+        util::sql_timestamp(window.start),
+        util::sql_timestamp(window.end.unwrap()),
+        window.reason.as_ref().map_or("NULL".to_owned(), ::sql_escape),
+        ::db::sql_bool(window.read_only)
+    )).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank."))).last_insert_id() as i32;
+    try!(audit::record(&mut conn, &actor, audit::Action::AddMaintenance, id, &window.snapshot()).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    Ok(Response::with((status::SeeOther, Header(headers::Location("/admin/wartung".to_owned())))))
+}
+
+pub fn add_window(req: &mut Request) -> IronResult<Response> {
+    add_window_inner(req).or_else(|e| maintenance_page_inner(Some(e), req))
+}
+
+pub fn del_window(req: &mut Request) -> IronResult<Response> {
+    let actor = audit::actor(req);
+    let mut conn = try!(::db_connection());
+    let err_msg = "Fehler beim Lesen der Nummer.";
+    let id_str = try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg))).find("id").ok_or(Error::internal(err_msg)));
+    let id = try!(i32::from_str(id_str).map_err(|e| Error::invalid(e, format!("Die Nummer {:?} ist keine Nummer.", id_str))));
+    let snapshot = try!(window_snapshot(id, &mut conn).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    if snapshot.is_null() {
+        return Err(Error::not_found("Dieses Wartungsfenster existiert nicht.").into());
+    }
+    try!(conn.query(format!("DELETE FROM maintenance WHERE id={}", id)).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(audit::record(&mut conn, &actor, audit::Action::DeleteMaintenance, id, &snapshot).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    Ok(Response::with((status::SeeOther, Header(headers::Location("/admin/wartung".to_owned())))))
+}
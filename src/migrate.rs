@@ -0,0 +1,145 @@
+//! Versioned schema migrations, embedded from `assets/migrations`, with one script per database backend (see `db`).
+//!
+//! The applied versions are recorded in the table `schema_version` (`version`, `applied_at`). Pending migrations are applied on every start of the server, or with `wiw-boerse migrate` without starting it. Starting from an empty `wiwboerse` database, or without an SQLite file, creates all tables. Migration 1 is the schema the Börse had before, so databases which were set up by hand are brought up to date by the later migrations.
+//!
+//! MySQL commits schema changes immediately, so a migration which fails halfway has to be finished by hand. Each statement of a migration must end with a `;` at the end of a line.
+
+use chrono::prelude::*;
+
+use mysql::prelude::*;
+
+use db;
+use logging;
+use util::{self, CONFIG};
+
+/// All migrations in order, as version and the SQL scripts for MySQL and SQLite. New migrations must be appended with the next version number.
+const MIGRATIONS: &'static [(u32, &'static str, &'static str)] = &[
+    (1, include_str!("../assets/migrations/mysql/0001_initial.sql"), include_str!("../assets/migrations/sqlite/0001_initial.sql")),
+    (2, include_str!("../assets/migrations/mysql/0002_features.sql"), include_str!("../assets/migrations/sqlite/0002_features.sql")),
+    (3, include_str!("../assets/migrations/mysql/0003_two_factor_last_counter.sql"), include_str!("../assets/migrations/sqlite/0003_two_factor_last_counter.sql")),
+    (4, include_str!("../assets/migrations/mysql/0004_notice_html.sql"), include_str!("../assets/migrations/sqlite/0004_notice_html.sql"))
+];
+
+/// The highest version recorded in `schema_version`, or 0 for an empty database.
+fn current_version(conn: &mut ::db::Conn) -> Result<u32, ::db::Error> {
+    try!(conn.query("CREATE TABLE IF NOT EXISTS schema_version (version INT NOT NULL PRIMARY KEY, applied_at DATETIME NOT NULL)"));
+    for row in try!(conn.query("SELECT MAX(version) FROM schema_version")) {
+        let values = try!(row);
+        return Ok(Option::<u32>::from_value(values[0].clone()).unwrap_or(0));
+    }
+    Ok(0)
+}
+
+/// Splits a script into statements, skipping comment lines.
+fn statements(script: &str) -> Vec<String> {
+    let mut statements = Vec::default();
+    let mut statement = String::default();
+    for line in script.lines().filter(|line| !line.trim_left().starts_with("--")) {
+        statement.push_str(line);
+        statement.push('\n');
+        if line.trim_right().ends_with(';') {
+            statements.push(statement.trim().trim_right_matches(';').to_owned());
+            statement = String::default();
+        }
+    }
+    if !statement.trim().is_empty() { statements.push(statement.trim().to_owned()); }
+    statements
+}
+
+/// Applies all pending migrations and returns their versions.
+pub fn migrate(conn: &mut ::db::Conn) -> Result<Vec<u32>, ::db::Error> {
+    migrate_backend(conn, CONFIG.database)
+}
+
+fn migrate_backend(conn: &mut ::db::Conn, backend: db::Backend) -> Result<Vec<u32>, ::db::Error> {
+    let current = try!(current_version(conn));
+    let mut applied = Vec::default();
+    for &(version, mysql_script, sqlite_script) in MIGRATIONS.iter().filter(|&&(version, _, _)| version > current) {
+        let script = match backend {
+            db::Backend::Mysql => mysql_script,
+            db::Backend::Sqlite => sqlite_script
+        };
+        for statement in statements(script) {
+            try!(conn.query(statement));
+        }
+        try!(conn.query(format!("INSERT INTO schema_version (version, applied_at) VALUES ({}, {})", version, util::sql_timestamp(UTC::now()))));
+        applied.push(version);
+    }
+    Ok(applied)
+}
+
+/// An in-memory SQLite database with all migrations applied, for the tests of other modules.
+#[cfg(test)]
+pub fn test_conn() -> ::db::Conn {
+    let mut conn = ::db::Conn::Sqlite(::rusqlite::Connection::open_in_memory().unwrap());
+    migrate_backend(&mut conn, db::Backend::Sqlite).unwrap();
+    conn
+}
+
+/// Connects to the database and applies all pending migrations, reporting on stderr. Returns whether the schema is up to date.
+pub fn run() -> bool {
+    let result = ::db::Conn::new().and_then(|mut conn| migrate(&mut conn));
+    match result {
+        Ok(applied) => {
+            for version in applied {
+                logging::log_message("info", &format!("applied schema migration {}", version));
+            }
+            true
+        }
+        Err(e) => {
+            logging::log_message("error", &format!("failed to migrate the database schema: {}", e));
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mysql::prelude::*;
+
+    use db;
+
+    use super::{MIGRATIONS, migrate_backend, statements};
+
+    fn sqlite_conn() -> db::Conn {
+        db::Conn::Sqlite(::rusqlite::Connection::open_in_memory().unwrap())
+    }
+
+    #[test]
+    fn versions_are_consecutive() {
+        for (i, &(version, _, _)) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(version, i as u32 + 1);
+        }
+    }
+
+    #[test]
+    fn splits_statements() {
+        let script = "-- a comment;\nCREATE TABLE a (\n    id INT\n);\n\nALTER TABLE a ADD COLUMN b INT;\nDROP TABLE c";
+        assert_eq!(statements(script), vec!["CREATE TABLE a (\n    id INT\n)", "ALTER TABLE a ADD COLUMN b INT", "DROP TABLE c"]);
+    }
+
+    #[test]
+    fn applies_sqlite_migrations_in_order_once() {
+        let mut conn = sqlite_conn();
+        let all = MIGRATIONS.iter().map(|&(version, _, _)| version).collect::<Vec<_>>();
+        assert_eq!(migrate_backend(&mut conn, db::Backend::Sqlite).unwrap(), all);
+        assert!(migrate_backend(&mut conn, db::Backend::Sqlite).unwrap().is_empty());
+        conn.query("SELECT position, text, visible_from, visible_until, severity, priority, html, deleted_at FROM notices").unwrap();
+        conn.query("SELECT account, secret, pending_secret, last_counter FROM two_factor").unwrap();
+    }
+
+    #[test]
+    fn keeps_existing_notices_as_html() {
+        let mut conn = sqlite_conn();
+        for statement in statements(MIGRATIONS[0].2) {
+            conn.query(statement).unwrap();
+        }
+        conn.query("CREATE TABLE schema_version (version INT NOT NULL PRIMARY KEY, applied_at DATETIME NOT NULL)").unwrap();
+        conn.query("INSERT INTO schema_version (version, applied_at) VALUES (1, '2017-03-01 18:00:00')").unwrap();
+        conn.query("INSERT INTO notices (position, text) VALUES ('index', '<b>Alt</b>')").unwrap();
+        assert_eq!(migrate_backend(&mut conn, db::Backend::Sqlite).unwrap(), (2..MIGRATIONS.len() as u32 + 1).collect::<Vec<_>>());
+        conn.query("INSERT INTO notices (position, text) VALUES ('index', '**Neu**')").unwrap();
+        let html = conn.query("SELECT html FROM notices ORDER BY id").unwrap().map(|row| bool::from_value(row.unwrap()[0].clone())).collect::<Vec<_>>();
+        assert_eq!(html, vec![true, false]);
+    }
+}
@@ -0,0 +1,725 @@
+//! Notices published by the administration on the board and the entry forms.
+//!
+//! A notice can be limited to a time span with `visible_from` and `visible_until`, both stored in UTC. Notices are sorted by descending `priority` and their text is rendered as Markdown (see `util::render_markdown`), except for notices written before Markdown was supported, which contain HTML.
+//!
+//! Visitors can hide individual notices. The ids of hidden notices are stored in a cookie, so this only applies to the current browser.
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use chrono::prelude::*;
+
+use chrono_tz::Europe::Berlin;
+
+use iron::{headers, status};
+use iron::mime::Mime;
+use iron::modifiers::Header;
+use iron::prelude::*;
+
+use mysql::prelude::*;
+
+use router::Router;
+
+use serde_json;
+
+use urlencoded::{QueryMap, UrlEncodedBody};
+
+use admin::IsAdmin;
+use audit;
+use db;
+use entry;
+use error::{self, Error};
+use util;
+
+/// A kind of page on which notices can be shown.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Position {
+    Index,
+    CreateOffer,
+    CreateRequest,
+    /// The detail pages of offers and requests.
+    Detail,
+    /// The pages shown after an offer, request or notice has been submitted.
+    Confirmation,
+    Admin,
+    /// The print view and PDF of the board, see `print`.
+    Print,
+    /// The page of the category with the given short name.
+    Category(String)
+}
+
+impl Position {
+    /// The positions which can be selected with a checkbox, with their form field names and descriptions.
+    fn fixed() -> Vec<(Position, &'static str, &'static str)> {
+        vec![
+            (Position::Index, "position-index", "Börsen-Hauptseite"),
+            (Position::CreateOffer, "position-create-offer", "Formular „neues Angebot“"),
+            (Position::CreateRequest, "position-create-request", "Formular „neue Anfrage“"),
+            (Position::Detail, "position-detail", "Detailseiten von Angeboten und Anfragen"),
+            (Position::Confirmation, "position-confirmation", "Bestätigungsseiten"),
+            (Position::Admin, "position-admin", "Administrationsseiten"),
+            (Position::Print, "position-print", "Druckansicht und PDF")
+        ]
+    }
+
+    fn from_str(s: &str) -> Option<Position> {
+        match s {
+            "index" => Some(Position::Index),
+            "create_offer" => Some(Position::CreateOffer),
+            "create_request" => Some(Position::CreateRequest),
+            "detail" => Some(Position::Detail),
+            "confirmation" => Some(Position::Confirmation),
+            "admin" => Some(Position::Admin),
+            "print" => Some(Position::Print),
+            _ => if s.starts_with("category:") { Some(Position::Category(s["category:".len()..].to_owned())) } else { None }
+        }
+    }
+
+    fn sql_string(&self) -> String {
+        match *self {
+            Position::Index => "index".to_owned(),
+            Position::CreateOffer => "create_offer".to_owned(),
+            Position::CreateRequest => "create_request".to_owned(),
+            Position::Detail => "detail".to_owned(),
+            Position::Confirmation => "confirmation".to_owned(),
+            Position::Admin => "admin".to_owned(),
+            Position::Print => "print".to_owned(),
+            Position::Category(ref category) => format!("category:{}", category)
+        }
+    }
+
+    fn german_description(&self) -> String {
+        match *self {
+            Position::Category(ref category) => format!("Kategorie „{}“", entry::category_name(category).unwrap_or(&category[..])),
+            ref position => Position::fixed().into_iter().find(|&(ref fixed, _, _)| fixed == position).map_or(String::default(), |(_, _, description)| description.to_owned())
+        }
+    }
+}
+
+/// The set of positions where a notice is shown, stored as a comma-separated list in the `position` column. The special value `all` matches every position, including ones added later.
+#[derive(Default)]
+pub struct NoticePositions {
+    all: bool,
+    positions: BTreeSet<Position>
+}
+
+impl NoticePositions {
+    pub fn any(&self) -> bool {
+        self.all || !self.positions.is_empty()
+    }
+
+    fn contains(&self, pos: &Position) -> bool {
+        self.all || self.positions.contains(pos)
+    }
+
+    fn categories(&self) -> Vec<&str> {
+        self.positions.iter().filter_map(|position| if let Position::Category(ref category) = *position { Some(&category[..]) } else { None }).collect()
+    }
+
+    pub fn sql_string(&self) -> String {
+        if self.all {
+            "all".to_owned()
+        } else {
+            self.positions.iter().map(Position::sql_string).collect::<Vec<_>>().join(",")
+        }
+    }
+
+    fn german_descriptions(&self) -> Vec<String> {
+        if self.all {
+            vec!["alle Seiten".to_owned()]
+        } else {
+            self.positions.iter().map(Position::german_description).collect()
+        }
+    }
+}
+
+impl<S: AsRef<str>> From<S> for NoticePositions {
+    fn from(s: S) -> NoticePositions {
+        let mut positions = NoticePositions::default();
+        for position_str in s.as_ref().split(',').map(str::trim) {
+            if position_str == "all" {
+                positions.all = true;
+            } else if let Some(position) = Position::from_str(position_str) {
+                positions.positions.insert(position);
+            }
+        }
+        positions
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Success,
+    Warning,
+    Danger
+}
+
+impl Severity {
+    fn all() -> Vec<Severity> {
+        vec![Severity::Info, Severity::Success, Severity::Warning, Severity::Danger]
+    }
+
+    pub fn from_str(s: &str) -> Option<Severity> {
+        Severity::all().into_iter().find(|severity| severity.as_str() == s)
+    }
+
+    /// The name used in the database, which is also the suffix of the Bootstrap `alert-*` class.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Severity::Info => "info",
+            Severity::Success => "success",
+            Severity::Warning => "warning",
+            Severity::Danger => "danger"
+        }
+    }
+
+    fn german_name(&self) -> &'static str {
+        match *self {
+            Severity::Info => "Information",
+            Severity::Success => "Erfolg",
+            Severity::Warning => "Warnung",
+            Severity::Danger => "Dringend"
+        }
+    }
+}
+
+impl Default for Severity {
+    fn default() -> Severity {
+        Severity::Info
+    }
+}
+
+/// The editable data of a notice.
+#[derive(Default)]
+pub struct NoticeData {
+    pub text: String,
+    pub positions: NoticePositions,
+    pub visible_from: Option<DateTime<UTC>>,
+    pub visible_until: Option<DateTime<UTC>>,
+    pub severity: Severity,
+    pub priority: i32,
+    /// Whether the text is HTML instead of Markdown, which is only the case for notices written before Markdown was supported and backups of them. Editing a notice keeps it.
+    pub html: bool
+}
+
+/// The columns read by `NoticeData::from_row`, in order.
+const NOTICE_COLUMNS: &'static str = "position, text, visible_from, visible_until, severity, priority, html";
+
+impl NoticeData {
+    /// Reads the `NOTICE_COLUMNS`, starting at the given column index.
+    fn from_row(values: &::db::Row, offset: usize) -> NoticeData {
+        NoticeData {
+            text: String::from_value(values[offset + 1].clone()),
+            positions: NoticePositions::from(String::from_value(values[offset].clone())),
+            visible_from: Option::<NaiveDateTime>::from_value(values[offset + 2].clone()).map(|time| UTC.from_utc_datetime(&time)),
+            visible_until: Option::<NaiveDateTime>::from_value(values[offset + 3].clone()).map(|time| UTC.from_utc_datetime(&time)),
+            severity: Severity::from_str(&String::from_value(values[offset + 4].clone())).unwrap_or_default(),
+            priority: i32::from_value(values[offset + 5].clone()),
+            html: bool::from_value(values[offset + 6].clone())
+        }
+    }
+
+    /// Returns the data of a notice which has not been deleted.
+    pub fn load(id: i32, conn: &mut ::db::Conn) -> Result<Option<NoticeData>, ::db::Error> {
+        for row in try!(conn.query(format!("SELECT {} FROM notices WHERE id={} AND deleted_at IS NULL", NOTICE_COLUMNS, id))) {
+            let values = try!(row);
+            return Ok(Some(NoticeData::from_row(&values, 0)));
+        }
+        Ok(None)
+    }
+
+    pub fn snapshot(&self) -> serde_json::Value {
+        json!({
+            "position": self.positions.sql_string(),
+            "text": self.text,
+            "visible_from": self.visible_from.map(|time| time.to_rfc3339()),
+            "visible_until": self.visible_until.map(|time| time.to_rfc3339()),
+            "severity": self.severity.as_str(),
+            "priority": self.priority,
+            "html": self.html
+        })
+    }
+
+    /// Inserts a new notice and returns its id.
+    pub fn insert(&self, conn: &mut ::db::Conn) -> Result<i32, ::db::Error> {
+        Ok(try!(conn.query(format!(
+            "INSERT INTO notices ({}) VALUES ({}, {}, {}, {}, {}, {}, {})",
+            NOTICE_COLUMNS,
+            ::sql_escape(self.positions.sql_string()),
+            ::sql_escape(&self.text),
+            sql_datetime_nullable(self.visible_from),
+            sql_datetime_nullable(self.visible_until),
+            ::sql_escape(self.severity.as_str()),
+            self.priority,
+            db::sql_bool(self.html)
+        ))).last_insert_id() as i32)
+    }
+
+    /// Inserts a notice with the given id, or replaces the notice with that id and restores it if it was deleted. Used by `transfer` to restore backups.
+    pub fn upsert(&self, id: i32, conn: &mut ::db::Conn) -> Result<(), ::db::Error> {
+        try!(conn.query(format!(
+            "INSERT INTO notices (id, {}) VALUES ({}, {}, {}, {}, {}, {}, {}, {}) {}, deleted_at=NULL",
+            NOTICE_COLUMNS,
+            id,
+            ::sql_escape(self.positions.sql_string()),
+            ::sql_escape(&self.text),
+            sql_datetime_nullable(self.visible_from),
+            sql_datetime_nullable(self.visible_until),
+            ::sql_escape(self.severity.as_str()),
+            self.priority,
+            db::sql_bool(self.html),
+            db::upsert_clause("id", &["position", "text", "visible_from", "visible_until", "severity", "priority", "html"])
+        )));
+        Ok(())
+    }
+
+    fn render(&self, id: i32, is_admin: bool) -> String {
+        format!(
+            r#"<div class="alert alert-{severity}"><a href="/notiz/{id}/ausblenden" class="close" title="Ausblenden">&times;</a>{edit_buttons}{text}</div>"#,
+            severity=self.severity.as_str(),
+            id=id,
+            edit_buttons=if is_admin { format!(r#"<div style="float: right;"><a href="/notiz/{0}" class="btn btn-default"><i class="fa fa-pencil"></i></a> <a href="/notiz/{0}/loeschen" class="btn btn-danger"><i class="fa fa-trash-o"></i></a></div>"#, id) } else { "".to_owned() },
+            text=self.text_html()
+        )
+    }
+
+    /// The text as HTML.
+    fn text_html(&self) -> String {
+        if self.html { self.text.clone() } else { util::render_markdown(&self.text) }
+    }
+
+    fn german_schedule(&self) -> String {
+        let format_time = |time: DateTime<UTC>| time.with_timezone(&Berlin).format("%d.%m.%Y %H:%M").to_string();
+        match (self.visible_from, self.visible_until) {
+            (None, None) => "unbegrenzt".to_owned(),
+            (Some(from), None) => format!("ab {} Uhr", format_time(from)),
+            (None, Some(until)) => format!("bis {} Uhr", format_time(until)),
+            (Some(from), Some(until)) => format!("{} Uhr bis {} Uhr", format_time(from), format_time(until))
+        }
+    }
+}
+
+/// SQL condition matching notices which are currently visible.
+fn visible_condition() -> String {
+    let now = util::sql_timestamp(UTC::now());
+    format!("deleted_at IS NULL AND (visible_from IS NULL OR visible_from <= {0}) AND (visible_until IS NULL OR visible_until > {0})", now)
+}
+
+/// All currently visible notices with their ids, regardless of position.
+pub fn visible_notices(conn: &mut ::db::Conn) -> Result<Vec<(i32, NoticeData)>, ::db::Error> {
+    let mut notices = Vec::default();
+    for row in try!(conn.query(format!("SELECT id, {} FROM notices WHERE {} ORDER BY priority DESC, id", NOTICE_COLUMNS, visible_condition()))) {
+        let values = try!(row);
+        notices.push((i32::from_value(values[0].clone()), NoticeData::from_row(&values, 1)));
+    }
+    Ok(notices)
+}
+
+/// All notices which have not been deleted, including scheduled and expired ones, with their ids.
+pub fn all_notices(conn: &mut ::db::Conn) -> Result<Vec<(i32, NoticeData)>, ::db::Error> {
+    let mut notices = Vec::default();
+    for row in try!(conn.query(format!("SELECT id, {} FROM notices WHERE deleted_at IS NULL ORDER BY id", NOTICE_COLUMNS))) {
+        let values = try!(row);
+        notices.push((i32::from_value(values[0].clone()), NoticeData::from_row(&values, 1)));
+    }
+    Ok(notices)
+}
+
+const HIDDEN_COOKIE: &'static str = "wiw_hidden_notices";
+/// The maximum number of hidden notices remembered in the cookie. The oldest ones are forgotten first.
+const MAX_HIDDEN: usize = 100;
+
+fn hidden_notices(req: &Request) -> Vec<i32> {
+    util::cookie(req, HIDDEN_COOKIE).map_or(Vec::default(), |cookie| cookie.split('.').filter_map(|id| i32::from_str(id).ok()).collect())
+}
+
+pub fn format_notices(position: &Position, req: &Request, conn: &mut ::db::Conn, is_admin: bool) -> Result<String, ::db::Error> {
+    let hidden = hidden_notices(req);
+    let entries = try!(conn.query(format!("SELECT id, {} FROM notices WHERE {} ORDER BY priority DESC, id", NOTICE_COLUMNS, visible_condition())));
+    Ok(entries.filter_map(|row| match row {
+        Ok(values) => {
+            let id = i32::from_value(values[0].clone());
+            let notice = NoticeData::from_row(&values, 1);
+            if notice.positions.contains(position) && !hidden.contains(&id) {
+                Some(notice.render(id, is_admin))
+            } else {
+                None
+            }
+        }
+        Err(_) => Some(r#"<div class="alert alert-danger"><strong>Fehlerhafte Nachricht.</strong></div>"#.to_owned())
+    }).collect())
+}
+
+/// The notices selected for printing, rendered without buttons since they cannot be hidden on paper.
+pub fn print_notices(conn: &mut ::db::Conn) -> Result<String, ::db::Error> {
+    Ok(try!(visible_notices(conn)).into_iter().filter(|&(_, ref notice)| notice.positions.contains(&Position::Print)).map(|(_, notice)| format!(
+        r#"<div class="notice notice-{}">{}</div>"#,
+        notice.severity.as_str(),
+        notice.text_html()
+    )).collect())
+}
+
+/// Hides a notice in the current browser and returns to the previous page.
+pub fn hide_notice(req: &mut Request) -> IronResult<Response> {
+    let err_msg = "Fehler beim Lesen der Notiznummer.";
+    let id_str = try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg))).find("id").ok_or(Error::internal(err_msg)));
+    let id = try!(i32::from_str(id_str).map_err(|e| Error::invalid(e, format!("Die Notiznummer {:?} ist keine Nummer.", id_str))));
+    let mut hidden = hidden_notices(req);
+    if !hidden.contains(&id) { hidden.push(id); }
+    let skip = hidden.len().saturating_sub(MAX_HIDDEN);
+    let cookie_value = hidden[skip..].iter().map(|id| id.to_string()).collect::<Vec<_>>().join(".");
+    let mut response = Response::with((status::SeeOther, Header(headers::Location(util::back_location(req)))));
+    response.headers.set(headers::SetCookie(vec![format!("{}={}; Path=/; Max-Age=31536000", HIDDEN_COOKIE, cookie_value)]));
+    Ok(response)
+}
+
+/// Returns the current data of a notice for the audit log, or `null` if it does not exist.
+pub fn notice_snapshot(id: i32, conn: &mut ::db::Conn) -> Result<serde_json::Value, ::db::Error> {
+    Ok(try!(NoticeData::load(id, conn)).map_or(serde_json::Value::Null, |notice| notice.snapshot()))
+}
+
+fn notice_form(action: &str, notice: &NoticeData, submit_label: &str) -> String {
+    format!(
+        r#"
+        <form class="form-horizontal" action="{action}" method="post" enctype="application/x-www-form-urlencoded">
+            <div class="form-group">
+                <label for="text" class="col-sm-2 control-label">Text</label>
+                <div class="col-sm-10">
+                    <input type="text" class="form-control" name="text" id="text" placeholder="Inhalt der Notiz" value="{text}" />
+                    <p class="help-block">Formatierung: **fett**, *kursiv*, [Linktext](https://example.com)</p>
+                </div>
+            </div>
+            <div class="form-group">
+                <label for="severity" class="col-sm-2 control-label">Art</label>
+                <div class="col-sm-4">
+                    <select class="form-control" name="severity" id="severity">
+                        {severity_options}
+                    </select>
+                </div>
+                <label for="priority" class="col-sm-2 control-label">Priorität</label>
+                <div class="col-sm-4">
+                    <input type="number" class="form-control" name="priority" id="priority" value="{priority}" />
+                </div>
+                <div class="col-sm-offset-2 col-sm-10">
+                    <p class="help-block">Notizen mit höherer Priorität werden weiter oben angezeigt.</p>
+                </div>
+            </div>
+            <div class="form-group">
+                <label for="position" class="col-sm-2 control-label">Anzeigen auf</label>
+                <div class="col-sm-10">
+                    <div class="checkbox">
+                        <label>
+                            <input type="checkbox" name="position-all"{all_checked} /> <strong>Alle Seiten</strong>
+                        </label>
+                    </div>
+                    {position_checkboxes}
+                    <input type="text" class="form-control" name="position-categories" placeholder="Kategorieseiten (Kürzel, durch Komma getrennt)" value="{categories}" />
+                    <p class="help-block">Kürzel: {category_slugs}</p>
+                </div>
+            </div>
+            <div class="form-group">
+                <label for="visible-from" class="col-sm-2 control-label">Anzeigen ab</label>
+                <div class="col-sm-4">
+                    <input type="datetime-local" class="form-control" name="visible-from" id="visible-from" placeholder="TT.MM.JJJJ HH:MM" value="{visible_from}" />
+                </div>
+                <label for="visible-until" class="col-sm-2 control-label">Anzeigen bis</label>
+                <div class="col-sm-4">
+                    <input type="datetime-local" class="form-control" name="visible-until" id="visible-until" placeholder="TT.MM.JJJJ HH:MM" value="{visible_until}" />
+                </div>
+                <div class="col-sm-offset-2 col-sm-10">
+                    <p class="help-block">Optional. Ohne Angabe wird die Notiz sofort bzw. bis zur Löschung angezeigt.</p>
+                </div>
+            </div>
+            <div class="form-group">
+                <div class="col-sm-offset-2 col-sm-10">
+                    <a href="/" style="float: right;" class="btn btn-danger">Abbrechen</a>
+                    <button type="submit" class="btn btn-primary">{submit_label}</button>
+                </div>
+            </div>
+        </form>
+        "#,
+        action=action,
+        text=util::escape_html(&notice.text),
+        severity_options=Severity::all().into_iter().map(|severity| format!(r#"<option value="{}"{}>{}</option>"#, severity.as_str(), if severity == notice.severity { " selected" } else { "" }, severity.german_name())).collect::<String>(),
+        priority=notice.priority,
+        all_checked=if notice.positions.all { " checked" } else { "" },
+        position_checkboxes=Position::fixed().into_iter().map(|(position, field_name, description)| format!(
+            r#"<div class="checkbox"><label><input type="checkbox" name="{}"{} /> {}</label></div>"#,
+            field_name,
+            if notice.positions.positions.contains(&position) { " checked" } else { "" },
+            description
+        )).collect::<String>(),
+        categories=util::escape_html(notice.positions.categories().join(", ")),
+        category_slugs=entry::CATEGORIES.iter().map(|&(slug, name)| format!("<code>{}</code> ({})", slug, name)).collect::<Vec<_>>().join(", "),
+        visible_from=notice.visible_from.map_or(String::default(), util::datetime_input_value),
+        visible_until=notice.visible_until.map_or(String::default(), util::datetime_input_value),
+        submit_label=submit_label
+    )
+}
+
+fn new_notice_page_inner(form_error: Option<&'static str>, req: &mut Request) -> IronResult<Response> {
+    let is_admin = req.get::<IsAdmin>().unwrap_or(false);
+    Ok(Response::with((if form_error.is_some() { status::BadRequest } else { status::Ok }, "text/html".parse::<Mime>().unwrap(), util::page("/notiz/neu", is_admin, &format!(
+        r#"
+        {error_message}
+        <h2>Neue Notiz</h2>
+        {form}
+        "#,
+        error_message=if let Some(msg) = form_error { format!(r#"<div class="alert alert-danger"><strong>{}</strong> Bitte füllen Sie das Formular erneut aus.</div>"#, msg) } else { String::default() },
+        form=notice_form("/notiz/neu", &NoticeData::default(), "Notiz veröffentlichen")
+    )))))
+}
+
+pub fn new_notice_page(req: &mut Request) -> IronResult<Response> {
+    new_notice_page_inner(None, req)
+}
+
+fn form_datetime(form_data: &QueryMap, key: &str) -> Result<Option<DateTime<UTC>>, &'static str> {
+    match form_data.get(key).and_then(|values| values.first()).map(|value| value.trim()) {
+        None | Some("") => Ok(None),
+        Some(value) => util::parse_berlin_datetime(value).map(Some).ok_or("Ungültige Zeitangabe. Bitte verwenden Sie das Format TT.MM.JJJJ HH:MM.")
+    }
+}
+
+fn notice_form_data(req: &mut Request) -> Result<NoticeData, &'static str> {
+    let form_data = try!(req.get_ref::<UrlEncodedBody>().map_err(|_| "Fehlender Formularinhalt."));
+    let text = try!(form_data.get("text").and_then(|values| values.first()).ok_or("Fehlender Text.")).to_owned();
+    if text.is_empty() { return Err("Fehlender Text."); }
+    let mut positions = NoticePositions::default();
+    positions.all = form_data.contains_key("position-all");
+    for (position, field_name, _) in Position::fixed() {
+        if form_data.contains_key(field_name) { positions.positions.insert(position); }
+    }
+    if let Some(categories) = form_data.get("position-categories").and_then(|values| values.first()) {
+        for category in categories.split(',').map(str::trim).filter(|category| !category.is_empty()) {
+            positions.positions.insert(Position::Category(category.to_lowercase()));
+        }
+    }
+    if !positions.any() { return Err("Bitte geben Sie mindestens eine Position an, wo die Notiz angezeigt werden soll."); }
+    let severity = try!(Severity::from_str(form_data.get("severity").and_then(|values| values.first()).map_or("info", |severity| &severity[..])).ok_or("Ungültige Art der Notiz."));
+    let priority = match form_data.get("priority").and_then(|values| values.first()).map(|priority| priority.trim()) {
+        None | Some("") => 0,
+        Some(priority) => try!(i32::from_str(priority).map_err(|_| "Die Priorität muss eine ganze Zahl sein."))
+    };
+    let visible_from = try!(form_datetime(form_data, "visible-from"));
+    let visible_until = try!(form_datetime(form_data, "visible-until"));
+    if let (Some(from), Some(until)) = (visible_from, visible_until) {
+        if until <= from { return Err("Das Ende des Anzeigezeitraums muss nach dem Beginn liegen."); }
+    }
+    Ok(NoticeData { text: text, positions: positions, visible_from: visible_from, visible_until: visible_until, severity: severity, priority: priority, html: false })
+}
+
+fn sql_datetime_nullable(time: Option<DateTime<UTC>>) -> String {
+    time.map_or("NULL".to_owned(), util::sql_timestamp)
+}
+
+fn add_notice_inner(req: &mut Request) -> Result<Response, &'static str> {
+    let actor = audit::actor(req);
+    let notice = try!(notice_form_data(req));
+    let mut conn = try!(::db_connection().map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    let id = try!(notice.insert(&mut conn).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(audit::record(&mut conn, &actor, audit::Action::AddNotice, id, &notice.snapshot()).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/notiz/neu", true, &format!(
+        r#"
+        {notices}
+        <div class="alert alert-success">Die Notiz wurde veröffentlicht.</div>
+        <p><a href="/notiz/{id}">Notiz ansehen</a> · <a href="/">Zurück zur Börse</a></p>
+        "#,
+        notices=try!(format_notices(&Position::Confirmation, req, &mut conn, true).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank."))),
+        id=id
+    )))))
+}
+
+pub fn add_notice(req: &mut Request) -> IronResult<Response> {
+    add_notice_inner(req).or_else(|e| new_notice_page_inner(Some(e), req))
+}
+
+fn notice_page_inner(form_error: Option<&'static str>, req: &mut Request) -> IronResult<Response> {
+    let is_admin = req.get::<IsAdmin>().unwrap_or(false);
+    let mut conn = try!(::db_connection());
+    let err_msg = "Fehler beim Lesen der Notiznummer.";
+    let id_str = try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg))).find("id").ok_or(Error::internal(err_msg)));
+    let id = try!(i32::from_str(id_str).map_err(|e| Error::invalid(e, format!("Die Notiznummer {:?} ist keine Nummer.", id_str))));
+    let notice = try!(try!(NoticeData::load(id, &mut conn).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))).ok_or(Error::not_found("Diese Notiz existiert nicht.")));
+    let positions_description = notice.positions.german_descriptions().join(", ");
+    Ok(Response::with((if form_error.is_some() { status::BadRequest } else { status::Ok }, "text/html".parse::<Mime>().unwrap(), util::page(&format!("/notiz/{}", id), is_admin, &format!(
+        r#"
+        {error_message}
+        <h2>Notiz</h2>
+        {notice}
+        <p>Angezeigt auf: {positions}</p>
+        <p>Anzeigezeitraum: {schedule}</p>
+        {edit_form}
+        "#,
+        error_message=if let Some(msg) = form_error { format!(r#"<div class="alert alert-danger"><strong>{}</strong> Die Änderungen wurden nicht gespeichert.</div>"#, msg) } else { String::default() },
+        notice=notice.render(id, false),
+        positions=if positions_description.is_empty() { "(nirgends)".to_owned() } else { positions_description },
+        schedule=notice.german_schedule(),
+        edit_form=if is_admin {
+            format!(
+                r#"<h3>Bearbeiten <a href="/notiz/{id}/loeschen" class="btn btn-danger"><i class="fa fa-trash-o"></i> Löschen</a></h3>{form}"#,
+                id=id,
+                form=notice_form(&format!("/notiz/{}", id), &notice, "Änderungen speichern")
+            )
+        } else {
+            String::default()
+        }
+    )))))
+}
+
+pub fn notice_page(req: &mut Request) -> IronResult<Response> {
+    notice_page_inner(None, req)
+}
+
+fn edit_notice_inner(req: &mut Request) -> Result<Response, &'static str> {
+    let actor = audit::actor(req);
+    let id = try!(req.extensions.get::<Router>().and_then(|params| params.find("id")).and_then(|id| i32::from_str(id).ok()).ok_or("Fehler beim Lesen der Notiznummer."));
+    let notice = try!(notice_form_data(req));
+    let mut conn = try!(::db_connection().map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    let before = try!(notice_snapshot(id, &mut conn).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    if before.is_null() { return Err("Diese Notiz existiert nicht."); }
+    try!(conn.query(format!(
+        "UPDATE notices SET position={}, text={}, visible_from={}, visible_until={}, severity={}, priority={} WHERE id={} AND deleted_at IS NULL",
+        ::sql_escape(notice.positions.sql_string()),
+        ::sql_escape(&notice.text),
+        sql_datetime_nullable(notice.visible_from),
+        sql_datetime_nullable(notice.visible_until),
+        ::sql_escape(notice.severity.as_str()),
+        notice.priority,
+        id
+    )).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(audit::record(&mut conn, &actor, audit::Action::EditNotice, id, &json!({"before": before, "after": notice.snapshot()})).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    Ok(Response::with((status::SeeOther, Header(headers::Location(format!("/notiz/{}", id))))))
+}
+
+pub fn edit_notice(req: &mut Request) -> IronResult<Response> {
+    edit_notice_inner(req).or_else(|e| notice_page_inner(Some(e), req))
+}
+
+fn notices_table(rows: &str, empty_msg: &str) -> String {
+    format!(
+        r#"
+        <table class="table table-responsive">
+            <thead>
+                <tr>
+                    <th>Text</th>
+                    <th>Art</th>
+                    <th>Priorität</th>
+                    <th>Angezeigt auf</th>
+                    <th>Anzeigezeitraum</th>
+                </tr>
+            </thead>
+            <tbody>
+                {}
+            </tbody>
+        </table>
+        "#,
+        if rows.is_empty() { format!(r#"<tr><td colspan="5" style="color: gray; font-style: italic;">{}</td></tr>"#, empty_msg) } else { rows.to_owned() }
+    )
+}
+
+/// Lists all notices, split into currently visible, upcoming and expired ones.
+pub fn notices_admin_page(req: &mut Request) -> IronResult<Response> {
+    let mut conn = try!(::db_connection());
+    let now = UTC::now();
+    let mut current = String::default();
+    let mut upcoming = String::default();
+    let mut expired = String::default();
+    for row in try!(conn.query(format!("SELECT id, {} FROM notices WHERE deleted_at IS NULL ORDER BY priority DESC, id DESC", NOTICE_COLUMNS)).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))) {
+        let values = try!(row.map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+        let notice = NoticeData::from_row(&values, 1);
+        let notice_row = format!(
+            r#"
+<tr>
+    <td><div style="float: right;"><a href="/notiz/{id}" class="btn btn-default"><i class="fa fa-pencil"></i></a></div>{text}</td>
+    <td>{severity}</td>
+    <td>{priority}</td>
+    <td>{positions}</td>
+    <td>{schedule}</td>
+</tr>
+            "#,
+            id=i32::from_value(values[0].clone()),
+            text=notice.text_html(),
+            severity=notice.severity.german_name(),
+            priority=notice.priority,
+            positions=notice.positions.german_descriptions().join(", "),
+            schedule=notice.german_schedule()
+        );
+        if notice.visible_from.map_or(false, |from| from > now) {
+            upcoming.push_str(&notice_row);
+        } else if notice.visible_until.map_or(false, |until| until <= now) {
+            expired.push_str(&notice_row);
+        } else {
+            current.push_str(&notice_row);
+        }
+    }
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/admin/notizen", true, &format!(
+        r#"
+        {notices}
+        <h2>Notizen <a href="/notiz/neu" class="btn btn-success"><i class="fa fa-plus"></i> Notiz hinzufügen</a></h2>
+        <h3>Aktuell angezeigt</h3>
+        {current}
+        <h3>Geplant</h3>
+        {upcoming}
+        <h3>Abgelaufen</h3>
+        {expired}
+        "#,
+        notices=try!(format_notices(&Position::Admin, req, &mut conn, true).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))),
+        current=notices_table(&current, "Keine aktuellen Notizen."),
+        upcoming=notices_table(&upcoming, "Keine geplanten Notizen."),
+        expired=notices_table(&expired, "Keine abgelaufenen Notizen.")
+    )))))
+}
+
+pub fn del_notice(req: &mut Request) -> IronResult<Response> {
+    let actor = audit::actor(req);
+    let mut conn = try!(::db_connection());
+    let err_msg = "Fehler beim Lesen der Notiznummer.";
+    let id_str = try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg))).find("id").ok_or(Error::internal(err_msg)));
+    let id = try!(i32::from_str(id_str).map_err(|e| Error::invalid(e, format!("Die Notiznummer {:?} ist keine Nummer.", id_str))));
+    let snapshot = try!(notice_snapshot(id, &mut conn).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    let deleted = !snapshot.is_null() && try!(conn.query(format!("UPDATE notices SET deleted_at={} WHERE id={} AND deleted_at IS NULL", util::sql_timestamp(UTC::now()), id)).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))).affected_rows() > 0;
+    if !deleted {
+        return Err(Error::not_found("Diese Notiz existiert nicht oder ist bereits im Papierkorb.").into());
+    }
+    try!(audit::record(&mut conn, &actor, audit::Action::DeleteNotice, id, &snapshot).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    Ok(Response::with((status::Ok, "Die Notiz wurde in den Papierkorb verschoben.")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NoticePositions, Position};
+
+    #[test]
+    fn parses_positions() {
+        let positions = NoticePositions::from("index, detail,unbekannt,category:kleidung");
+        assert!(positions.contains(&Position::Index));
+        assert!(positions.contains(&Position::Detail));
+        assert!(positions.contains(&Position::Category("kleidung".to_owned())));
+        assert!(!positions.contains(&Position::Admin));
+        assert!(!positions.contains(&Position::Category("moebel".to_owned())));
+        assert_eq!(positions.sql_string(), "index,detail,category:kleidung");
+    }
+
+    #[test]
+    fn all_matches_every_position() {
+        let positions = NoticePositions::from("all");
+        assert!(positions.contains(&Position::Print));
+        assert!(positions.contains(&Position::Category("kleidung".to_owned())));
+        assert_eq!(positions.sql_string(), "all");
+    }
+
+    #[test]
+    fn unknown_positions_are_ignored() {
+        assert!(!NoticePositions::from("").any());
+        assert!(!NoticePositions::from("unbekannt").any());
+    }
+
+    #[test]
+    fn fixed_positions_round_trip() {
+        for (position, _, _) in Position::fixed() {
+            assert!(NoticePositions::from(position.sql_string()).contains(&position));
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Photos attached to entries.
+//!
+//! Uploads are decoded and re-encoded as JPEG, which drops all metadata like EXIF and GPS tags. Each photo is stored as `<file>.jpg` and `<file>-thumb.jpg` in `upload_dir` (see `util::Config`), with one row per photo in the table `entry_images` (`id`, `entry_table`, `entry_id`, `file`). The files are deleted when the entry is purged from the trash.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use image::{self, DynamicImage, FilterType, GenericImage};
+
+use iron::status;
+use iron::mime::Mime;
+use iron::prelude::*;
+
+use mysql::prelude::*;
+
+use params;
+
+use router::Router;
+
+use entry;
+use error::{self, Error};
+use logging;
+use util::{self, CONFIG};
+
+/// The maximum number of photos per entry.
+pub const MAX_PHOTOS: usize = 4;
+const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+/// The maximum number of pixels of an upload, checked before decoding since a small file can contain a huge image.
+const MAX_PIXELS: u64 = 50 * 1000 * 1000;
+const MAX_DIMENSION: u32 = 1600;
+const THUMBNAIL_DIMENSION: u32 = 200;
+
+fn path(file: &str, thumbnail: bool) -> PathBuf {
+    PathBuf::from(&CONFIG.upload_dir).join(format!("{}{}.jpg", file, if thumbnail { "-thumb" } else { "" }))
+}
+
+/// A big endian number.
+fn read_be(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |n, &b| n << 8 | b as u32)
+}
+
+/// Reads width and height from the header of a JPEG, PNG or GIF file, or returns `None` for other or broken files.
+fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        if data.get(12..16) != Some(&b"IHDR"[..]) { return None; }
+        return data.get(16..24).map(|bytes| (read_be(&bytes[..4]), read_be(&bytes[4..])));
+    }
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return data.get(6..10).map(|bytes| (bytes[0] as u32 | (bytes[1] as u32) << 8, bytes[2] as u32 | (bytes[3] as u32) << 8));
+    }
+    if !data.starts_with(b"\xff\xd8") { return None; }
+    // Walks through the segments of the JPEG up to the start of frame, which contains the dimensions.
+    let mut pos = 2;
+    loop {
+        let (prefix, marker) = match data.get(pos..pos + 2) {
+            Some(bytes) => (bytes[0], bytes[1]),
+            None => { return None; }
+        };
+        if prefix != 0xff { return None; }
+        match marker {
+            0xff => { pos += 1; }
+            0x01 | 0xd0...0xd7 => { pos += 2; }
+            0xc0...0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => {
+                return data.get(pos + 5..pos + 9).map(|bytes| (read_be(&bytes[2..]), read_be(&bytes[..2])));
+            }
+            0xd9 | 0xda => { return None; }
+            _ => match data.get(pos + 2..pos + 4) {
+                Some(length) => { pos += 2 + read_be(length) as usize; }
+                None => { return None; }
+            }
+        }
+    }
+}
+
+/// Decodes the uploaded files, skipping empty file inputs. The format is detected from the content, since the uploaded files have no extension. Errors are keys of the translation catalogues.
+pub fn decode_uploads(files: &[&params::File]) -> Result<Vec<DynamicImage>, &'static str> {
+    let files = files.iter().filter(|file| file.size > 0).collect::<Vec<_>>();
+    if files.len() > MAX_PHOTOS { return Err("error.too_many_photos"); }
+    let mut images = Vec::default();
+    for file in files {
+        if file.size > MAX_FILE_SIZE { return Err("error.photo_too_large"); }
+        let mut data = Vec::default();
+        try!(File::open(&file.path).and_then(|mut f| f.read_to_end(&mut data)).map_err(|e| error::logged(e, "error.photo_unreadable")));
+        let (width, height) = try!(dimensions(&data).ok_or("error.photo_unreadable"));
+        if width as u64 * height as u64 > MAX_PIXELS { return Err("error.photo_too_many_pixels"); }
+        images.push(try!(image::load_from_memory(&data).map_err(|_| "error.photo_unreadable")));
+    }
+    Ok(images)
+}
+
+fn save_one(entry_type: entry::Type, entry_id: i32, img: DynamicImage, file: &str, conn: &mut ::db::Conn) -> Result<(), &'static str> {
+    let (width, height) = img.dimensions();
+    let img = if width > MAX_DIMENSION || height > MAX_DIMENSION { img.resize(MAX_DIMENSION, MAX_DIMENSION, FilterType::Lanczos3) } else { img };
+    let img = DynamicImage::ImageRgb8(img.to_rgb());
+    try!(img.save(path(file, false)).map_err(|e| error::logged(e, "error.photo_save")));
+    try!(img.thumbnail(THUMBNAIL_DIMENSION, THUMBNAIL_DIMENSION).save(path(file, true)).map_err(|e| error::logged(e, "error.photo_save")));
+    try!(conn.query(format!(
+        "INSERT INTO entry_images (entry_table, entry_id, file) VALUES ({}, {}, {})",
+        ::sql_escape(entry_type.table()),
+        entry_id,
+        ::sql_escape(file)
+    )).map_err(|e| error::logged(e, "error.db")));
+    Ok(())
+}
+
+/// Saves decoded photos for an entry as full-size image and thumbnail and returns their file names. If one of them fails, the files written so far are removed again. Errors are keys of the translation catalogues.
+pub fn save(entry_type: entry::Type, entry_id: i32, images: Vec<DynamicImage>, conn: &mut ::db::Conn) -> Result<Vec<String>, &'static str> {
+    try!(fs::create_dir_all(&CONFIG.upload_dir).map_err(|e| error::logged(e, "error.photo_save")));
+    let mut files = Vec::default();
+    for img in images {
+        files.push(util::random_token(24));
+        if let Err(e) = save_one(entry_type, entry_id, img, files.last().unwrap(), conn) {
+            remove_files(&files);
+            return Err(e);
+        }
+    }
+    Ok(files)
+}
+
+/// The file names of the photos of an entry, without extension.
+pub fn files(entry_type: entry::Type, entry_id: i32, conn: &mut ::db::Conn) -> Result<Vec<String>, ::db::Error> {
+    let mut files = Vec::default();
+    for row in try!(conn.query(format!("SELECT file FROM entry_images WHERE entry_table={} AND entry_id={} ORDER BY id", ::sql_escape(entry_type.table()), entry_id))) {
+        files.push(String::from_value(try!(row)[0].clone()));
+    }
+    Ok(files)
+}
+
+/// The file names of the photos of the given entries, by entry id.
+pub fn files_by_entry(entry_type: entry::Type, ids: &[i32], conn: &mut ::db::Conn) -> Result<HashMap<i32, Vec<String>>, ::db::Error> {
+    let mut files = HashMap::new();
+    if ids.is_empty() { return Ok(files); }
+    for row in try!(conn.query(format!(
+        "SELECT entry_id, file FROM entry_images WHERE entry_table={} AND entry_id IN ({}) ORDER BY id",
+        ::sql_escape(entry_type.table()),
+        ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", ")
+    ))) {
+        let values = try!(row);
+        files.entry(i32::from_value(values[0].clone())).or_insert_with(Vec::default).push(String::from_value(values[1].clone()));
+    }
+    Ok(files)
+}
+
+pub fn format_thumbnails(files: &[String]) -> String {
+    files.iter().map(|file| format!(r#"<img src="/bilder/{}-thumb.jpg" class="img-thumbnail" style="max-width: 100px; margin: 5px 5px 0 0;" />"#, file)).collect()
+}
+
+pub fn format_photos(files: &[String]) -> String {
+    files.iter().map(|file| format!(r#"<a href="/bilder/{0}.jpg"><img src="/bilder/{0}-thumb.jpg" class="img-thumbnail" style="margin: 0 10px 10px 0;" /></a>"#, file)).collect()
+}
+
+/// Removes the files of photos and their thumbnails, e.g. after the rows have been rolled back.
+pub fn remove_files(files: &[String]) {
+    for file in files {
+        for &thumbnail in &[false, true] {
+            if let Err(e) = fs::remove_file(path(file, thumbnail)) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    logging::log_message("error", &format!("failed to delete photo {}: {}", file, e));
+                }
+            }
+        }
+    }
+}
+
+/// Deletes the photos of an entry, including the files.
+pub fn delete(entry_type: entry::Type, entry_id: i32, conn: &mut ::db::Conn) -> Result<(), ::db::Error> {
+    remove_files(&try!(files(entry_type, entry_id, conn)));
+    try!(conn.query(format!("DELETE FROM entry_images WHERE entry_table={} AND entry_id={}", ::sql_escape(entry_type.table()), entry_id)));
+    Ok(())
+}
+
+/// Serves a photo or thumbnail from the upload directory.
+pub fn serve(req: &mut Request) -> IronResult<Response> {
+    let err_msg = "Fehler beim Lesen des Dateinamens.";
+    let name = try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg))).find("file").ok_or(Error::internal(err_msg))).to_owned();
+    let (file, thumbnail) = match name.trim_right_matches(".jpg") {
+        stem if stem.len() == name.len() => { return Err(Error::not_found("Dieses Bild existiert nicht.").into()); }
+        stem if stem.ends_with("-thumb") => (stem.trim_right_matches("-thumb").to_owned(), true),
+        stem => (stem.to_owned(), false)
+    };
+    if file.is_empty() || !file.chars().all(|c| c.is_digit(36)) {
+        return Err(Error::not_found("Dieses Bild existiert nicht.").into());
+    }
+    let path = path(&file, thumbnail);
+    if !path.is_file() {
+        return Err(Error::not_found("Dieses Bild existiert nicht.").into());
+    }
+    Ok(Response::with((status::Ok, "image/jpeg".parse::<Mime>().unwrap(), path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dimensions;
+
+    #[test]
+    fn reads_png_dimensions() {
+        let mut png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 0x0a, 0x00, 0, 0, 0x07, 0x80, 8, 2, 0, 0, 0]);
+        assert_eq!(dimensions(&png), Some((2560, 1920)));
+        assert_eq!(dimensions(&png[..20]), None);
+    }
+
+    #[test]
+    fn reads_gif_dimensions() {
+        assert_eq!(dimensions(b"GIF89a\x40\x01\xf0\x00\x00\x00\x00"), Some((320, 240)));
+        assert_eq!(dimensions(b"GIF87a\x40"), None);
+    }
+
+    #[test]
+    fn reads_jpeg_dimensions_after_other_segments() {
+        let mut jpeg = b"\xff\xd8\xff\xe0\x00\x10JFIF\x00\x01\x01\x00\x00\x01\x00\x01\x00\x00".to_vec();
+        jpeg.extend_from_slice(b"\xff\xc4\x00\x04\x00\x00");
+        jpeg.extend_from_slice(b"\xff\xc0\x00\x11\x08\x01\xe0\x02\x80\x03\x01\x22\x00\x02\x11\x01\x03\x11\x01");
+        assert_eq!(dimensions(&jpeg), Some((640, 480)));
+        assert_eq!(dimensions(&jpeg[..jpeg.len() - 16]), None);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_eq!(dimensions(b""), None);
+        assert_eq!(dimensions(b"BM\x00\x00\x00\x00"), None);
+        assert_eq!(dimensions(b"\xff\xd8\xff\xda\x00\x02"), None);
+        assert_eq!(dimensions(b"\xff\xd8\x00\x00"), None);
+    }
+}
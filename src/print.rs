@@ -0,0 +1,160 @@
+//! A print view of all open offers and requests, grouped by category, for residents without internet access.
+//!
+//! `/druck` is meant to be printed from the browser, `/druck.pdf` is the same page converted by `wkhtmltopdf` (see `util::Config`). Admins can restrict both to offers or requests with the query parameter `type`.
+
+use std::io::{self, ErrorKind};
+use std::io::prelude::*;
+use std::process::{Command, Stdio};
+
+use chrono::prelude::*;
+
+use chrono_tz::Europe::Berlin;
+
+use iron::status;
+use iron::mime::Mime;
+use iron::prelude::*;
+
+use urlencoded::UrlEncodedQuery;
+
+use admin::IsAdmin;
+use entry::{self, Entry};
+use error::Error;
+use i18n::{CurrentLocale, Locale};
+use notice;
+use util::{self, CONFIG};
+
+/// The entry types to print. Only admins can choose, everyone else gets both.
+fn entry_types(req: &mut Request, is_admin: bool) -> Vec<entry::Type> {
+    let all = vec![entry::Type::Offer, entry::Type::Request];
+    if !is_admin { return all; }
+    match req.get_ref::<UrlEncodedQuery>().ok().and_then(|query| query.get("type")) {
+        Some(values) => all.into_iter().filter(|entry_type| values.iter().any(|value| value == entry_type.url_part())).collect(),
+        None => all
+    }
+}
+
+fn format_entry(entry: &Entry, translation: Option<&String>, locale: Locale) -> String {
+    format!(
+        r#"
+        <div class="entry">
+            <p class="name">{name}</p>
+            <p>{description}</p>
+            <p class="contact">{contact}</p>
+        </div>
+        "#,
+        name=util::escape_html(&entry.name),
+        description=match translation {
+            Some(translation) => format!(r#"<span lang="{}">{}</span>"#, locale.code(), util::escape_html(translation).replace("\n", "<br />")),
+            None => format!(r#"<span dir="auto">{}</span>"#, util::escape_html(&entry.description).replace("\n", "<br />"))
+        },
+        contact=entry.phone.iter().map(|phone| format!("{}: {}", locale.t("field.phone"), util::escape_html(phone)))
+            .chain(entry.mail.iter().map(|mail| format!("{}: {}", locale.t("field.mail"), util::escape_html(mail))))
+            .collect::<Vec<_>>().join(" · ")
+    )
+}
+
+/// The open entries of a type, grouped by category in the order of `entry::CATEGORIES`, with uncategorized entries last.
+fn format_section(entry_type: entry::Type, locale: Locale, conn: &mut ::db::Conn) -> Result<String, ::db::Error> {
+    let entries = try!(Entry::list(entry_type, conn)).into_iter().filter(|entry| entry.status == entry::Status::Open).collect::<Vec<_>>();
+    let translations = try!(entry::translated_descriptions(entry_type, &entries.iter().map(|entry| entry.id).collect::<Vec<_>>(), locale, conn));
+    let groups = entry::CATEGORIES.iter().map(|&(slug, _)| (Some(slug), locale.t(&format!("category.{}", slug)))).chain(Some((None, locale.t("print.no_category"))));
+    let mut section = format!("<h2>{}</h2>", entry_type.text(locale, "heading"));
+    let mut any = false;
+    for (slug, name) in groups {
+        let group = entries.iter().filter(|entry| entry.category.as_ref().map(|category| &category[..]) == slug).map(|entry| format_entry(entry, translations.get(&entry.id), locale)).collect::<String>();
+        if group.is_empty() { continue; }
+        section.push_str(&format!(r#"<h3>{}</h3><div class="entries">{}</div>"#, name, group));
+        any = true;
+    }
+    if !any {
+        section.push_str(&format!(r#"<p class="empty">{}</p>"#, entry_type.text(locale, "none")));
+    }
+    Ok(section)
+}
+
+fn print_html(req: &mut Request, pdf: bool) -> IronResult<String> {
+    let is_admin = req.get::<IsAdmin>().unwrap_or(false);
+    let locale = req.get::<CurrentLocale>().unwrap_or_default();
+    let entry_types = entry_types(req, is_admin);
+    let mut conn = try!(::db_connection());
+    let mut sections = String::default();
+    for &entry_type in &entry_types {
+        sections.push_str(&try!(format_section(entry_type, locale, &mut conn).map_err(|e| Error::db(e, locale.t("error.db")))));
+    }
+    let query = entry_types.iter().map(|entry_type| format!("type={}", entry_type.url_part())).collect::<Vec<_>>().join("&amp;");
+    Ok(format!(
+        r#"<!DOCTYPE html>
+<html {html_attributes}>
+<head>
+    <meta charset="utf-8" />
+    <title>{title}</title>
+    <style>{style}</style>
+</head>
+<body>
+    {controls}
+    <h1>{title}</h1>
+    <p class="generated">{generated}</p>
+    {notices}
+    {sections}
+</body>
+</html>
+"#,
+        html_attributes=locale.html_attributes(),
+        title=locale.t("print.title"),
+        style=include_str!("../assets/print.css"),
+        controls=if pdf { String::default() } else {
+            format!(
+                r#"
+    <form class="controls" action="/druck" method="get">
+        <a href="/druck.pdf{pdf_query}">{download_pdf}</a> · <a href="javascript:window.print()">{print}</a> · <a href="/">{back}</a>
+        {type_checkboxes}
+    </form>
+                "#,
+                pdf_query=if is_admin { format!("?{}", query) } else { String::default() },
+                download_pdf=locale.t("print.pdf"),
+                print=locale.t("print.print"),
+                back=locale.t("back"),
+                type_checkboxes=if is_admin {
+                    format!(
+                        r#"<br />{}: {} <button type="submit">{}</button>"#,
+                        locale.t("print.types"),
+                        [entry::Type::Offer, entry::Type::Request].iter().map(|entry_type| format!(
+                            r#"<label><input type="checkbox" name="type" value="{}"{} /> {}</label>"#,
+                            entry_type.url_part(),
+                            if entry_types.iter().any(|selected| selected.url_part() == entry_type.url_part()) { " checked" } else { "" },
+                            entry_type.plural(locale)
+                        )).collect::<Vec<_>>().join(" "),
+                        locale.t("print.apply")
+                    )
+                } else { String::default() }
+            )
+        },
+        generated=locale.t_args("print.generated", &[("date", &UTC::now().with_timezone(&Berlin).format("%d.%m.%Y %H:%M").to_string())]),
+        notices=try!(notice::print_notices(&mut conn).map_err(|e| Error::db(e, locale.t("error.db")))),
+        sections=sections
+    ))
+}
+
+fn html_to_pdf(html: &str) -> io::Result<Vec<u8>> {
+    let mut child = try!(Command::new(&CONFIG.wkhtmltopdf).arg("--quiet").arg("--encoding").arg("utf-8").arg("-").arg("-").stdin(Stdio::piped()).stdout(Stdio::piped()).spawn());
+    {
+        let stdin = try!(child.stdin.as_mut().ok_or(io::Error::new(ErrorKind::Other, "wkhtmltopdf has no stdin")));
+        try!(stdin.write_all(html.as_bytes()));
+    }
+    let output = try!(child.wait_with_output());
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(io::Error::new(ErrorKind::Other, format!("wkhtmltopdf exited with {}", output.status)))
+    }
+}
+
+pub fn print_page(req: &mut Request) -> IronResult<Response> {
+    Ok(Response::with((status::Ok, "text/html; charset=utf-8".parse::<Mime>().unwrap(), try!(print_html(req, false)))))
+}
+
+pub fn print_pdf(req: &mut Request) -> IronResult<Response> {
+    let html = try!(print_html(req, true));
+    let pdf = try!(html_to_pdf(&html).map_err(|e| Error::other(e, "Das PDF konnte nicht erzeugt werden.")));
+    Ok(Response::with((status::Ok, "application/pdf".parse::<Mime>().unwrap(), pdf)))
+}
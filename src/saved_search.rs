@@ -0,0 +1,347 @@
+//! Saved searches which send an e-mail as soon as a matching offer or request is added.
+//!
+//! Stored in the table `saved_searches` (`id`, `mail`, `entry_type`, `keywords`, `category`, `token`, `created_at`, `confirmed_at`). Like digest subscriptions, a saved search needs to be confirmed via e-mail first, and the `token` in the links authenticates the management and delete pages, which are exempt from `check_auth`.
+
+use std::thread;
+
+use chrono::Duration;
+use chrono::prelude::*;
+
+use iron::{headers, status};
+use iron::mime::Mime;
+use iron::modifiers::Header;
+use iron::prelude::*;
+
+use mysql::prelude::*;
+
+use router::Router;
+
+use urlencoded::{QueryMap, UrlEncodedBody, UrlEncodedQuery};
+
+use admin::IsAdmin;
+use digest;
+use entry::{self, CATEGORIES, Entry};
+use error::{self, Error};
+use logging;
+use util::{self, BASE_URL};
+
+/// The editable part of a saved search.
+#[derive(Default)]
+struct Query {
+    /// `None` matches both offers and requests.
+    entry_type: Option<entry::Type>,
+    /// Whitespace-separated words which must all appear in the name or description.
+    keywords: String,
+    category: Option<String>
+}
+
+impl Query {
+    fn entry_type_str(&self) -> Option<&'static str> {
+        self.entry_type.map(|entry_type| entry_type.map("offer", "request"))
+    }
+
+    fn matches(&self, entry_type: entry::Type, entry: &Entry) -> bool {
+        if self.entry_type_str().map_or(false, |query_type| query_type != entry_type.map("offer", "request")) { return false; }
+        if self.category.is_some() && self.category != entry.category { return false; }
+        let text = format!("{} {}", entry.name, entry.description).to_lowercase();
+        self.keywords.split_whitespace().all(|keyword| text.contains(&keyword.to_lowercase()))
+    }
+
+    fn german_description(&self) -> String {
+        let mut description = self.entry_type.map_or("Angebote und Anfragen", |entry_type| entry_type.german_plural()).to_owned();
+        if !self.keywords.trim().is_empty() { description.push_str(&format!(" mit „{}“", self.keywords.trim())); }
+        if let Some(ref category) = self.category { description.push_str(&format!(" in der Kategorie {}", entry::category_name(category).unwrap_or(&category[..]))); }
+        description
+    }
+
+    fn from_form(form_data: &QueryMap) -> Result<Query, &'static str> {
+        let entry_type = match form_data.get("entry-type").and_then(|values| values.first()).map(|value| &value[..]) {
+            Some("offer") => Some(entry::Type::Offer),
+            Some("request") => Some(entry::Type::Request),
+            _ => None
+        };
+        let keywords = form_data.get("keywords").and_then(|values| values.first()).map_or(String::default(), |keywords| keywords.split_whitespace().collect::<Vec<_>>().join(" "));
+        let category = match form_data.get("category").and_then(|values| values.first()).map(|value| value.trim()) {
+            None | Some("") => None,
+            Some(category) => Some(try!(entry::category_name(category).map(|_| category.to_owned()).ok_or("Unbekannte Kategorie.")))
+        };
+        if keywords.is_empty() && category.is_none() { return Err("Bitte geben Sie Suchbegriffe oder eine Kategorie an."); }
+        Ok(Query { entry_type: entry_type, keywords: keywords, category: category })
+    }
+}
+
+struct SavedSearch {
+    mail: String,
+    token: String,
+    query: Query
+}
+
+/// The columns read by `SavedSearch::from_row`, in order.
+const SEARCH_COLUMNS: &'static str = "mail, token, entry_type, keywords, category";
+
+impl SavedSearch {
+    fn from_row(values: &::db::Row) -> SavedSearch {
+        SavedSearch {
+            mail: String::from_value(values[0].clone()),
+            token: String::from_value(values[1].clone()),
+            query: Query {
+                entry_type: match Option::<String>::from_value(values[2].clone()).as_ref().map(|entry_type| &entry_type[..]) {
+                    Some("offer") => Some(entry::Type::Offer),
+                    Some("request") => Some(entry::Type::Request),
+                    _ => None
+                },
+                keywords: String::from_value(values[3].clone()),
+                category: Option::<String>::from_value(values[4].clone())
+            }
+        }
+    }
+
+    fn load(token: &str, conn: &mut ::db::Conn) -> Result<Option<SavedSearch>, ::db::Error> {
+        for row in try!(conn.query(format!("SELECT {} FROM saved_searches WHERE token={}", SEARCH_COLUMNS, ::sql_escape(token)))) {
+            return Ok(Some(SavedSearch::from_row(&try!(row))));
+        }
+        Ok(None)
+    }
+
+    fn manage_url(&self) -> String {
+        format!("{}/suchauftrag/{}", BASE_URL, self.token)
+    }
+
+    fn delete_url(&self) -> String {
+        format!("{}/suchauftrag/{}/loeschen", BASE_URL, self.token)
+    }
+}
+
+/// E-mails everyone whose confirmed saved search matches a newly added entry. The mails are sent in a background thread and errors are only logged, so a failure never prevents the entry from being added.
+pub fn notify_matches(entry_type: entry::Type, entry: Entry, conn: &mut ::db::Conn) {
+    let mut matches = Vec::default();
+    match conn.query(format!("SELECT {} FROM saved_searches WHERE confirmed_at IS NOT NULL", SEARCH_COLUMNS)) {
+        Ok(rows) => for row in rows {
+            match row {
+                Ok(values) => {
+                    let search = SavedSearch::from_row(&values);
+                    if search.query.matches(entry_type, &entry) { matches.push(search); }
+                }
+                Err(e) => { logging::log_message("error", &format!("failed to read saved search: {}", e)); }
+            }
+        },
+        Err(e) => { logging::log_message("error", &format!("failed to read saved searches: {}", e)); }
+    }
+    if matches.is_empty() { return; }
+    thread::spawn(move || {
+        for search in matches {
+            let body = format!(
+                "Hallo,\n\nauf der WiW-Börse wurde {article} neue{ending} {noun} eingestellt, {pronoun} zu Ihrem Suchauftrag „{description}“ passt:\n\n{name}: {entry_description}\n{base_url}/{url_part}/{id}\n\n-- \nSuchauftrag ändern: {manage_url}\nSuchauftrag löschen: {delete_url}\n",
+                article=entry_type.map("ein", "eine"),
+                ending=entry_type.map("s", ""),
+                noun=entry_type.german_noun(),
+                pronoun=entry_type.map("das", "die"),
+                description=search.query.german_description(),
+                name=entry.name,
+                entry_description=entry.description,
+                base_url=BASE_URL,
+                url_part=entry_type.url_part(),
+                id=entry.id,
+                manage_url=search.manage_url(),
+                delete_url=search.delete_url()
+            );
+            if let Err(e) = digest::send_mail(&search.mail, "WiW-Boerse: neuer Treffer fuer Ihren Suchauftrag", &body, Some(&search.delete_url())) {
+                logging::log_message("error", &format!("failed to send saved search mail to {}: {}", search.mail, e));
+            }
+        }
+    });
+}
+
+fn query_form(action: &str, query: &Query, mail_field: bool, submit_label: &str) -> String {
+    format!(
+        r#"
+        <form class="form-horizontal" action="{action}" method="post">
+            {mail_field}
+            <div class="form-group">
+                <label for="keywords" class="col-sm-2 control-label">Suchbegriffe</label>
+                <div class="col-sm-10">
+                    <input type="text" class="form-control" name="keywords" id="keywords" placeholder="z.B. Kinderfahrrad" value="{keywords}" />
+                    <p class="help-block">Alle Begriffe müssen im Namen oder in der Beschreibung vorkommen.</p>
+                </div>
+            </div>
+            <div class="form-group">
+                <label for="category" class="col-sm-2 control-label">Kategorie</label>
+                <div class="col-sm-10">
+                    <select class="form-control" name="category" id="category">
+                        <option value="">alle Kategorien</option>
+                        {category_options}
+                    </select>
+                </div>
+            </div>
+            <div class="form-group">
+                <label class="col-sm-2 control-label">Suchen in</label>
+                <div class="col-sm-10">
+                    <div class="radio"><label><input type="radio" name="entry-type" value="offer"{offer_checked} /> Angeboten</label></div>
+                    <div class="radio"><label><input type="radio" name="entry-type" value="request"{request_checked} /> Anfragen</label></div>
+                    <div class="radio"><label><input type="radio" name="entry-type" value="both"{both_checked} /> beidem</label></div>
+                </div>
+            </div>
+            <div class="form-group">
+                <div class="col-sm-offset-2 col-sm-10">
+                    <button type="submit" class="btn btn-primary">{submit_label}</button>
+                </div>
+            </div>
+        </form>
+        "#,
+        action=action,
+        mail_field=if mail_field {
+            r#"
+            <div class="form-group">
+                <label for="mail" class="col-sm-2 control-label">E-Mail</label>
+                <div class="col-sm-10">
+                    <input type="email" class="form-control" name="mail" id="mail" />
+                </div>
+            </div>
+            "#
+        } else { "" },
+        keywords=util::escape_html(&query.keywords),
+        category_options=CATEGORIES.iter().map(|&(slug, name)| format!(r#"<option value="{}"{}>{}</option>"#, slug, if query.category.as_ref().map_or(false, |category| category == slug) { " selected" } else { "" }, name)).collect::<String>(),
+        offer_checked=if query.entry_type_str() == Some("offer") { " checked" } else { "" },
+        request_checked=if query.entry_type_str() == Some("request") { " checked" } else { "" },
+        both_checked=if query.entry_type.is_none() { " checked" } else { "" },
+        submit_label=submit_label
+    )
+}
+
+fn new_search_page_inner(form_error: Option<&'static str>, req: &mut Request) -> IronResult<Response> {
+    let is_admin = req.get::<IsAdmin>().unwrap_or(false);
+    // the form can be prefilled, e.g. from a category page
+    let query = match req.get_ref::<UrlEncodedQuery>() {
+        Ok(query_map) => Query::from_form(query_map).unwrap_or_default(),
+        Err(_) => Query::default()
+    };
+    Ok(Response::with((if form_error.is_some() { status::BadRequest } else { status::Ok }, "text/html".parse::<Mime>().unwrap(), util::page("/suchauftrag/neu", is_admin, &format!(
+        r#"
+        {error_message}
+        <h2>Neuer Suchauftrag</h2>
+        <p>Sobald ein passendes Angebot oder eine passende Anfrage eingestellt wird, schicken wir Ihnen eine E-Mail.</p>
+        {form}
+        "#,
+        error_message=if let Some(msg) = form_error { format!(r#"<div class="alert alert-danger"><strong>{}</strong> Bitte füllen Sie das Formular erneut aus.</div>"#, msg) } else { String::default() },
+        form=query_form("/suchauftrag/neu", &query, true, "Suchauftrag speichern")
+    )))))
+}
+
+pub fn new_search_page(req: &mut Request) -> IronResult<Response> {
+    new_search_page_inner(None, req)
+}
+
+fn add_search_inner(req: &mut Request) -> Result<Response, &'static str> {
+    let is_admin = req.get::<IsAdmin>().unwrap_or(false);
+    let (mail, query) = {
+        let form_data = try!(req.get_ref::<UrlEncodedBody>().map_err(|_| "Fehlender Formularinhalt."));
+        let mail = form_data.get("mail").and_then(|values| values.first()).map_or(String::default(), |mail| mail.trim().to_owned());
+        if !digest::is_valid_mail(&mail) { return Err("Ungültige Mailadresse."); }
+        (mail, try!(Query::from_form(form_data)))
+    };
+    let token = util::random_token(32);
+    let mut conn = try!(::db_connection().map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    let now = UTC::now();
+    try!(conn.query(format!("DELETE FROM saved_searches WHERE confirmed_at IS NULL AND created_at < {}", util::sql_timestamp(now - Duration::days(digest::CONFIRMATION_DAYS)))).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(conn.query(format!(
+        "INSERT INTO saved_searches (mail, entry_type, keywords, category, token, created_at) VALUES ({}, {}, {}, {}, {}, {})",
+        ::sql_escape(&mail),
+        query.entry_type_str().map_or("NULL".to_owned(), ::sql_escape),
+        ::sql_escape(&query.keywords),
+        query.category.as_ref().map_or("NULL".to_owned(), ::sql_escape),
+        ::sql_escape(&token),
+        util::sql_timestamp(now)
+    )).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(digest::send_mail(&mail, "WiW-Boerse: Bitte bestaetigen Sie Ihren Suchauftrag", &format!(
+        "Hallo,\n\nSie möchten per Mail benachrichtigt werden, wenn auf der WiW-Börse {description} eingestellt werden. Bitte bestätigen Sie dies über den folgenden Link:\n\n{base_url}/suchauftrag/{token}/bestaetigen\n\nWenn Sie das nicht waren, können Sie diese Mail ignorieren. Ohne Bestätigung wird der Suchauftrag nach {days} Tagen gelöscht.\n",
+        description=query.german_description(),
+        base_url=BASE_URL,
+        token=token,
+        days=digest::CONFIRMATION_DAYS
+    ), None).map_err(|e| error::logged(e, "Die Bestätigungsmail konnte nicht verschickt werden.")));
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/suchauftrag/neu", is_admin, &format!(
+        r#"
+        <div class="alert alert-success">Wir haben eine Mail an {} geschickt. Bitte öffnen Sie den Link darin, um den Suchauftrag zu bestätigen.</div>
+        <p><a href="/">Zurück zur Börse</a></p>
+        "#,
+        util::escape_html(&mail)
+    )))))
+}
+
+pub fn add_search(req: &mut Request) -> IronResult<Response> {
+    add_search_inner(req).or_else(|e| new_search_page_inner(Some(e), req))
+}
+
+fn token_param(req: &Request) -> IronResult<String> {
+    let err_msg = "Fehler beim Lesen des Links.";
+    Ok(try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg))).find("token").ok_or(Error::internal(err_msg))).to_owned())
+}
+
+fn search_page_inner(form_error: Option<&'static str>, req: &mut Request) -> IronResult<Response> {
+    let token = try!(token_param(req));
+    let mut conn = try!(::db_connection());
+    let search = try!(try!(SavedSearch::load(&token, &mut conn).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))).ok_or(Error::not_found("Dieser Suchauftrag existiert nicht oder wurde gelöscht.")));
+    Ok(Response::with((if form_error.is_some() { status::BadRequest } else { status::Ok }, "text/html".parse::<Mime>().unwrap(), util::page("/suchauftrag/neu", false, &format!(
+        r#"
+        {error_message}
+        <h2>Suchauftrag <a href="/suchauftrag/{token}/loeschen" class="btn btn-danger"><i class="fa fa-trash-o"></i> Löschen</a></h2>
+        <p>Benachrichtigungen über {description} gehen an {mail}.</p>
+        <h3>Ändern</h3>
+        {form}
+        "#,
+        error_message=if let Some(msg) = form_error { format!(r#"<div class="alert alert-danger"><strong>{}</strong> Die Änderungen wurden nicht gespeichert.</div>"#, msg) } else { String::default() },
+        token=search.token,
+        description=util::escape_html(search.query.german_description()),
+        mail=util::escape_html(&search.mail),
+        form=query_form(&format!("/suchauftrag/{}", search.token), &search.query, false, "Änderungen speichern")
+    )))))
+}
+
+pub fn search_page(req: &mut Request) -> IronResult<Response> {
+    search_page_inner(None, req)
+}
+
+fn edit_search_inner(req: &mut Request) -> Result<Response, &'static str> {
+    let token = try!(req.extensions.get::<Router>().and_then(|params| params.find("token")).ok_or("Fehler beim Lesen des Links.")).to_owned();
+    let query = {
+        let form_data = try!(req.get_ref::<UrlEncodedBody>().map_err(|_| "Fehlender Formularinhalt."));
+        try!(Query::from_form(form_data))
+    };
+    let mut conn = try!(::db_connection().map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(conn.query(format!(
+        "UPDATE saved_searches SET entry_type={}, keywords={}, category={} WHERE token={}",
+        query.entry_type_str().map_or("NULL".to_owned(), ::sql_escape),
+        ::sql_escape(&query.keywords),
+        query.category.as_ref().map_or("NULL".to_owned(), ::sql_escape),
+        ::sql_escape(&token)
+    )).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    Ok(Response::with((status::SeeOther, Header(headers::Location(format!("/suchauftrag/{}", token))))))
+}
+
+pub fn edit_search(req: &mut Request) -> IronResult<Response> {
+    edit_search_inner(req).or_else(|e| search_page_inner(Some(e), req))
+}
+
+pub fn confirm(req: &mut Request) -> IronResult<Response> {
+    let token = try!(token_param(req));
+    let mut conn = try!(::db_connection());
+    let search = try!(try!(SavedSearch::load(&token, &mut conn).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))).ok_or(Error::not_found("Dieser Link ist ungültig oder abgelaufen.")));
+    try!(conn.query(format!("UPDATE saved_searches SET confirmed_at={} WHERE token={} AND confirmed_at IS NULL", util::sql_timestamp(UTC::now()), ::sql_escape(&token))).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/suchauftrag/neu", false, &format!(
+        r#"<div class="alert alert-success">Der Suchauftrag ist bestätigt. Sie werden benachrichtigt, sobald {} eingestellt werden.</div><p><a href="/suchauftrag/{}">Suchauftrag verwalten</a></p>"#,
+        util::escape_html(search.query.german_description()),
+        search.token
+    )))))
+}
+
+/// Deletes a saved search. Works with both GET (link in the mail) and POST (one-click unsubscribe from the mail client).
+pub fn del_search(req: &mut Request) -> IronResult<Response> {
+    let token = try!(token_param(req));
+    let mut conn = try!(::db_connection());
+    let deleted = try!(conn.query(format!("DELETE FROM saved_searches WHERE token={}", ::sql_escape(&token))).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))).affected_rows() > 0;
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/suchauftrag/neu", false, if deleted {
+        r#"<div class="alert alert-success">Der Suchauftrag wurde gelöscht.</div>"#
+    } else {
+        r#"<div class="alert alert-info">Dieser Suchauftrag wurde bereits gelöscht.</div>"#
+    }))))
+}
@@ -0,0 +1,650 @@
+//! Export and import of offers, requests and notices as CSV or JSON, to restore backups and to migrate old spreadsheet lists.
+//!
+//! Both formats have the same fields, with empty values (or `null` in JSON) standing for missing ones. Exported CSV files are separated by `;` and start with a byte order mark, so spreadsheet programs open them correctly; imports may also be separated by `,`.
+//!
+//! An import is checked completely before anything is written, and nothing is written if any row is invalid or the dry run option is set. The rows are written in one transaction. Rows with an `id` replace the entry or notice with that number, restoring it if it was deleted. Rows without one are added as new entries. Photos and translations are not exported, and imported entries do not trigger saved search notifications.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::prelude::*;
+use std::str::FromStr;
+
+use chrono::prelude::*;
+
+use csv;
+
+use iron::status;
+use iron::mime::Mime;
+use iron::prelude::*;
+
+use mysql::prelude::*;
+
+use params;
+
+use router::Router;
+
+use serde_json;
+
+use audit;
+use db;
+use entry::{self, Entry};
+use error::{self, Error};
+use notice::{self, NoticeData, NoticePositions, Position, Severity};
+use util;
+
+/// Uploads larger than this are rejected without being read.
+const MAX_FILE_SIZE: u64 = 5 * 1024 * 1024;
+
+const ENTRY_FIELDS: &'static [&'static str] = &["id", "name", "description", "phone", "mail", "status", "category", "created_at"];
+const NOTICE_FIELDS: &'static [&'static str] = &["id", "text", "position", "visible_from", "visible_until", "severity", "priority", "html"];
+
+/// What is exported or imported. Also the first part of the export file names.
+#[derive(Clone, Copy)]
+enum Kind {
+    Entries(entry::Type),
+    Notices
+}
+
+impl Kind {
+    fn all() -> Vec<Kind> {
+        vec![Kind::Entries(entry::Type::Offer), Kind::Entries(entry::Type::Request), Kind::Notices]
+    }
+
+    fn from_str(s: &str) -> Option<Kind> {
+        Kind::all().into_iter().find(|kind| kind.url_part() == s)
+    }
+
+    fn url_part(&self) -> &'static str {
+        match *self {
+            Kind::Entries(entry_type) => entry_type.url_part(),
+            Kind::Notices => "notizen"
+        }
+    }
+
+    fn german_plural(&self) -> &'static str {
+        match *self {
+            Kind::Entries(entry_type) => entry_type.german_plural(),
+            Kind::Notices => "Notizen"
+        }
+    }
+
+    fn table(&self) -> &'static str {
+        match *self {
+            Kind::Entries(entry_type) => entry_type.table(),
+            Kind::Notices => "notices"
+        }
+    }
+
+    fn fields(&self) -> &'static [&'static str] {
+        match *self {
+            Kind::Entries(_) => ENTRY_FIELDS,
+            Kind::Notices => NOTICE_FIELDS
+        }
+    }
+
+    /// A field every import file must have, to catch files with the wrong kind or without a header row.
+    fn required_field(&self) -> &'static str {
+        match *self {
+            Kind::Entries(_) => "name",
+            Kind::Notices => "text"
+        }
+    }
+
+    fn audit_action(&self) -> audit::Action {
+        match *self {
+            Kind::Entries(entry_type) => entry_type.map(audit::Action::ImportOffers, audit::Action::ImportRequests),
+            Kind::Notices => audit::Action::ImportNotices
+        }
+    }
+}
+
+fn format_time(time: Option<DateTime<UTC>>) -> String {
+    time.map_or(String::default(), |time| time.to_rfc3339())
+}
+
+/// The values of all entries or notices which have not been deleted, in the order of `Kind::fields`.
+fn export_rows(kind: Kind, conn: &mut ::db::Conn) -> Result<Vec<Vec<String>>, ::db::Error> {
+    Ok(match kind {
+        Kind::Entries(entry_type) => try!(Entry::list(entry_type, conn)).into_iter().map(|entry| vec![
+            entry.id.to_string(),
+            entry.name,
+            entry.description,
+            entry.phone.unwrap_or_default(),
+            entry.mail.unwrap_or_default(),
+            entry.status.as_str().to_owned(),
+            entry.category.unwrap_or_default(),
+            format_time(entry.created_at)
+        ]).collect(),
+        Kind::Notices => try!(notice::all_notices(conn)).into_iter().map(|(id, notice)| vec![
+            id.to_string(),
+            notice.text.clone(),
+            notice.positions.sql_string(),
+            format_time(notice.visible_from),
+            format_time(notice.visible_until),
+            notice.severity.as_str().to_owned(),
+            notice.priority.to_string(),
+            ::db::sql_bool(notice.html).to_owned()
+        ]).collect()
+    })
+}
+
+fn to_csv(fields: &[&str], rows: &[Vec<String>]) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::WriterBuilder::new().delimiter(b';').from_writer("\u{feff}".as_bytes().to_vec());
+    try!(writer.write_record(fields));
+    for row in rows {
+        try!(writer.write_record(row));
+    }
+    writer.into_inner().map_err(|e| csv::Error::from(e.into_error()))
+}
+
+fn to_json(fields: &[&str], rows: &[Vec<String>]) -> serde_json::Value {
+    serde_json::Value::Array(rows.iter().map(|row| serde_json::Value::Object(fields.iter().zip(row).map(|(&field, value)| (field.to_owned(), if value.is_empty() {
+        serde_json::Value::Null
+    } else if field == "id" || field == "priority" {
+        value.parse::<i64>().map(serde_json::Value::from).unwrap_or_else(|_| serde_json::Value::String(value.clone()))
+    } else {
+        serde_json::Value::String(value.clone())
+    })).collect())).collect())
+}
+
+/// Downloads a file like `biete.csv` or `notizen.json`.
+pub fn export(req: &mut Request) -> IronResult<Response> {
+    let err_msg = "Fehler beim Lesen des Dateinamens.";
+    let file_name = try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg))).find("file").ok_or(Error::internal(err_msg))).to_owned();
+    let (kind, format) = {
+        let mut parts = file_name.splitn(2, '.');
+        (parts.next().and_then(Kind::from_str), parts.next().map(str::to_owned))
+    };
+    let kind = try!(kind.ok_or(Error::not_found("Diese Datei gibt es nicht.")));
+    let mut conn = try!(::db_connection());
+    let rows = try!(export_rows(kind, &mut conn).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    let (mime, body) = match format.as_ref().map(|format| &format[..]) {
+        Some("csv") => ("text/csv; charset=utf-8", try!(to_csv(kind.fields(), &rows).map_err(|e| Error::other(e, "Die CSV-Datei konnte nicht erstellt werden.")))),
+        Some("json") => ("application/json", try!(serde_json::to_vec_pretty(&to_json(kind.fields(), &rows)).map_err(|e| Error::other(e, "Die JSON-Datei konnte nicht erstellt werden.")))),
+        _ => { return Err(Error::not_found("Diese Datei gibt es nicht.").into()); }
+    };
+    let mut response = Response::with((status::Ok, mime.parse::<Mime>().unwrap(), body));
+    response.headers.set_raw("Content-Disposition", vec![format!("attachment; filename=\"{}\"", file_name).into_bytes()]);
+    Ok(response)
+}
+
+/// A row of an uploaded file, by lowercase field name.
+struct Record {
+    /// The line in a CSV file, or the position in a JSON array.
+    line: u64,
+    fields: BTreeMap<String, String>
+}
+
+impl Record {
+    fn get(&self, field: &str) -> &str {
+        self.fields.get(field).map_or("", |value| value.trim())
+    }
+}
+
+fn parse_csv(text: &str) -> Result<Vec<Record>, String> {
+    let header_line = text.lines().next().unwrap_or("");
+    let delimiter = if header_line.matches(',').count() > header_line.matches(';').count() { b',' } else { b';' };
+    let mut reader = csv::ReaderBuilder::new().delimiter(delimiter).from_reader(text.as_bytes());
+    let headers = try!(reader.headers().map_err(|e| format!("Die Kopfzeile konnte nicht gelesen werden: {}", e))).iter().map(|header| header.trim().to_lowercase()).collect::<Vec<_>>();
+    let mut records = Vec::default();
+    for result in reader.records() {
+        let record = try!(result.map_err(|e| format!("Die CSV-Datei konnte nicht gelesen werden: {}", e)));
+        // spreadsheet programs often export empty rows below the data
+        if record.iter().all(|value| value.trim().is_empty()) { continue; }
+        records.push(Record {
+            line: record.position().map_or(0, |position| position.line()),
+            fields: headers.iter().cloned().zip(record.iter().map(str::to_owned)).collect()
+        });
+    }
+    Ok(records)
+}
+
+fn parse_json(text: &str) -> Result<Vec<Record>, String> {
+    let rows = try!(serde_json::from_str::<Vec<BTreeMap<String, serde_json::Value>>>(text).map_err(|e| format!("Die JSON-Datei konnte nicht gelesen werden: {}", e)));
+    Ok(rows.into_iter().enumerate().map(|(i, row)| Record {
+        line: i as u64 + 1,
+        fields: row.into_iter().map(|(field, value)| (field.to_lowercase(), match value {
+            serde_json::Value::Null => String::default(),
+            serde_json::Value::String(value) => value,
+            value => value.to_string()
+        })).collect()
+    }).collect())
+}
+
+struct EntryRow {
+    entry_type: entry::Type,
+    id: Option<i32>,
+    name: String,
+    description: String,
+    /// Empty if missing, like `mail` and `category`.
+    phone: String,
+    mail: String,
+    status: entry::Status,
+    category: String,
+    created_at: DateTime<UTC>
+}
+
+/// A validated row, ready to be written.
+enum Row {
+    Entry(EntryRow),
+    Notice(Option<i32>, NoticeData)
+}
+
+impl Row {
+    fn id(&self) -> Option<i32> {
+        match *self {
+            Row::Entry(ref entry) => entry.id,
+            Row::Notice(id, _) => id
+        }
+    }
+}
+
+fn parse_id(record: &Record) -> Result<Option<i32>, String> {
+    match record.get("id") {
+        "" => Ok(None),
+        id => match i32::from_str(id) {
+            Ok(id) if id > 0 => Ok(Some(id)),
+            _ => Err(format!("Die Nummer „{}“ ist keine positive ganze Zahl.", id))
+        }
+    }
+}
+
+fn parse_time(record: &Record, field: &str) -> Result<Option<DateTime<UTC>>, String> {
+    match record.get(field) {
+        "" => Ok(None),
+        time => DateTime::parse_from_rfc3339(time).map(|time| Some(time.with_timezone(&UTC))).map_err(|_| format!("Ungültige Zeitangabe „{}“ in der Spalte {}. Bitte verwenden Sie das Format 2017-03-01T18:00:00+01:00.", time, field))
+    }
+}
+
+/// Checks a row. Status and category may also be given by their German names, as in old spreadsheet lists.
+fn validate(kind: Kind, record: &Record, now: DateTime<UTC>) -> Result<Row, String> {
+    let id = try!(parse_id(record));
+    match kind {
+        Kind::Entries(entry_type) => {
+            let name = record.get("name");
+            if name.is_empty() { return Err("Fehlender Name.".to_owned()); }
+            let description = record.get("description");
+            if description.is_empty() { return Err("Fehlende Beschreibung.".to_owned()); }
+            let phone = record.get("phone");
+            let mail = record.get("mail");
+            if phone.is_empty() && mail.is_empty() { return Err("Weder Telefonnummer noch E-Mail-Adresse angegeben.".to_owned()); }
+            let status = match record.get("status") {
+                "" => entry::Status::default(),
+                status => try!(entry::Status::all().into_iter().find(|candidate| candidate.as_str() == status || candidate.german_name() == status.to_lowercase()).ok_or(format!("Unbekannter Status „{}“.", status)))
+            };
+            let category = match record.get("category") {
+                "" => String::default(),
+                category => try!(entry::CATEGORIES.iter().find(|&&(slug, name)| slug == category || name == category).map(|&(slug, _)| slug.to_owned()).ok_or(format!("Unbekannte Kategorie „{}“.", category)))
+            };
+            Ok(Row::Entry(EntryRow {
+                entry_type: entry_type,
+                id: id,
+                name: name.to_owned(),
+                description: description.to_owned(),
+                phone: phone.to_owned(),
+                mail: mail.to_owned(),
+                status: status,
+                category: category,
+                created_at: try!(parse_time(record, "created_at")).unwrap_or(now)
+            }))
+        }
+        Kind::Notices => {
+            let text = record.get("text");
+            if text.is_empty() { return Err("Fehlender Text.".to_owned()); }
+            let positions = NoticePositions::from(record.get("position"));
+            if !positions.any() { return Err(format!("Keine gültige Position in „{}“.", record.get("position"))); }
+            let severity = match record.get("severity") {
+                "" => Severity::default(),
+                severity => try!(Severity::from_str(severity).ok_or(format!("Unbekannte Art „{}“.", severity)))
+            };
+            let priority = match record.get("priority") {
+                "" => 0,
+                priority => try!(i32::from_str(priority).map_err(|_| format!("Die Priorität „{}“ ist keine ganze Zahl.", priority)))
+            };
+            let visible_from = try!(parse_time(record, "visible_from"));
+            let visible_until = try!(parse_time(record, "visible_until"));
+            if let (Some(from), Some(until)) = (visible_from, visible_until) {
+                if until <= from { return Err("Das Ende des Anzeigezeitraums muss nach dem Beginn liegen.".to_owned()); }
+            }
+            let html = match record.get("html") {
+                "1" => true,
+                "" | "0" => false,
+                html => { return Err(format!("Ungültiger Wert „{}“ für html, erlaubt sind 0 und 1.", html)); }
+            };
+            Ok(Row::Notice(id, NoticeData { text: text.to_owned(), positions: positions, visible_from: visible_from, visible_until: visible_until, severity: severity, priority: priority, html: html }))
+        }
+    }
+}
+
+/// The ids which already exist in the table, including deleted ones.
+fn existing_ids(kind: Kind, ids: &HashSet<i32>, conn: &mut ::db::Conn) -> Result<HashSet<i32>, ::db::Error> {
+    let mut existing = HashSet::new();
+    if ids.is_empty() { return Ok(existing); }
+    for row in try!(conn.query(format!("SELECT id FROM {} WHERE id IN ({})", kind.table(), ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", ")))) {
+        let values = try!(row);
+        existing.insert(i32::from_value(values[0].clone()));
+    }
+    Ok(existing)
+}
+
+/// Writes a validated row and returns the id of the entry or notice.
+fn write(row: &Row, conn: &mut ::db::Conn) -> Result<i32, ::db::Error> {
+    match *row {
+        Row::Entry(ref entry) => {
+            let values = format!(
+                "{}, {}, {}, {}, {}, {}, {}",
+                ::sql_escape(&entry.name),
+                ::sql_escape(&entry.description),
+                ::sql_escape_nullable(&entry.phone),
+                ::sql_escape_nullable(&entry.mail),
+                ::sql_escape(entry.status.as_str()),
+                util::sql_timestamp(entry.created_at),
+                ::sql_escape_nullable(&entry.category)
+            );
+            match entry.id {
+                Some(id) => {
+                    try!(conn.query(format!(
+                        "INSERT INTO {} (id, name, description, phone, mail, status, created_at, category) VALUES ({}, {}) {}, deleted_at=NULL",
+                        entry.entry_type.table(),
+                        id,
+                        values,
+                        db::upsert_clause("id", &["name", "description", "phone", "mail", "status", "created_at", "category"])
+                    )));
+                    Ok(id)
+                }
+                None => Ok(try!(conn.query(format!("INSERT INTO {} (name, description, phone, mail, status, created_at, category) VALUES ({})", entry.entry_type.table(), values))).last_insert_id() as i32)
+            }
+        }
+        Row::Notice(Some(id), ref notice) => {
+            try!(notice.upsert(id, conn));
+            Ok(id)
+        }
+        Row::Notice(None, ref notice) => notice.insert(conn)
+    }
+}
+
+/// The result of an import, with the outcome or error message of each row.
+struct Report {
+    kind: Kind,
+    dry_run: bool,
+    rows: Vec<(u64, Result<String, String>)>
+}
+
+impl Report {
+    fn num_errors(&self) -> usize {
+        self.rows.iter().filter(|&&(_, ref result)| result.is_err()).count()
+    }
+}
+
+fn import_inner(req: &mut Request) -> Result<Report, String> {
+    let actor = audit::actor(req);
+    let (kind, dry_run, text) = {
+        let form_data = try!(req.get_ref::<params::Params>().map_err(|_| "Fehlender Formularinhalt.".to_owned()));
+        let kind = try!(Kind::from_str(::param_str(form_data, "kind")).ok_or("Bitte wählen Sie aus, was importiert werden soll.".to_owned()));
+        let file = match form_data.find(&["file"]) {
+            Some(&params::Value::File(ref file)) if file.size > 0 => file,
+            _ => { return Err("Bitte wählen Sie eine Datei aus.".to_owned()); }
+        };
+        if file.size > MAX_FILE_SIZE { return Err("Die Datei ist zu groß. Es können höchstens 5 MB auf einmal importiert werden.".to_owned()); }
+        let mut text = String::default();
+        try!(File::open(&file.path).and_then(|mut f| f.read_to_string(&mut text)).map_err(|_| "Die Datei konnte nicht gelesen werden. Bitte speichern Sie sie mit der Kodierung UTF-8.".to_owned()));
+        (kind, form_data.find(&["dry_run"]).is_some(), text)
+    };
+    let text = text.trim_left_matches('\u{feff}');
+    let records = try!(if text.trim_left().starts_with('[') { parse_json(text) } else { parse_csv(text) });
+    if records.is_empty() { return Err("Die Datei enthält keine Datensätze.".to_owned()); }
+    if !records.iter().any(|record| record.fields.contains_key(kind.required_field())) {
+        return Err(format!("Die Datei hat keine Spalte „{}“. Erwartet werden die Spalten {}.", kind.required_field(), kind.fields().join(", ")));
+    }
+    let now = UTC::now();
+    let mut rows = records.iter().map(|record| (record.line, validate(kind, record, now))).collect::<Vec<_>>();
+    let mut ids = HashSet::new();
+    for &mut (_, ref mut row) in rows.iter_mut() {
+        let id = row.as_ref().ok().and_then(Row::id);
+        if let Some(id) = id {
+            if !ids.insert(id) { *row = Err(format!("Die Nummer {} kommt mehrfach vor.", id)); }
+        }
+    }
+    let mut conn = try!(::db_connection().map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.".to_owned())));
+    let existing = try!(existing_ids(kind, &ids, &mut conn).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.".to_owned())));
+    let write_rows = !dry_run && rows.iter().all(|&(_, ref row)| row.is_ok());
+    if write_rows {
+        try!(conn.query("BEGIN").map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.".to_owned())));
+    }
+    let mut report = Report { kind: kind, dry_run: dry_run, rows: Vec::default() };
+    let (mut num_inserted, mut num_updated) = (0, 0);
+    for (line, row) in rows {
+        report.rows.push((line, match row {
+            Ok(row) => {
+                let id = if write_rows {
+                    match write(&row, &mut conn) {
+                        Ok(id) => Some(id),
+                        Err(e) => {
+                            error::log(&e);
+                            conn.rollback();
+                            return Err(format!("Fehler beim Speichern von Zeile {}.", line));
+                        }
+                    }
+                } else { row.id() };
+                Ok(match row.id() {
+                    Some(old_id) if existing.contains(&old_id) => {
+                        num_updated += 1;
+                        format!("aktualisiert (Nr. {})", old_id)
+                    }
+                    _ => {
+                        num_inserted += 1;
+                        id.map_or("neu".to_owned(), |id| format!("neu (Nr. {})", id))
+                    }
+                })
+            }
+            Err(e) => Err(e)
+        }));
+    }
+    if write_rows {
+        let committed = audit::record(&mut conn, &actor, kind.audit_action(), 0, &json!({"inserted": num_inserted, "updated": num_updated})).is_ok() && conn.query("COMMIT").is_ok();
+        if !committed {
+            conn.rollback();
+            return Err("Fehler beim Zugriff auf die Datenbank.".to_owned());
+        }
+    }
+    Ok(report)
+}
+
+fn data_page_inner(report: Option<Report>, form_error: Option<String>, req: &mut Request) -> IronResult<Response> {
+    let mut conn = try!(::db_connection());
+    let failed = form_error.is_some() || report.as_ref().map_or(false, |report| report.num_errors() > 0);
+    Ok(Response::with((if failed { status::BadRequest } else { status::Ok }, "text/html".parse::<Mime>().unwrap(), util::page("/admin/daten", true, &format!(
+        r#"
+        {notices}
+        {message}
+        <h2>Export</h2>
+        <p>Die Dateien enthalten alle Einträge, die nicht gelöscht wurden. Bilder und Übersetzungen werden nicht exportiert.</p>
+        <table class="table table-responsive">
+            <tbody>
+                {export_rows}
+            </tbody>
+        </table>
+        <h2>Import</h2>
+        <p>Zum Wiederherstellen einer Sicherung oder zum Übernehmen alter Listen. Die Datei muss die gleichen Spalten wie ein Export haben: für Angebote und Anfragen <code>{entry_fields}</code>, für Notizen <code>{notice_fields}</code>. Zeilen mit Nummer (<code>id</code>) ersetzen den Eintrag mit dieser Nummer, Zeilen ohne Nummer werden neu hinzugefügt. Enthält die Datei einen Fehler, wird nichts importiert.</p>
+        <form class="form-horizontal" action="/admin/daten/import" method="post" enctype="multipart/form-data">
+            <div class="form-group">
+                <label for="kind" class="col-sm-2 control-label">Inhalt</label>
+                <div class="col-sm-10">
+                    <select class="form-control" name="kind" id="kind">
+                        {kind_options}
+                    </select>
+                </div>
+            </div>
+            <div class="form-group">
+                <label for="file" class="col-sm-2 control-label">Datei</label>
+                <div class="col-sm-10">
+                    <input type="file" name="file" id="file" accept=".csv,.json,text/csv,application/json" />
+                    <p class="help-block">CSV (mit <code>;</code> oder <code>,</code> getrennt, UTF-8) oder JSON, höchstens 5 MB.</p>
+                </div>
+            </div>
+            <div class="form-group">
+                <div class="col-sm-offset-2 col-sm-10">
+                    <div class="checkbox"><label><input type="checkbox" name="dry_run"{dry_run_checked} /> Probelauf: nur prüfen, nichts speichern</label></div>
+                </div>
+            </div>
+            <div class="form-group">
+                <div class="col-sm-offset-2 col-sm-10">
+                    <button type="submit" class="btn btn-primary">Hochladen</button>
+                </div>
+            </div>
+        </form>
+        {report}
+        "#,
+        notices=try!(notice::format_notices(&Position::Admin, req, &mut conn, true).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))),
+        message=match (&report, form_error) {
+            (_, Some(msg)) => format!(r#"<div class="alert alert-danger"><strong>{}</strong> Es wurde nichts importiert.</div>"#, util::escape_html(msg)),
+            (&Some(ref report), None) => match report.num_errors() {
+                0 if report.dry_run => format!(r#"<div class="alert alert-info"><strong>Probelauf:</strong> Alle {} Zeilen sind gültig. Es wurde nichts gespeichert. Laden Sie die Datei ohne Probelauf erneut hoch, um sie zu importieren.</div>"#, report.rows.len()),
+                0 => format!(r#"<div class="alert alert-success">{} {} wurden importiert.</div>"#, report.rows.len(), report.kind.german_plural()),
+                num_errors => format!(r#"<div class="alert alert-danger"><strong>{} von {} Zeilen sind fehlerhaft.</strong> Es wurde nichts importiert. Bitte korrigieren Sie die Datei und laden Sie sie erneut hoch.</div>"#, num_errors, report.rows.len())
+            },
+            (&None, None) => String::default()
+        },
+        export_rows=Kind::all().into_iter().map(|kind| format!(
+            r#"<tr><td>{0}</td><td><a href="/admin/daten/{1}.csv"><i class="fa fa-download"></i> CSV</a></td><td><a href="/admin/daten/{1}.json"><i class="fa fa-download"></i> JSON</a></td></tr>"#,
+            kind.german_plural(),
+            kind.url_part()
+        )).collect::<String>(),
+        entry_fields=ENTRY_FIELDS.join(";"),
+        notice_fields=NOTICE_FIELDS.join(";"),
+        kind_options=Kind::all().into_iter().map(|kind| format!(
+            r#"<option value="{}"{}>{}</option>"#,
+            kind.url_part(),
+            if report.as_ref().map_or(false, |report| report.kind.url_part() == kind.url_part()) { " selected" } else { "" },
+            kind.german_plural()
+        )).collect::<String>(),
+        dry_run_checked=if report.as_ref().map_or(true, |report| report.dry_run) { " checked" } else { "" },
+        report=match report {
+            Some(ref report) => format!(
+                r#"
+        <h3>Ergebnis</h3>
+        <table class="table table-responsive">
+            <thead>
+                <tr>
+                    <th>Zeile</th>
+                    <th>Ergebnis</th>
+                </tr>
+            </thead>
+            <tbody>
+                {}
+            </tbody>
+        </table>
+                "#,
+                report.rows.iter().map(|&(line, ref result)| match *result {
+                    Ok(ref outcome) => format!("<tr><td>{}</td><td>{}</td></tr>", line, outcome),
+                    Err(ref e) => format!(r#"<tr class="danger"><td>{}</td><td>Fehler: {}</td></tr>"#, line, util::escape_html(e))
+                }).collect::<String>()
+            ),
+            None => String::default()
+        }
+    )))))
+}
+
+pub fn data_page(req: &mut Request) -> IronResult<Response> {
+    data_page_inner(None, None, req)
+}
+
+pub fn import(req: &mut Request) -> IronResult<Response> {
+    match import_inner(req) {
+        Ok(report) => data_page_inner(Some(report), None, req),
+        Err(e) => data_page_inner(None, Some(e), req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::prelude::*;
+
+    use entry;
+    use notice::Severity;
+
+    use super::{Kind, Record, Row, parse_csv, parse_json, validate};
+
+    fn record(fields: &[(&str, &str)]) -> Record {
+        Record { line: 2, fields: fields.iter().map(|&(field, value)| (field.to_owned(), value.to_owned())).collect() }
+    }
+
+    #[test]
+    fn parse_csv_detects_semicolons() {
+        let records = parse_csv("id;name;description\n1;Anna;Stuhl, gut erhalten\n").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].get("id"), "1");
+        assert_eq!(records[0].get("description"), "Stuhl, gut erhalten");
+    }
+
+    #[test]
+    fn parse_csv_detects_commas() {
+        let records = parse_csv("ID, Name ,Description\n,Anna,\"Stuhl; alt\"\n").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].get("name"), "Anna");
+        assert_eq!(records[0].get("description"), "Stuhl; alt");
+    }
+
+    #[test]
+    fn parse_csv_skips_empty_rows() {
+        let records = parse_csv("name;mail\nAnna;anna@example.org\n;\n").unwrap();
+        assert_eq!(records.len(), 1);
+    }
+
+    #[test]
+    fn parse_json_reads_null_and_numbers() {
+        let records = parse_json(r#"[{"id": 3, "Text": "Hallo", "visible_from": null}]"#).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].line, 1);
+        assert_eq!(records[0].get("id"), "3");
+        assert_eq!(records[0].get("text"), "Hallo");
+        assert_eq!(records[0].get("visible_from"), "");
+    }
+
+    #[test]
+    fn validate_accepts_german_names() {
+        let now = UTC::now();
+        match validate(Kind::Entries(entry::Type::Offer), &record(&[("name", "Anna"), ("description", "Stuhl"), ("mail", "anna@example.org"), ("status", "Reserviert"), ("category", "Möbel")]), now) {
+            Ok(Row::Entry(row)) => {
+                assert_eq!(row.id, None);
+                assert_eq!(row.status, entry::Status::Reserved);
+                assert_eq!(row.category, "moebel");
+                assert_eq!(row.created_at, now);
+            }
+            _ => panic!("the entry should be valid")
+        }
+    }
+
+    #[test]
+    fn validate_rejects_invalid_entries() {
+        let kind = Kind::Entries(entry::Type::Request);
+        assert!(validate(kind, &record(&[("name", "Anna"), ("description", "Stuhl")]), UTC::now()).is_err());
+        assert!(validate(kind, &record(&[("id", "-1"), ("name", "Anna"), ("description", "Stuhl"), ("phone", "123")]), UTC::now()).is_err());
+        assert!(validate(kind, &record(&[("name", "Anna"), ("description", "Stuhl"), ("phone", "123"), ("status", "verschenkt")]), UTC::now()).is_err());
+    }
+
+    #[test]
+    fn validate_reads_notices() {
+        match validate(Kind::Notices, &record(&[("id", "4"), ("text", "<b>Hinweis</b>"), ("position", "index,print"), ("severity", "warning"), ("priority", "2"), ("html", "1")]), UTC::now()) {
+            Ok(Row::Notice(id, notice)) => {
+                assert_eq!(id, Some(4));
+                assert_eq!(notice.positions.sql_string(), "index,print");
+                assert_eq!(notice.severity, Severity::Warning);
+                assert_eq!(notice.priority, 2);
+                assert!(notice.html);
+            }
+            _ => panic!("the notice should be valid")
+        }
+        match validate(Kind::Notices, &record(&[("text", "Hinweis"), ("position", "all")]), UTC::now()) {
+            Ok(Row::Notice(None, notice)) => assert!(!notice.html),
+            _ => panic!("the notice should be valid")
+        }
+    }
+
+    #[test]
+    fn validate_rejects_invalid_notices() {
+        assert!(validate(Kind::Notices, &record(&[("text", "Hinweis"), ("position", "all"), ("html", "ja")]), UTC::now()).is_err());
+        assert!(validate(Kind::Notices, &record(&[("text", "Hinweis"), ("position", "unbekannt")]), UTC::now()).is_err());
+        assert!(validate(Kind::Notices, &record(&[("text", "Hinweis"), ("position", "all"), ("visible_from", "2017-03-02T18:00:00+01:00"), ("visible_until", "2017-03-01T18:00:00+01:00")]), UTC::now()).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//! Deleted entries and notices are only marked as deleted (column `deleted_at`) and can be restored until they are purged after `trash_retention_days`. Photos and translations of purged entries are deleted along with them.
+
+use std::str::FromStr;
+use std::thread;
+use std::time;
+
+use chrono::Duration;
+use chrono::prelude::*;
+
+use iron::status;
+use iron::mime::Mime;
+use iron::prelude::*;
+
+use mysql::prelude::*;
+
+use router::Router;
+
+use serde_json;
+
+use audit;
+use entry;
+use error::Error;
+use logging;
+use notice::{self, Position};
+use photo;
+use util::{self, CONFIG};
+
+const TABLES: [&'static str; 3] = ["offers", "requests", "notices"];
+
+/// Permanently deletes everything that has been in the trash for longer than the retention period.
+pub fn purge(conn: &mut ::db::Conn) -> Result<(), ::db::Error> {
+    let cutoff = util::sql_timestamp(UTC::now() - Duration::days(CONFIG.trash_retention_days));
+    for &entry_type in &[entry::Type::Offer, entry::Type::Request] {
+        let ids = try!(conn.query(format!("SELECT id FROM {} WHERE deleted_at < {}", entry_type.table(), cutoff))).map(|row| row.map(|values| i32::from_value(values[0].clone()))).collect::<Result<Vec<_>, _>>();
+        for id in try!(ids) {
+            try!(photo::delete(entry_type, id, conn));
+            try!(entry::delete_translations(entry_type, id, conn));
+        }
+    }
+    for table in &TABLES {
+        try!(conn.query(format!("DELETE FROM {} WHERE deleted_at < {}", table, cutoff)));
+    }
+    Ok(())
+}
+
+/// Runs `purge` once an hour. Meant to be run in a background thread.
+pub fn purge_loop() {
+    loop {
+        if let Err(e) = ::db::connection().and_then(|mut conn| purge(&mut conn)) {
+            logging::log_message("error", &format!("failed to purge the trash: {}", e));
+        }
+        thread::sleep(time::Duration::from_secs(60 * 60));
+    }
+}
+
+fn format_deleted_at(deleted_at: NaiveDateTime) -> String {
+    let deleted_at = util::berlin_time(deleted_at);
+    format!("{} (endgültige Löschung am {})", deleted_at.format("%d.%m.%Y %H:%M"), (deleted_at + Duration::days(CONFIG.trash_retention_days)).format("%d.%m.%Y"))
+}
+
+fn format_deleted_entries(entry_type: entry::Type, conn: &mut ::db::Conn) -> Result<String, ::db::Error> {
+    let mut rows = String::default();
+    for row in try!(conn.query(format!("SELECT id, name, description, deleted_at FROM {} WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC", entry_type.table()))) {
+        let values = try!(row);
+        let id = i32::from_value(values[0].clone());
+        rows.push_str(&format!(
+            r#"
+<tr>
+    <td>{name}</td>
+    <td><div style="float: right;"><a href="/{url_part}/{id}/wiederherstellen" class="btn btn-success"><i class="fa fa-undo"></i> Wiederherstellen</a></div>{description}</td>
+    <td>{deleted_at}</td>
+</tr>
+            "#,
+            name=util::escape_html(String::from_value(values[1].clone())),
+            url_part=entry_type.url_part(),
+            id=id,
+            description=util::escape_html(String::from_value(values[2].clone())).replace("\n", "<br />"),
+            deleted_at=format_deleted_at(NaiveDateTime::from_value(values[3].clone()))
+        ));
+    }
+    Ok(if rows.is_empty() {
+        format!(r#"<tr><td colspan="3" style="color: gray; font-style: italic;">Keine gelöschten {}.</td></tr>"#, entry_type.german_plural())
+    } else {
+        rows
+    })
+}
+
+fn format_deleted_notices(conn: &mut ::db::Conn) -> Result<String, ::db::Error> {
+    let mut rows = String::default();
+    for row in try!(conn.query("SELECT id, text, deleted_at FROM notices WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC")) {
+        let values = try!(row);
+        rows.push_str(&format!(
+            r#"
+<tr>
+    <td><div style="float: right;"><a href="/notiz/{id}/wiederherstellen" class="btn btn-success"><i class="fa fa-undo"></i> Wiederherstellen</a></div>{text}</td>
+    <td>{deleted_at}</td>
+</tr>
+            "#,
+            id=i32::from_value(values[0].clone()),
+            text=util::escape_html(String::from_value(values[1].clone())),
+            deleted_at=format_deleted_at(NaiveDateTime::from_value(values[2].clone()))
+        ));
+    }
+    Ok(if rows.is_empty() {
+        r#"<tr><td colspan="2" style="color: gray; font-style: italic;">Keine gelöschten Notizen.</td></tr>"#.to_owned()
+    } else {
+        rows
+    })
+}
+
+pub fn trash_page(req: &mut Request) -> IronResult<Response> {
+    let mut conn = try!(::db_connection());
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/admin/papierkorb", true, &format!(
+        r#"
+        {notices}
+        <h2>Papierkorb</h2>
+        <p>Gelöschte Angebote, Anfragen und Notizen werden nach {retention_days} Tagen endgültig gelöscht.</p>
+        <h3>Angebote</h3>
+        <table class="table table-responsive">
+            <thead>
+                <tr>
+                    <th>Eingestellt von</th>
+                    <th>Beschreibung</th>
+                    <th>Gelöscht am</th>
+                </tr>
+            </thead>
+            <tbody>
+                {offers}
+            </tbody>
+        </table>
+        <h3>Anfragen</h3>
+        <table class="table table-responsive">
+            <thead>
+                <tr>
+                    <th>Eingestellt von</th>
+                    <th>Beschreibung</th>
+                    <th>Gelöscht am</th>
+                </tr>
+            </thead>
+            <tbody>
+                {requests}
+            </tbody>
+        </table>
+        <h3>Notizen</h3>
+        <table class="table table-responsive">
+            <thead>
+                <tr>
+                    <th>Text</th>
+                    <th>Gelöscht am</th>
+                </tr>
+            </thead>
+            <tbody>
+                {deleted_notices}
+            </tbody>
+        </table>
+        "#,
+        notices=try!(notice::format_notices(&Position::Admin, req, &mut conn, true).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))),
+        retention_days=CONFIG.trash_retention_days,
+        offers=try!(format_deleted_entries(entry::Type::Offer, &mut conn).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))),
+        requests=try!(format_deleted_entries(entry::Type::Request, &mut conn).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))),
+        deleted_notices=try!(format_deleted_notices(&mut conn).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")))
+    )))))
+}
+
+/// Restores an entry or notice from the trash and records it in the audit log, with `snapshot` loading the restored data.
+fn restore<F: Fn(i32, &mut ::db::Conn) -> Result<serde_json::Value, ::db::Error>>(table: &str, action: audit::Action, success_msg: &str, snapshot: F, req: &mut Request) -> IronResult<Response> {
+    let actor = audit::actor(req);
+    let mut conn = try!(::db_connection());
+    let err_msg = "Fehler beim Lesen der Nummer.";
+    let id_str = try!(try!(req.extensions.get::<Router>().ok_or(Error::internal(err_msg))).find("id").ok_or(Error::internal(err_msg)));
+    let id = try!(i32::from_str(id_str).map_err(|e| Error::invalid(e, format!("Die Nummer {:?} ist keine Nummer.", id_str))));
+    let restored = try!(conn.query(format!("UPDATE {} SET deleted_at=NULL WHERE id={} AND deleted_at IS NOT NULL", table, id)).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank."))).affected_rows() > 0;
+    if !restored {
+        return Err(Error::not_found("Dieser Eintrag ist nicht im Papierkorb.").into());
+    }
+    let snapshot = try!(snapshot(id, &mut conn).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(audit::record(&mut conn, &actor, action, id, &snapshot).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/admin/papierkorb", true, &format!(r#"<div class="alert alert-success">{} <a href="/admin/papierkorb">Zurück zum Papierkorb</a></div>"#, success_msg)))))
+}
+
+pub fn restore_offer(req: &mut Request) -> IronResult<Response> {
+    restore("offers", audit::Action::RestoreOffer, "Das Angebot wurde wiederhergestellt.", |id, conn| ::entry_snapshot(entry::Type::Offer, id, conn), req)
+}
+
+pub fn restore_request(req: &mut Request) -> IronResult<Response> {
+    restore("requests", audit::Action::RestoreRequest, "Die Anfrage wurde wiederhergestellt.", |id, conn| ::entry_snapshot(entry::Type::Request, id, conn), req)
+}
+
+pub fn restore_notice(req: &mut Request) -> IronResult<Response> {
+    restore("notices", audit::Action::RestoreNotice, "Die Notiz wurde wiederhergestellt.", notice::notice_snapshot, req)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::prelude::*;
+
+    use mysql::prelude::*;
+
+    use migrate;
+    use util;
+
+    use super::purge;
+
+    #[test]
+    fn purges_only_expired_items() {
+        let mut conn = migrate::test_conn();
+        let now = util::sql_timestamp(UTC::now());
+        conn.query("INSERT INTO offers (name, description, phone, deleted_at) VALUES ('Alt', 'Stuhl', '123', '2000-01-01 00:00:00')").unwrap();
+        conn.query(format!("INSERT INTO offers (name, description, phone, deleted_at) VALUES ('Neu', 'Tisch', '123', {})", now)).unwrap();
+        conn.query("INSERT INTO offers (name, description, phone) VALUES ('Aktiv', 'Bank', '123')").unwrap();
+        conn.query("INSERT INTO entry_translations (entry_table, entry_id, locale, description) VALUES ('offers', 1, 'en', 'Chair')").unwrap();
+        conn.query("INSERT INTO notices (position, text, deleted_at) VALUES ('index', 'Alt', '2000-01-01 00:00:00')").unwrap();
+        purge(&mut conn).unwrap();
+        let names = conn.query("SELECT name FROM offers ORDER BY id").unwrap().map(|row| String::from_value(row.unwrap()[0].clone())).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Neu", "Aktiv"]);
+        assert_eq!(conn.query("SELECT id FROM entry_translations").unwrap().count(), 0);
+        assert_eq!(conn.query("SELECT id FROM notices").unwrap().count(), 0);
+    }
+}
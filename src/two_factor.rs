@@ -0,0 +1,416 @@
+//! Optional TOTP (RFC 6238) second factor for admin and coordinator accounts.
+//!
+//! The password is still checked via HTTP basic auth on every request. After entering a code, the browser gets a session cookie that counts as the second factor until it expires. The sessions are only kept in memory, so restarting the server logs out all admins, who then have to enter a code again.
+//!
+//! After `MAX_FAILED_ATTEMPTS` wrong codes in a row, logging in with the second factor is locked for `LOCKOUT_MINUTES`. The lock applies to the account rather than the client's address, since all requests reach the Börse through the web server. A TOTP code is only accepted for a later time step than the last accepted one, so it cannot be used twice.
+//!
+//! Uses the tables `two_factor` (`account`, `secret`, `pending_secret`, `last_counter`) and `two_factor_recovery` (`account`, `code_hash`).
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use base32::{self, Alphabet};
+
+use chrono::Duration;
+use chrono::prelude::*;
+
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha1::Sha1;
+use crypto::sha2::Sha256;
+
+use iron::{headers, status};
+use iron::mime::Mime;
+use iron::modifiers::Header;
+use iron::prelude::*;
+use iron::typemap::Key;
+
+use mysql::prelude::*;
+
+use qrcode::QrCode;
+use qrcode::render::svg;
+
+use rand::{self, Rng};
+
+use urlencoded::UrlEncodedBody;
+
+use db;
+use error::{self, Error};
+use util::{self, IsTls, admin_account};
+
+const COOKIE_NAME: &'static str = "wiw_2fa";
+const ISSUER: &'static str = "WiW-Börse";
+const LOCKOUT_MINUTES: i64 = 15;
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+const RECOVERY_CODES: usize = 10;
+const SECRET_ALPHABET: Alphabet = Alphabet::RFC4648 { padding: false };
+const SESSION_HOURS: i64 = 12;
+const TIME_STEP: i64 = 30;
+
+lazy_static! {
+    /// Maps session tokens to account names and expiry times.
+    static ref SESSIONS: Mutex<HashMap<String, (String, DateTime<UTC>)>> = Mutex::new(HashMap::default());
+    /// Maps account names to the number of wrong codes in a row and the time of the last one.
+    static ref FAILED_ATTEMPTS: Mutex<HashMap<String, (u32, DateTime<UTC>)>> = Mutex::new(HashMap::default());
+}
+
+fn totp(secret: &[u8], counter: u64) -> u32 {
+    let mut counter_bytes = [0; 8];
+    for i in 0..8 {
+        counter_bytes[7 - i] = (counter >> (8 * i)) as u8;
+    }
+    let mut hmac = Hmac::new(Sha1::new(), secret);
+    hmac.input(&counter_bytes);
+    let digest = hmac.result();
+    let digest = digest.code();
+    let offset = (digest[19] & 0xf) as usize;
+    let code = ((digest[offset] as u32 & 0x7f) << 24) | ((digest[offset + 1] as u32) << 16) | ((digest[offset + 2] as u32) << 8) | digest[offset + 3] as u32;
+    code % 1_000_000
+}
+
+/// Returns the time step the code belongs to, if it is valid. Accepts codes from the previous and next time step as well, to allow for clock drift.
+fn verify_totp(secret: &str, code: &str) -> Option<i64> {
+    verify_totp_at(secret, code, UTC::now().timestamp())
+}
+
+/// Like `verify_totp`, at the given Unix time.
+fn verify_totp_at(secret: &str, code: &str, time: i64) -> Option<i64> {
+    let secret = match base32::decode(SECRET_ALPHABET, secret) {
+        Some(secret) => secret,
+        None => { return None; }
+    };
+    if code.len() != 6 { return None; }
+    let code = match u32::from_str(code) {
+        Ok(code) => code,
+        Err(_) => { return None; }
+    };
+    let counter = time / TIME_STEP;
+    (-1..2).map(|skew| counter + skew).find(|&counter| totp(&secret, counter as u64) == code)
+}
+
+/// Records the time step of an accepted code. Returns `false` if a code for the same or a later time step has already been used.
+fn use_counter(conn: &mut ::db::Conn, account: &str, counter: i64) -> Result<bool, ::db::Error> {
+    let result = try!(conn.query(format!("UPDATE two_factor SET last_counter={0} WHERE account={1} AND (last_counter IS NULL OR last_counter < {0})", counter, ::sql_escape(account))));
+    Ok(result.affected_rows() > 0)
+}
+
+fn is_locked(account: &str) -> bool {
+    let mut failed_attempts = FAILED_ATTEMPTS.lock().unwrap();
+    let now = UTC::now();
+    failed_attempts.retain(|_, &mut (_, last_attempt)| last_attempt + Duration::minutes(LOCKOUT_MINUTES) > now);
+    failed_attempts.get(account).map_or(false, |&(count, _)| count >= MAX_FAILED_ATTEMPTS)
+}
+
+fn record_failed_attempt(account: &str) {
+    let mut failed_attempts = FAILED_ATTEMPTS.lock().unwrap();
+    let entry = failed_attempts.entry(account.to_owned()).or_insert((0, UTC::now()));
+    *entry = (entry.0 + 1, UTC::now());
+}
+
+fn new_secret() -> String {
+    let mut secret = [0; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    base32::encode(SECRET_ALPHABET, &secret)
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input_str(&code.trim().to_lowercase());
+    hasher.result_str()
+}
+
+/// Returns the confirmed and the pending (not yet confirmed) secret of the account.
+fn secrets(conn: &mut ::db::Conn, account: &str) -> Result<(Option<String>, Option<String>), ::db::Error> {
+    for row in try!(conn.query(format!("SELECT secret, pending_secret FROM two_factor WHERE account={}", ::sql_escape(account)))) {
+        let values = try!(row);
+        return Ok((Option::<String>::from_value(values[0].clone()), Option::<String>::from_value(values[1].clone())));
+    }
+    Ok((None, None))
+}
+
+/// Recovery codes can only be used once, so a matching code is deleted.
+fn use_recovery_code(conn: &mut ::db::Conn, account: &str, code: &str) -> Result<bool, ::db::Error> {
+    let result = try!(conn.query(format!("DELETE FROM two_factor_recovery WHERE account={} AND code_hash={}", ::sql_escape(account), ::sql_escape(hash_recovery_code(code)))));
+    Ok(result.affected_rows() > 0)
+}
+
+fn has_session(req: &Request, account: &str) -> bool {
+    if let Some(token) = util::cookie(req, COOKIE_NAME) {
+        let mut sessions = SESSIONS.lock().unwrap();
+        let now = UTC::now();
+        sessions.retain(|_, &mut (_, expires)| expires > now);
+        sessions.get(&token).map_or(false, |&(ref session_account, _)| session_account == account)
+    } else {
+        false
+    }
+}
+
+/// The cookie is only marked `Secure` if the request came in over TLS, since browsers would not send it back over plain HTTP.
+fn start_session(req: &mut Request, response: &mut Response, account: &str) {
+    let token = util::random_token(32);
+    SESSIONS.lock().unwrap().insert(token.clone(), (account.to_owned(), UTC::now() + Duration::hours(SESSION_HOURS)));
+    let secure = if req.get::<IsTls>().unwrap_or(false) { "; Secure" } else { "" };
+    response.headers.set(headers::SetCookie(vec![format!("{}={}; Path=/; HttpOnly{}; SameSite=Strict", COOKIE_NAME, token, secure)]));
+}
+
+fn redirect(location: &str) -> Response {
+    Response::with((status::SeeOther, Header(headers::Location(location.to_owned()))))
+}
+
+/// Whether the account of the current request has passed the second factor, so that `IsAdmin` and the admin routes' middleware query the database only once per request.
+struct SecondFactorPassed;
+
+impl Key for SecondFactorPassed {
+    type Value = bool;
+}
+
+/// Before middleware for admin pages, to be run after the password has been checked.
+pub fn check_second_factor(req: &mut Request) -> IronResult<()> {
+    let (account, role) = try!(admin_account(req).ok_or(Error::auth("Zugriff nur für die Administration.")));
+    let passed = match req.extensions.get::<SecondFactorPassed>().cloned() {
+        Some(passed) => passed,
+        None => {
+            let passed = has_session(req, &account) || {
+                let mut conn = try!(::db_connection());
+                let (secret, _) = try!(secrets(&mut conn, &account).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+                secret.is_none() && !role.requires_two_factor()
+            };
+            req.extensions.insert::<SecondFactorPassed>(passed);
+            passed
+        }
+    };
+    if passed {
+        Ok(())
+    } else {
+        Err(IronError::new(Error::auth("Die Anmeldung mit dem zweiten Faktor steht noch aus."), (status::SeeOther, Header(headers::Location("/admin/2fa".to_owned())))))
+    }
+}
+
+fn login_page_inner(form_error: Option<&'static str>, req: &mut Request) -> IronResult<Response> {
+    let (account, role) = try!(admin_account(req).ok_or(Error::auth("")));
+    let mut conn = try!(::db_connection());
+    let (secret, _) = try!(secrets(&mut conn, &account).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    if secret.is_none() {
+        return Ok(if role.requires_two_factor() {
+            redirect("/admin/2fa/einrichten")
+        } else {
+            Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/admin/2fa", true, r#"<div class="alert alert-info">Für Ihr Konto ist keine Zwei-Faktor-Authentifizierung eingerichtet. <a href="/admin/2fa/einrichten">Jetzt einrichten</a></div>"#)))
+        });
+    }
+    Ok(Response::with((if form_error.is_some() { status::BadRequest } else { status::Ok }, "text/html".parse::<Mime>().unwrap(), util::page("/admin/2fa", false, &format!(
+        r#"
+        {error_message}
+        <h2>Zwei-Faktor-Authentifizierung</h2>
+        <form class="form-horizontal" action="/admin/2fa" method="post" enctype="application/x-www-form-urlencoded">
+            <div class="form-group">
+                <label for="code" class="col-sm-2 control-label">Code</label>
+                <div class="col-sm-10">
+                    <input type="text" class="form-control" name="code" id="code" autocomplete="off" autofocus placeholder="Code aus der Authenticator-App" />
+                    <p class="help-block">Falls Sie keinen Zugriff auf Ihre App haben, können Sie stattdessen einen Ihrer Wiederherstellungscodes eingeben.</p>
+                </div>
+            </div>
+            <div class="form-group">
+                <div class="col-sm-offset-2 col-sm-10">
+                    <button type="submit" class="btn btn-primary">Anmelden</button>
+                </div>
+            </div>
+        </form>
+        "#,
+        error_message=if let Some(msg) = form_error { format!(r#"<div class="alert alert-danger"><strong>{}</strong></div>"#, msg) } else { String::default() }
+    )))))
+}
+
+pub fn login_page(req: &mut Request) -> IronResult<Response> {
+    login_page_inner(None, req)
+}
+
+fn login_inner(req: &mut Request) -> Result<Response, &'static str> {
+    let code = {
+        let form_data = try!(req.get_ref::<UrlEncodedBody>().map_err(|_| "Fehlender Formularinhalt."));
+        try!(form_data.get("code").and_then(|values| values.first()).ok_or("Fehlender Code.")).trim().to_owned()
+    };
+    let (account, _) = try!(admin_account(req).ok_or("Benutzername oder Passwort falsch."));
+    let mut conn = try!(::db_connection().map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    let (secret, _) = try!(secrets(&mut conn, &account).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    let secret = try!(secret.ok_or("Für Ihr Konto ist keine Zwei-Faktor-Authentifizierung eingerichtet."));
+    if is_locked(&account) { return Err("Zu viele ungültige Codes. Bitte versuchen Sie es in 15 Minuten erneut."); }
+    let valid = match verify_totp(&secret, &code) {
+        Some(counter) => try!(use_counter(&mut conn, &account, counter).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank."))),
+        None => try!(use_recovery_code(&mut conn, &account, &code).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")))
+    };
+    if valid {
+        FAILED_ATTEMPTS.lock().unwrap().remove(&account);
+        let mut response = redirect("/");
+        start_session(req, &mut response, &account);
+        Ok(response)
+    } else {
+        record_failed_attempt(&account);
+        Err("Der Code ist ungültig oder wurde bereits verwendet.")
+    }
+}
+
+pub fn login(req: &mut Request) -> IronResult<Response> {
+    login_inner(req).or_else(|e| login_page_inner(Some(e), req))
+}
+
+fn enrol_page_inner(form_error: Option<&'static str>, req: &mut Request) -> IronResult<Response> {
+    let (account, role) = try!(admin_account(req).ok_or(Error::auth("")));
+    let mut conn = try!(::db_connection());
+    let (secret, pending_secret) = try!(secrets(&mut conn, &account).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    if secret.is_some() && !has_session(req, &account) { return Ok(redirect("/admin/2fa")); }
+    let pending_secret = match pending_secret {
+        Some(pending_secret) if form_error.is_some() => pending_secret,
+        _ => {
+            let pending_secret = new_secret();
+            try!(conn.query(format!("INSERT INTO two_factor (account, pending_secret) VALUES ({}, {}) {}", ::sql_escape(&account), ::sql_escape(&pending_secret), db::upsert_clause("account", &["pending_secret"]))).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+            pending_secret
+        }
+    };
+    let uri = format!("otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}", issuer=util::percent_encode(ISSUER), account=util::percent_encode(&account), secret=pending_secret);
+    let qr_code = try!(QrCode::new(uri.as_bytes()).map_err(|_| Error::internal("Fehler beim Erzeugen des QR-Codes.")));
+    Ok(Response::with((if form_error.is_some() { status::BadRequest } else { status::Ok }, "text/html".parse::<Mime>().unwrap(), util::page("/admin/2fa/einrichten", secret.is_some(), &format!(
+        r#"
+        {error_message}
+        <h2>Zwei-Faktor-Authentifizierung einrichten</h2>
+        <p>Scannen Sie den QR-Code mit einer Authenticator-App (z.B. FreeOTP oder Google Authenticator) und geben Sie zur Bestätigung den angezeigten Code ein.{replace_notice}</p>
+        <p>{qr_code}</p>
+        <p>Falls Sie den Code nicht scannen können, geben Sie diesen Schlüssel manuell ein: <code>{secret}</code></p>
+        <form class="form-horizontal" action="/admin/2fa/einrichten" method="post" enctype="application/x-www-form-urlencoded">
+            <div class="form-group">
+                <label for="code" class="col-sm-2 control-label">Code</label>
+                <div class="col-sm-10">
+                    <input type="text" class="form-control" name="code" id="code" autocomplete="off" placeholder="Code aus der Authenticator-App" />
+                </div>
+            </div>
+            <div class="form-group">
+                <div class="col-sm-offset-2 col-sm-10">
+                    <button type="submit" class="btn btn-primary">Bestätigen</button>
+                </div>
+            </div>
+        </form>
+        {disable_form}
+        "#,
+        error_message=if let Some(msg) = form_error { format!(r#"<div class="alert alert-danger"><strong>{}</strong> Bitte versuchen Sie es erneut.</div>"#, msg) } else { String::default() },
+        replace_notice=if secret.is_some() { " Ihr bisheriger zweiter Faktor und Ihre Wiederherstellungscodes werden dabei ersetzt." } else { "" },
+        qr_code=qr_code.render::<svg::Color>().min_dimensions(200, 200).build(),
+        secret=pending_secret,
+        disable_form=if secret.is_some() && !role.requires_two_factor() {
+            r#"<form action="/admin/2fa/deaktivieren" method="post"><button type="submit" class="btn btn-danger">Zwei-Faktor-Authentifizierung deaktivieren</button></form>"#
+        } else {
+            ""
+        }
+    )))))
+}
+
+pub fn enrol_page(req: &mut Request) -> IronResult<Response> {
+    enrol_page_inner(None, req)
+}
+
+fn enrol_inner(req: &mut Request) -> Result<Response, &'static str> {
+    let code = {
+        let form_data = try!(req.get_ref::<UrlEncodedBody>().map_err(|_| "Fehlender Formularinhalt."));
+        try!(form_data.get("code").and_then(|values| values.first()).ok_or("Fehlender Code.")).trim().to_owned()
+    };
+    let (account, _) = try!(admin_account(req).ok_or("Benutzername oder Passwort falsch."));
+    let mut conn = try!(::db_connection().map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    let (secret, pending_secret) = try!(secrets(&mut conn, &account).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    if secret.is_some() && !has_session(req, &account) { return Err("Bitte melden Sie sich zuerst mit Ihrem bisherigen zweiten Faktor an."); }
+    let pending_secret = try!(pending_secret.ok_or("Die Einrichtung wurde nicht gestartet."));
+    let counter = try!(verify_totp(&pending_secret, &code).ok_or("Der Code ist ungültig. Bitte prüfen Sie auch die Uhrzeit Ihres Geräts."));
+    let escaped_account = ::sql_escape(&account);
+    try!(conn.query(format!("UPDATE two_factor SET secret=pending_secret, pending_secret=NULL, last_counter={} WHERE account={}", counter, escaped_account)).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(conn.query(format!("DELETE FROM two_factor_recovery WHERE account={}", escaped_account)).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    let recovery_codes = (0..RECOVERY_CODES).map(|_| util::random_token(10).to_lowercase()).collect::<Vec<_>>();
+    for recovery_code in &recovery_codes {
+        try!(conn.query(format!("INSERT INTO two_factor_recovery (account, code_hash) VALUES ({}, {})", escaped_account, ::sql_escape(hash_recovery_code(recovery_code)))).map_err(|e| error::logged(e, "Fehler beim Zugriff auf die Datenbank.")));
+    }
+    let mut response = Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/admin/2fa/einrichten", true, &format!(
+        r#"
+        <div class="alert alert-success">Die Zwei-Faktor-Authentifizierung ist jetzt eingerichtet.</div>
+        <p>Bitte notieren Sie sich diese Wiederherstellungscodes und bewahren Sie sie sicher auf. Jeder Code kann einmal anstelle der App verwendet werden. Sie werden nur jetzt angezeigt.</p>
+        <ul>{}</ul>
+        <p><a href="/">Zurück zur Börse</a></p>
+        "#,
+        recovery_codes.iter().map(|recovery_code| format!("<li><code>{}</code></li>", recovery_code)).collect::<String>()
+    ))));
+    start_session(req, &mut response, &account);
+    Ok(response)
+}
+
+pub fn enrol(req: &mut Request) -> IronResult<Response> {
+    enrol_inner(req).or_else(|e| enrol_page_inner(Some(e), req))
+}
+
+pub fn disable(req: &mut Request) -> IronResult<Response> {
+    let (account, role) = try!(admin_account(req).ok_or(Error::auth("")));
+    if role.requires_two_factor() {
+        return Err(Error::forbidden("Für Ihr Konto ist die Zwei-Faktor-Authentifizierung vorgeschrieben.").into());
+    }
+    let mut conn = try!(::db_connection());
+    try!(conn.query(format!("DELETE FROM two_factor WHERE account={}", ::sql_escape(&account))).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    try!(conn.query(format!("DELETE FROM two_factor_recovery WHERE account={}", ::sql_escape(&account))).map_err(|e| Error::db(e, "Fehler beim Zugriff auf die Datenbank.")));
+    SESSIONS.lock().unwrap().retain(|_, &mut (ref session_account, _)| *session_account != account);
+    Ok(Response::with((status::Ok, "text/html".parse::<Mime>().unwrap(), util::page("/admin/2fa", true, r#"<div class="alert alert-success">Die Zwei-Faktor-Authentifizierung wurde deaktiviert.</div>"#))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The key of the test vectors in RFC 6238, base32 encoded.
+    const SECRET: &'static str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn totp_matches_rfc_6238() {
+        let secret = b"12345678901234567890";
+        assert_eq!(totp(secret, 59 / 30), 287082);
+        assert_eq!(totp(secret, 1111111109 / 30), 81804);
+        assert_eq!(totp(secret, 1234567890 / 30), 5924);
+    }
+
+    #[test]
+    fn verify_totp_allows_one_time_step_of_drift() {
+        let time = 1234567890;
+        let counter = time / TIME_STEP;
+        assert_eq!(verify_totp_at(SECRET, "005924", time), Some(counter));
+        assert_eq!(verify_totp_at(SECRET, "005924", time - TIME_STEP), Some(counter));
+        assert_eq!(verify_totp_at(SECRET, "005924", time + TIME_STEP), Some(counter));
+        assert_eq!(verify_totp_at(SECRET, "005924", time + 2 * TIME_STEP), None);
+        assert_eq!(verify_totp_at(SECRET, "590587", time), Some(counter + 1));
+    }
+
+    #[test]
+    fn verify_totp_rejects_malformed_input() {
+        let time = 1234567890;
+        assert_eq!(verify_totp_at(SECRET, "5924", time), None);
+        assert_eq!(verify_totp_at(SECRET, "0005924", time), None);
+        assert_eq!(verify_totp_at(SECRET, "00592a", time), None);
+        assert_eq!(verify_totp_at("not base32!", "005924", time), None);
+    }
+
+    #[test]
+    fn codes_cannot_be_used_twice() {
+        let mut conn = ::db::Conn::Sqlite(::rusqlite::Connection::open_in_memory().unwrap());
+        conn.query("CREATE TABLE two_factor (account TEXT NOT NULL PRIMARY KEY, secret TEXT NULL, pending_secret TEXT NULL, last_counter INTEGER NULL)").unwrap();
+        conn.query("INSERT INTO two_factor (account, secret) VALUES ('admin', 'secret')").unwrap();
+        assert!(use_counter(&mut conn, "admin", 41152263).unwrap());
+        assert!(!use_counter(&mut conn, "admin", 41152263).unwrap());
+        assert!(!use_counter(&mut conn, "admin", 41152262).unwrap());
+        assert!(use_counter(&mut conn, "admin", 41152264).unwrap());
+        assert!(!use_counter(&mut conn, "someone else", 41152265).unwrap());
+    }
+
+    #[test]
+    fn locks_after_too_many_wrong_codes() {
+        let account = "lockout test";
+        for _ in 1..MAX_FAILED_ATTEMPTS {
+            record_failed_attempt(account);
+        }
+        assert!(!is_locked(account));
+        record_failed_attempt(account);
+        assert!(is_locked(account));
+        assert!(!is_locked("another account"));
+    }
+}
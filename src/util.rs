@@ -1,18 +1,28 @@
-use std::{fmt, string};
-use std::error::Error;
-use std::fs::File;
-use std::io::prelude::*;
+use std::collections::BTreeMap;
 
 use chrono::prelude::*;
 
+use chrono_tz::Tz;
+use chrono_tz::Europe::Berlin;
+
 use iron::prelude::*;
-use iron::{headers, status};
+use iron::{Url, headers, status};
 use iron::typemap::{Key, TypeMap};
 
 use plugin;
 
+use rand::{self, Rng};
+
+use regex::Regex;
+
 use serde_json;
 
+use db;
+use error::Error;
+use i18n::{self, CurrentLocale, Locale};
+use logging;
+use two_factor;
+
 #[derive(Debug, Clone, Copy)]
 pub struct IsTls;
 
@@ -21,18 +31,20 @@ impl Key for IsTls {
 }
 
 impl<'a, 'b> plugin::Plugin<Request<'a, 'b>> for IsTls {
-    type Error = IsTlsError;
+    type Error = Error;
 
-    fn eval(req: &mut Request) -> Result<bool, IsTlsError> {
+    /// The header is set by the web server, so an invalid value is a problem with its configuration rather than with the request.
+    fn eval(req: &mut Request) -> Result<bool, Error> {
+        let err_msg = "Die Verschlüsselung der Verbindung konnte nicht festgestellt werden.";
         Ok(match req.headers.get_raw("X-Fenhl-TLS") {
             Some(header_bytes) => {
                 if header_bytes.len() != 1 {
-                    return Err(IsTlsError);
+                    return Err(Error::internal(err_msg));
                 }
-                match &*try!(String::from_utf8(header_bytes[0].clone())) {
+                match &*try!(String::from_utf8(header_bytes[0].clone()).map_err(|e| Error::other(e, err_msg))) {
                     "on" => true,
                     "" => false,
-                    _ => { return Err(IsTlsError); }
+                    _ => { return Err(Error::internal(err_msg)); }
                 }
             }
             None => false
@@ -40,7 +52,7 @@ impl<'a, 'b> plugin::Plugin<Request<'a, 'b>> for IsTls {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigMy {
     password: String
@@ -53,18 +65,119 @@ pub struct Config {
     password: String,
     admin_name: String,
     admin_pass: String,
-    mysql: ConfigMy
+    /// Additional accounts with admin rights, mapping user names to passwords.
+    #[serde(default)]
+    coordinators: BTreeMap<String, String>,
+    #[serde(default)]
+    two_factor: ConfigTwoFactor,
+    /// How long deleted entries and notices stay in the trash before they are purged.
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: i64,
+    /// JSON file in which the server's upgrade scripts announce maintenance windows.
+    #[serde(default = "default_reboot_file")]
+    pub reboot_file: String,
+    /// Blocks all submissions regardless of the maintenance schedule.
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub mail: ConfigMail,
+    /// Directory in which uploaded photos and their thumbnails are stored.
+    #[serde(default = "default_upload_dir")]
+    pub upload_dir: String,
+    /// Converts the print view to PDF.
+    #[serde(default = "default_wkhtmltopdf")]
+    pub wkhtmltopdf: String,
+    /// Which database backend to use, see `db`.
+    #[serde(default)]
+    pub database: db::Backend,
+    #[serde(default)]
+    pub db_pool: ConfigPool,
+    /// Only used with the `mysql` backend.
+    #[serde(default)]
+    mysql: ConfigMy,
+    /// The database file, only used with the `sqlite` backend.
+    #[serde(default = "default_sqlite_path")]
+    pub sqlite_path: String,
+    /// Whether requests and errors are logged as plain text or as JSON lines, see `logging`.
+    #[serde(default)]
+    pub log_format: logging::Format
 }
 
-#[derive(Deserialize)]
+fn default_trash_retention_days() -> i64 { 30 }
+
+fn default_reboot_file() -> String { "/opt/dev/reboot.json".to_owned() }
+
+fn default_upload_dir() -> String { "uploads".to_owned() }
+
+fn default_wkhtmltopdf() -> String { "/usr/bin/wkhtmltopdf".to_owned() }
+
+fn default_sqlite_path() -> String { "wiwboerse.sqlite".to_owned() }
+
+/// Which roles must use a second factor. Accounts whose role does not require one can still enrol voluntarily.
+#[derive(Deserialize, Default)]
 #[serde(deny_unknown_fields)]
-struct RebootConfig {
-    schedule: Option<DateTime<UTC>>,
+pub struct ConfigTwoFactor {
+    #[serde(default)]
+    admin: bool,
     #[serde(default)]
-    upgrade: bool
+    coordinator: bool
+}
+
+/// Limits of the database connection pool, see `db::connection`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigPool {
+    /// The maximum number of open connections. MySQL's own limit (`max_connections`) must be higher.
+    #[serde(default = "default_pool_max_size")]
+    pub max_size: usize,
+    /// How long a request waits for a connection when all are in use, before failing.
+    #[serde(default = "default_pool_acquire_timeout_secs")]
+    pub acquire_timeout_secs: u64
+}
+
+impl Default for ConfigPool {
+    fn default() -> ConfigPool {
+        ConfigPool {
+            max_size: default_pool_max_size(),
+            acquire_timeout_secs: default_pool_acquire_timeout_secs()
+        }
+    }
 }
 
+fn default_pool_max_size() -> usize { 10 }
+
+fn default_pool_acquire_timeout_secs() -> u64 { 10 }
+
+/// How digest and confirmation mails are sent.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigMail {
+    #[serde(default = "default_mail_from")]
+    pub from: String,
+    #[serde(default = "default_sendmail")]
+    pub sendmail: String
+}
+
+impl Default for ConfigMail {
+    fn default() -> ConfigMail {
+        ConfigMail {
+            from: default_mail_from(),
+            sendmail: default_sendmail()
+        }
+    }
+}
+
+fn default_mail_from() -> String { "boerse@willkommeninwoellstein.de".to_owned() }
+
+fn default_sendmail() -> String { "/usr/sbin/sendmail".to_owned() }
+
+/// Used for absolute links in feeds and mails.
+pub const BASE_URL: &'static str = "https://boerse.willkommeninwoellstein.de";
+
 lazy_static! {
+    static ref MARKDOWN_LINK: Regex = Regex::new(r"\[([^\]]+)\]\(((?:https?://|mailto:|/[^/])[^)\s]*)\)").unwrap();
+    static ref MARKDOWN_BOLD: Regex = Regex::new(r"\*\*([^*]+)\*\*").unwrap();
+    static ref MARKDOWN_ITALIC: Regex = Regex::new(r"\*([^*]+)\*").unwrap();
     pub static ref CONFIG: Config = serde_json::from_str(include_str!("../assets/config.json")).unwrap();
     pub static ref MY_OPTS: ::mysql::Opts = {
         let mut builder = ::mysql::OptsBuilder::new();
@@ -75,23 +188,59 @@ lazy_static! {
     };
 }
 
-pub fn check_admin_auth(req: &mut Request) -> IronResult<()> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Admin,
+    Coordinator
+}
+
+impl Role {
+    fn for_credentials(username: &str, password: &str) -> Option<Role> {
+        if username == CONFIG.admin_name && password == CONFIG.admin_pass {
+            Some(Role::Admin)
+        } else if CONFIG.coordinators.get(username).map_or(false, |pass| pass == password) {
+            Some(Role::Coordinator)
+        } else {
+            None
+        }
+    }
+
+    pub fn requires_two_factor(&self) -> bool {
+        match *self {
+            Role::Admin => CONFIG.two_factor.admin,
+            Role::Coordinator => CONFIG.two_factor.coordinator
+        }
+    }
+}
+
+/// Returns the name and role of the admin or coordinator whose password was sent with this request, ignoring the second factor.
+pub fn admin_account(req: &Request) -> Option<(String, Role)> {
+    match req.headers.get::<headers::Authorization<headers::Basic>>() {
+        Some(&headers::Authorization(headers::Basic { ref username, password: Some(ref password) })) => {
+            Role::for_credentials(username, password).map(|role| (username.clone(), role))
+        }
+        _ => None
+    }
+}
+
+/// Checks the admin or coordinator password only. Use `check_admin_auth` unless the handler is part of the second factor login itself.
+pub fn check_admin_password(req: &mut Request) -> IronResult<()> {
     match req.headers.get::<headers::Authorization<headers::Basic>>() {
         Some(&headers::Authorization(headers::Basic { ref username, password: Some(ref password) })) => {
-            if *username == CONFIG.admin_name && *password == CONFIG.admin_pass {
+            if Role::for_credentials(username, password).is_some() {
                 Ok(())
             } else {
-                Err(IronError::new(AuthError, (status::Unauthorized, "Zugriff nur für die Administration. Benutzername oder Passwort falsch.")))
+                Err(Error::auth("Zugriff nur für die Administration. Benutzername oder Passwort falsch.").into())
             }
         }
         Some(&headers::Authorization(headers::Basic { username: _, password: None })) => {
-            Err(IronError::new(AuthError, (status::Unauthorized, "Kein Passwort gefunden.")))
+            Err(Error::auth("Kein Passwort gefunden.").into())
         }
         None => {
             let mut hs = headers::Headers::new();
             hs.set_raw("WWW-Authenticate", vec![b"Basic realm=\"Anmeldung fuer die WiW-Boerse (nur fuer die Administration)\"".to_vec()]);
             Err(IronError {
-                error: Box::new(AuthError),
+                error: Box::new(Error::auth("")),
                 response: Response {
                     status: Some(status::Unauthorized),
                     headers: hs,
@@ -103,23 +252,51 @@ pub fn check_admin_auth(req: &mut Request) -> IronResult<()> {
     }
 }
 
+pub fn check_admin_auth(req: &mut Request) -> IronResult<()> {
+    try!(check_admin_password(req));
+    two_factor::check_second_factor(req)
+}
+
+/// The feeds, which can be accessed with an API token instead of the password.
+const FEED_PATHS: [&'static str; 3] = ["biete.atom", "suche.atom", "alle.atom"];
+
 pub fn check_auth(req: &mut Request) -> IronResult<()> {
+    if req.url.path().first() == Some(&"api") {
+        // the API authenticates clients with its own tokens, see `api::client`
+        return Ok(());
+    }
+    if req.url.path().len() == 1 && FEED_PATHS.contains(&req.url.path()[0]) && req.url.query().map_or(false, |query| query.split('&').any(|param| param.starts_with("token="))) {
+        // feed readers cannot log in, so feeds can be accessed with an API token instead, see `feed::check_token`
+        return Ok(());
+    }
+    {
+        let path = req.url.path();
+        if path.len() == 3 && path[0] == "abo" && (path[2] == "bestaetigen" || path[2] == "abbestellen") {
+            // links in digest mails must work without logging in, the subscription token serves as authentication
+            return Ok(());
+        }
+        if path.len() >= 2 && path[0] == "suchauftrag" && path[1] != "neu" {
+            // the same goes for the links in saved search mails
+            return Ok(());
+        }
+    }
+    let locale = req.get::<CurrentLocale>().unwrap_or_default();
     match req.headers.get::<headers::Authorization<headers::Basic>>() {
         Some(&headers::Authorization(headers::Basic { ref username, password: Some(ref password) })) => {
-            if (*username == CONFIG.username && *password == CONFIG.password) || (*username == CONFIG.admin_name && *password == CONFIG.admin_pass) {
+            if (*username == CONFIG.username && *password == CONFIG.password) || Role::for_credentials(username, password).is_some() {
                 Ok(())
             } else {
-                Err(IronError::new(AuthError, (status::Unauthorized, "Benutzername oder Passwort falsch.")))
+                Err(Error::auth(locale.t("error.wrong_credentials")).into())
             }
         }
         Some(&headers::Authorization(headers::Basic { username: _, password: None })) => {
-            Err(IronError::new(AuthError, (status::Unauthorized, "Kein Passwort gefunden.")))
+            Err(Error::auth(locale.t("error.missing_password")).into())
         }
         None => {
             let mut hs = headers::Headers::new();
             hs.set_raw("WWW-Authenticate", vec![b"Basic realm=\"Anmeldung fuer die WiW-Boerse\"".to_vec()]);
             Err(IronError {
-                error: Box::new(AuthError),
+                error: Box::new(Error::auth("")),
                 response: Response {
                     status: Some(status::Unauthorized),
                     headers: hs,
@@ -131,57 +308,172 @@ pub fn check_auth(req: &mut Request) -> IronResult<()> {
     }
 }
 
-pub fn reboot_time() -> Option<(DateTime<UTC>, bool)> {
-    if let Ok(mut f) = File::open("/opt/dev/reboot.json") {
-        let mut config_buf = String::default();
-        if f.read_to_string(&mut config_buf).is_err() {
-            return None;
-        }
-        if let Ok(conf) = serde_json::from_str::<RebootConfig>(&config_buf) {
-            conf.schedule.map(|sched| (sched, conf.upgrade))
-        } else {
-            None
-        }
-    } else {
-        None
+/// Returns the value of the cookie with the given name, if the request has one.
+pub fn cookie(req: &Request, name: &str) -> Option<String> {
+    req.headers.get::<headers::Cookie>().and_then(|&headers::Cookie(ref cookies)| cookies.iter().filter_map(|cookie| {
+        let mut split = cookie.splitn(2, '=');
+        if split.next().map(str::trim) == Some(name) { split.next().map(|value| value.trim().to_owned()) } else { None }
+    }).next())
+}
+
+/// Where to send the user back to after an action like switching the language: the path of the referring page if it is on this site, otherwise the start page.
+pub fn back_location(req: &Request) -> String {
+    match req.headers.get::<headers::Referer>() {
+        Some(&headers::Referer(ref referer)) => local_location(referer, &req.url),
+        None => "/".to_owned()
     }
 }
 
-macro_rules! errors {
-    ($($name:ident($msg:expr);)*) => {
-        $(
-            #[derive(Debug, Clone, Copy)]
-            pub struct $name;
+/// The path and query of `referer` if it is on the same host as `url`, otherwise the start page.
+fn local_location(referer: &str, url: &Url) -> String {
+    match Url::parse(referer) {
+        Ok(ref referer) if referer.host() == url.host() && referer.port() == url.port() => {
+            let location = format!("/{}{}", referer.path().join("/"), referer.query().map_or(String::default(), |query| format!("?{}", query)));
+            // a path starting with two slashes would be taken as another host
+            if location.starts_with("//") { "/".to_owned() } else { location }
+        }
+        _ => "/".to_owned()
+    }
+}
 
-            impl fmt::Display for $name {
-                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                    fmt::Display::fmt($msg, f)
-                }
-            }
+/// Generates a random alphanumeric string, suitable for session and confirmation tokens.
+pub fn random_token(len: usize) -> String {
+    rand::thread_rng().gen_ascii_chars().take(len).collect()
+}
 
-            impl Error for $name {
-                fn description(&self) -> &str {
-                    $msg
-                }
-            }
+pub fn escape_html<S: AsRef<str>>(s: S) -> String {
+    s.as_ref().replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;").replace("'", "&#39;")
+}
 
-            impl From<$name> for IronError {
-                fn from(err: $name) -> IronError {
-                    IronError::new(err, (status::BadRequest, $msg))
-                }
-            }
-        )*
+/// Renders a small subset of Markdown: `**bold**`, `*italic*` and `[links](https://…)` to web, mail or local addresses. All other HTML is escaped.
+pub fn render_markdown<S: AsRef<str>>(s: S) -> String {
+    let escaped = escape_html(s);
+    // The inline rules only apply to text, never to the addresses of links.
+    let mut rendered = String::default();
+    let mut last = 0;
+    for link in MARKDOWN_LINK.captures_iter(&escaped) {
+        let whole = link.get(0).unwrap();
+        rendered.push_str(&render_markdown_inline(&escaped[last..whole.start()]));
+        rendered.push_str(&format!(r#"<a href="{}">{}</a>"#, &link[2], render_markdown_inline(&link[1])));
+        last = whole.end();
     }
+    rendered.push_str(&render_markdown_inline(&escaped[last..]));
+    rendered.replace("\n", "<br />")
+}
+
+fn render_markdown_inline(escaped: &str) -> String {
+    let bold = MARKDOWN_BOLD.replace_all(escaped, "<strong>$1</strong>");
+    MARKDOWN_ITALIC.replace_all(&bold, "<em>$1</em>").into_owned()
+}
+
+pub fn percent_encode<S: AsRef<str>>(s: S) -> String {
+    s.as_ref().bytes().map(|b| match b {
+        b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b)
+    }).collect()
+}
+
+/// Formats a timestamp for use in an SQL statement. Timestamps are stored in UTC.
+pub fn sql_timestamp(time: DateTime<UTC>) -> String {
+    ::sql_escape(time.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// Converts a timestamp read from the database to German time.
+pub fn berlin_time(time: NaiveDateTime) -> DateTime<Tz> {
+    UTC.from_utc_datetime(&time).with_timezone(&Berlin)
+}
+
+/// Parses a date and time entered in a form as German time. Accepts the format sent by `datetime-local` inputs as well as `TT.MM.JJJJ HH:MM` for browsers without support for them.
+pub fn parse_berlin_datetime(s: &str) -> Option<DateTime<UTC>> {
+    ["%Y-%m-%dT%H:%M", "%d.%m.%Y %H:%M"].iter()
+        .filter_map(|format| NaiveDateTime::parse_from_str(s.trim(), format).ok())
+        .filter_map(|naive| Berlin.from_local_datetime(&naive).single())
+        .map(|time| time.with_timezone(&UTC))
+        .next()
+}
+
+/// Formats a timestamp as the value of a `datetime-local` input.
+pub fn datetime_input_value(time: DateTime<UTC>) -> String {
+    time.with_timezone(&Berlin).format("%Y-%m-%dT%H:%M").to_string()
+}
+
+/// Wraps the given HTML in the site layout.
+pub fn page(nav_path: &str, is_admin: bool, content: &str) -> String {
+    page_inner(nav_path, is_admin, None, content)
+}
+
+/// Like `page`, but in the language and text direction of `locale` and with the language switcher. Used for the public pages.
+pub fn localized_page(nav_path: &str, is_admin: bool, locale: Locale, content: &str) -> String {
+    page_inner(nav_path, is_admin, Some(locale), content)
 }
 
-errors! {
-    AuthError("authentication error");
-    DbError("database error");
-    InternalError("internal server error");
-    IsTlsError("failed to determine encryption status");
-    Nyi("not yet implemented");
+fn page_inner(nav_path: &str, is_admin: bool, locale: Option<Locale>, content: &str) -> String {
+    format!(
+        r#"
+<!DOCTYPE html>
+<html {html_attributes}>
+<head>
+    {header}
+    {locale_head}
+</head>
+<body>
+    {nav}
+    <div class="container" style="position: relative; top: 71px;">
+        {switcher}
+        {content}
+    </div>
+    {footer}
+</body>
+</html>
+        "#,
+        html_attributes=locale.unwrap_or_default().html_attributes(),
+        header=include_str!("../assets/header.html"),
+        locale_head=locale.map_or(String::default(), |locale| locale.head()),
+        nav=::wiw::nav("boerse", nav_path, is_admin),
+        switcher=locale.map_or(String::default(), i18n::format_switcher),
+        content=content,
+        footer=include_str!("../assets/footer.html")
+    )
 }
 
-impl From<string::FromUtf8Error> for IsTlsError {
-    fn from(_: string::FromUtf8Error) -> IsTlsError { IsTlsError }
+#[cfg(test)]
+mod tests {
+    use iron::Url;
+
+    use super::{local_location, render_markdown};
+
+    #[test]
+    fn local_location_keeps_pages_on_this_site() {
+        let url = Url::parse("http://boerse.example.org/sprache").unwrap();
+        assert_eq!(local_location("http://boerse.example.org/biete/3?sortierung=neu", &url), "/biete/3?sortierung=neu");
+        assert_eq!(local_location("http://boerse.example.org/", &url), "/");
+    }
+
+    #[test]
+    fn local_location_rejects_other_sites() {
+        let url = Url::parse("http://boerse.example.org/sprache").unwrap();
+        assert_eq!(local_location("http://evil.example.com/biete", &url), "/");
+        assert_eq!(local_location("http://boerse.example.org:8080/biete", &url), "/");
+        assert_eq!(local_location("http://boerse.example.org//evil.example.com/biete", &url), "/");
+        assert_eq!(local_location("kein Link", &url), "/");
+    }
+
+    #[test]
+    fn render_markdown_formats_text() {
+        assert_eq!(render_markdown("**fett** und *kursiv*\nneue Zeile"), "<strong>fett</strong> und <em>kursiv</em><br />neue Zeile");
+        assert_eq!(render_markdown("<script>alert(1)</script>"), "&lt;script&gt;alert(1)&lt;/script&gt;");
+    }
+
+    #[test]
+    fn render_markdown_only_formats_text_outside_of_addresses() {
+        assert_eq!(render_markdown("[Liste](https://example.org/a*b*c)"), r#"<a href="https://example.org/a*b*c">Liste</a>"#);
+        assert_eq!(render_markdown("*Siehe* [*hier*](/biete)"), r#"<em>Siehe</em> <a href="/biete"><em>hier</em></a>"#);
+        assert_eq!(render_markdown("[Mail](mailto:info@example.org)"), r#"<a href="mailto:info@example.org">Mail</a>"#);
+    }
+
+    #[test]
+    fn render_markdown_rejects_other_addresses() {
+        assert_eq!(render_markdown("[x](javascript:alert(1))"), "[x](javascript:alert(1))");
+        assert_eq!(render_markdown("[x](//evil.example.com)"), "[x](//evil.example.com)");
+    }
 }